@@ -3,6 +3,7 @@ use std::sync::Arc;
 use async_trait::async_trait;
 
 use crate::error::WalleResult;
+use crate::lifecycle::Lifecycle;
 use crate::util::SelfId;
 use crate::util::SelfIds;
 use crate::EventHandler;
@@ -30,11 +31,17 @@ pub trait ActionHandler<E, A, R>: GetStatus + SelfIds + Sync {
     async fn after_call_event(&self) -> WalleResult<()> {
         Ok(())
     }
+    /// 连接或 Bot 生命周期变化时调用
+    async fn on_lifecycle(&self, _lifecycle: &Lifecycle) {}
     async fn shutdown(&self) {}
 }
 
 pub trait GetStatus {
     fn get_status(&self) -> crate::structs::Status;
+    /// 单个 Bot 的状态，默认与 get_status 相同
+    fn get_bot_status(&self, _self_id: &str) -> crate::structs::Status {
+        self.get_status()
+    }
 }
 
 pub struct JoinedHandler<H0, H1>(pub H0, pub H1);
@@ -71,6 +78,9 @@ where
     fn get_status(&self) -> crate::structs::Status {
         self.0.get_status()
     }
+    fn get_bot_status(&self, self_id: &str) -> crate::structs::Status {
+        self.0.get_bot_status(self_id)
+    }
 }
 
 #[async_trait]
@@ -106,6 +116,10 @@ where
             Ok(crate::resp::resp_error::bad_request("bot not exist").into())
         }
     }
+    async fn on_lifecycle(&self, lifecycle: &Lifecycle) {
+        self.0.on_lifecycle(lifecycle).await;
+        self.1.on_lifecycle(lifecycle).await;
+    }
     async fn shutdown(&self) {
         self.0.shutdown().await;
         self.1.shutdown().await;
@@ -0,0 +1,466 @@
+//! walle 命令行客户端，基于 AppOBC 连接 OneBot 实现端
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use clap::{Parser, Subcommand};
+use colored::*;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::task::JoinHandle;
+use walle_core::{
+    action::Action,
+    alt::ColoredAlt,
+    config::{
+        AppConfig, ConfigLoader, HttpClient, HttpServer, ImplConfig, RelayConfig, Validate,
+        WebSocketClient, WebSocketServer,
+    },
+    error::WalleResult,
+    event::Event,
+    filter::EventFilter,
+    lifecycle::Lifecycle,
+    obc::{AppOBC, ConsoleOneBot, Relay},
+    resp::Resp,
+    segment::{IntoMessage, MessageSegment},
+    util::{shutdown_signal, Value, ValueMap},
+    ActionHandler, EventHandler, OneBot,
+};
+
+type CliOneBot = OneBot<AppOBC<Action, Resp>, CliHandler>;
+
+#[derive(Parser)]
+#[command(name = "walle", version, about = "OneBot 12 命令行客户端")]
+struct Cli {
+    /// 正向 WebSocket 连接地址，可重复
+    #[arg(long = "ws", value_name = "URL")]
+    websocket: Vec<String>,
+    /// 反向 WebSocket 监听地址，可重复
+    #[arg(long = "wsr", value_name = "ADDR")]
+    websocket_rev: Vec<SocketAddr>,
+    /// HTTP Webhook 监听地址，可重复
+    #[arg(long, value_name = "ADDR")]
+    webhook: Vec<SocketAddr>,
+    /// HTTP 连接，格式为 SELF_ID=URL，可重复
+    #[arg(long, value_name = "SELF_ID=URL")]
+    http: Vec<String>,
+    /// 所有连接使用的 access_token
+    #[arg(long)]
+    token: Option<String>,
+    /// 事件过滤表达式，如 `detail_type == group && group_id == 123`
+    #[arg(long)]
+    filter: Option<EventFilter>,
+    /// 发送 Action 的目标 Bot，默认为首个上线的 Bot
+    #[arg(long)]
+    self_id: Option<String>,
+    /// 等待 Bot 上线的秒数
+    #[arg(long, default_value_t = 5)]
+    wait: u64,
+    /// 以 json 打印收到的原始数据
+    #[arg(long)]
+    raw: bool,
+    /// 输出调试日志
+    #[arg(short, long)]
+    verbose: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// 打印收到的事件
+    Listen,
+    /// 发送一个 Action，参数格式为 KEY=VALUE，json 值使用 KEY:=VALUE
+    Call { action: String, params: Vec<String> },
+    /// 交互式发送 Action，同时打印收到的事件
+    Repl,
+    /// 运行协议中继，未指定配置文件时使用默认设置
+    Relay {
+        /// toml、json 或 yaml 格式的中继配置文件，可由 WALLE_* 环境变量覆盖
+        config: Option<PathBuf>,
+    },
+    /// 运行控制台实现端，在 stdin 输入消息，Bot id 由 --self-id 指定
+    Console {
+        /// toml、json 或 yaml 格式的实现端配置文件，修改后自动重启，
+        /// 默认反向 WebSocket 连接至 ws://127.0.0.1:8844
+        config: Option<PathBuf>,
+    },
+}
+
+impl Cli {
+    fn app_config(&self) -> Result<AppConfig, String> {
+        let mut config = AppConfig {
+            websocket_rev: vec![],
+            ..Default::default()
+        };
+        for url in &self.websocket {
+            config.websocket.push(WebSocketClient {
+                url: url.clone(),
+                access_token: self.token.clone(),
+                filter: self.filter.clone(),
+                ..Default::default()
+            });
+        }
+        for addr in &self.websocket_rev {
+            config.websocket_rev.push(WebSocketServer {
+                host: addr.ip(),
+                port: addr.port(),
+                access_token: self.token.clone(),
+                filter: self.filter.clone(),
+                ..Default::default()
+            });
+        }
+        for addr in &self.webhook {
+            config.http_webhook.push(HttpServer {
+                host: addr.ip(),
+                port: addr.port(),
+                access_token: self.token.clone(),
+                filter: self.filter.clone(),
+                ..Default::default()
+            });
+        }
+        for http in &self.http {
+            let (self_id, url) = http
+                .split_once('=')
+                .ok_or_else(|| format!("invalid http connection: {}", http))?;
+            config.http.insert(
+                self_id.to_string(),
+                HttpClient {
+                    url: url.to_string(),
+                    access_token: self.token.clone(),
+                    ..Default::default()
+                },
+            );
+        }
+        if config.websocket.is_empty()
+            && config.websocket_rev.is_empty()
+            && config.http_webhook.is_empty()
+            && config.http.is_empty()
+        {
+            config.websocket_rev.push(WebSocketServer {
+                access_token: self.token.clone(),
+                filter: self.filter.clone(),
+                ..Default::default()
+            });
+        }
+        Ok(config)
+    }
+}
+
+/// 打印事件与连接状态
+struct CliHandler {
+    quiet: bool,
+    raw: bool,
+}
+
+#[async_trait]
+impl EventHandler<Event, Action, Resp> for CliHandler {
+    type Config = ();
+    async fn start<AH, EH>(
+        &self,
+        _: &Arc<OneBot<AH, EH>>,
+        _: (),
+    ) -> WalleResult<Vec<JoinHandle<()>>>
+    where
+        AH: ActionHandler<Event, Action, Resp> + Send + Sync + 'static,
+        EH: EventHandler<Event, Action, Resp> + Send + Sync + 'static,
+    {
+        Ok(vec![])
+    }
+    async fn call(&self, event: Event) -> WalleResult<()> {
+        if self.quiet {
+        } else if self.raw {
+            println!("{}", serde_json::to_string(&event).unwrap_or_default());
+        } else {
+            println!("{} {}", event.self_id.bright_green(), event.colored_alt());
+        }
+        Ok(())
+    }
+    async fn on_lifecycle(&self, lifecycle: &Lifecycle) {
+        if self.quiet {
+            return;
+        }
+        match lifecycle {
+            Lifecycle::BotOnline {
+                self_id, transport, ..
+            } => eprintln!("{} {} via {}", "online".green(), self_id, transport),
+            Lifecycle::BotOffline { self_id, .. } => {
+                eprintln!("{} {}", "offline".red(), self_id)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// 解析 KEY=VALUE 参数，VALUE 视为字符串；KEY:=VALUE 时 VALUE 按 json 解析
+///
+/// 字符串类型的 message 参数会转换为纯文本消息
+fn parse_params(params: &[String]) -> Result<ValueMap, String> {
+    let mut map = ValueMap::default();
+    for param in params {
+        let (key, value) = param
+            .split_once('=')
+            .ok_or_else(|| format!("invalid param: {}", param))?;
+        let (key, value) = match key.strip_suffix(':') {
+            Some(key) => (
+                key,
+                serde_json::from_str::<Value>(value)
+                    .map_err(|e| format!("invalid json param {}: {}", key, e))?,
+            ),
+            None => (key, Value::Str(value.to_string())),
+        };
+        let value = match value {
+            Value::Str(s) if key == "message" => text_message(s),
+            v => v,
+        };
+        map.insert(key.to_string(), value);
+    }
+    Ok(map)
+}
+
+fn text_message(text: String) -> Value {
+    let segments: Vec<MessageSegment> = text.into_message();
+    segments.into()
+}
+
+/// 按空白分割一行输入，支持单双引号
+fn split_line(line: &str) -> Result<Vec<String>, String> {
+    let mut args = vec![];
+    let mut cur = String::new();
+    let mut quote = None;
+    let mut in_arg = false;
+    for c in line.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => cur.push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                in_arg = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut cur));
+                    in_arg = false;
+                }
+            }
+            (None, c) => {
+                cur.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if quote.is_some() {
+        return Err("unterminated quote".to_string());
+    }
+    if in_arg {
+        args.push(cur);
+    }
+    Ok(args)
+}
+
+/// 确定目标 Bot，未指定时等待首个 Bot 上线
+async fn resolve_self_id(ob: &CliOneBot, self_id: &Option<String>, wait: u64) -> Option<String> {
+    if self_id.is_some() {
+        return self_id.clone();
+    }
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(wait);
+    loop {
+        if let Some(bot) = ob.action_handler.bots.iter().next() {
+            return Some(bot.key().clone());
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return None;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+async fn call(
+    ob: &Arc<CliOneBot>,
+    self_id: &str,
+    action: String,
+    params: &[String],
+    raw: bool,
+) -> Result<bool, String> {
+    let mut params = parse_params(params)?;
+    params
+        .entry("self_id".to_string())
+        .or_insert_with(|| self_id.into());
+    let resp = ob
+        .handle_action(Action { action, params })
+        .await
+        .map_err(|e| e.to_string())?;
+    let ok = resp.retcode == 0;
+    if raw {
+        println!("{}", serde_json::to_string(&resp).unwrap_or_default());
+    } else if ok {
+        println!("{} {}", "ok".green(), resp.data.colored_alt());
+    } else {
+        println!(
+            "{} {} {}",
+            "failed".red(),
+            resp.retcode.to_string().red(),
+            resp.message
+        );
+    }
+    Ok(ok)
+}
+
+async fn repl(ob: &Arc<CliOneBot>, cli: &Cli) {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let args = match split_line(&line) {
+            Ok(args) if args.is_empty() => continue,
+            Ok(args) => args,
+            Err(e) => {
+                eprintln!("{}", e.red());
+                continue;
+            }
+        };
+        if matches!(args[0].as_str(), "exit" | "quit") {
+            break;
+        }
+        let self_id = match resolve_self_id(ob, &cli.self_id, 0).await {
+            Some(self_id) => self_id,
+            None => {
+                eprintln!("{}", "no bot online".red());
+                continue;
+            }
+        };
+        if let Err(e) = call(ob, &self_id, args[0].clone(), &args[1..], cli.raw).await {
+            eprintln!("{}", e.red());
+        }
+    }
+}
+
+/// 读取配置文件与 WALLE_* 环境变量，未指定文件时使用默认设置
+fn load_config<T>(path: &Option<PathBuf>) -> Result<(ConfigLoader, T), String>
+where
+    T: serde::de::DeserializeOwned + serde::Serialize + Default + Validate,
+{
+    let loader = ConfigLoader {
+        path: path.clone(),
+        ..Default::default()
+    };
+    let config = loader.load().map_err(|e| e.to_string())?;
+    Ok((loader, config))
+}
+
+/// 运行中继直至收到 SIGINT 或 SIGTERM
+async fn relay(path: &Option<PathBuf>) -> Result<(), String> {
+    let (_, config): (_, RelayConfig) = load_config(path)?;
+    let relay = Relay::new("relay");
+    relay.start(config).await.map_err(|e| e.to_string())?;
+    shutdown_signal().await;
+    relay.shutdown().await.map_err(|e| e.to_string())
+}
+
+/// 运行控制台实现端直至收到 SIGINT 或 SIGTERM
+async fn console(self_id: &str, path: &Option<PathBuf>) -> Result<(), String> {
+    let (loader, config): (_, ImplConfig) = load_config(path)?;
+    let ob = Arc::new(ConsoleOneBot::console(self_id, "console"));
+    ob.start((), config.clone(), true)
+        .await
+        .map_err(|e| e.to_string())?;
+    if path.is_some() {
+        loader.watch(&ob, config, Duration::from_secs(1), true, |c| ((), c));
+    }
+    eprintln!("{}", "/help for commands".bright_black());
+    ob.run_until_shutdown().await.map_err(|e| e.to_string())
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_max_level(if cli.verbose {
+            tracing::Level::DEBUG
+        } else {
+            tracing::Level::WARN
+        })
+        .init();
+    let served = match &cli.command {
+        Command::Relay { config } => Some(relay(config).await),
+        Command::Console { config } => {
+            let self_id = cli.self_id.as_deref().unwrap_or("console");
+            Some(console(self_id, config).await)
+        }
+        _ => None,
+    };
+    if let Some(result) = served {
+        if let Err(e) = result {
+            eprintln!("{}", e.red());
+            std::process::exit(1);
+        }
+        // a pending stdin read would keep the runtime from shutting down
+        std::process::exit(0);
+    }
+    let config = match cli.app_config() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e.red());
+            std::process::exit(2);
+        }
+    };
+    let handler = CliHandler {
+        quiet: matches!(cli.command, Command::Call { .. }),
+        raw: cli.raw,
+    };
+    let ob = Arc::new(OneBot::new(AppOBC::new(), handler));
+    if let Err(e) = ob.start(config, (), true).await {
+        eprintln!("{}", e.to_string().red());
+        std::process::exit(1);
+    }
+    let code = match &cli.command {
+        Command::Listen => {
+            shutdown_signal().await;
+            0
+        }
+        Command::Call { action, params } => {
+            match resolve_self_id(&ob, &cli.self_id, cli.wait).await {
+                Some(self_id) => match call(&ob, &self_id, action.clone(), params, cli.raw).await {
+                    Ok(true) => 0,
+                    Ok(false) => 1,
+                    Err(e) => {
+                        eprintln!("{}", e.red());
+                        1
+                    }
+                },
+                None => {
+                    eprintln!("{}", "no bot online".red());
+                    1
+                }
+            }
+        }
+        Command::Repl => {
+            repl(&ob, &cli).await;
+            0
+        }
+        Command::Relay { .. } | Command::Console { .. } => unreachable!(),
+    };
+    ob.shutdown().await.ok();
+    std::process::exit(code);
+}
+
+#[test]
+fn params_test() {
+    let params = parse_params(&[
+        "detail_type=private".to_string(),
+        "user_id=1".to_string(),
+        "flag:=true".to_string(),
+        "limit:=10".to_string(),
+        "message=hi".to_string(),
+    ])
+    .unwrap();
+    assert_eq!(params["user_id"], Value::Str("1".to_string()));
+    assert_eq!(params["flag"], Value::Bool(true));
+    assert_eq!(params["limit"], Value::Int(10));
+    assert_eq!(params["detail_type"], Value::Str("private".to_string()));
+    assert!(params["message"].is_list());
+    assert!(parse_params(&["flag:=yes".to_string()]).is_err());
+    assert_eq!(
+        split_line(r#"send_message message="hello world" user_id='1'"#).unwrap(),
+        vec!["send_message", "message=hello world", "user_id=1"]
+    );
+}
@@ -0,0 +1,389 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use super::Validate;
+use crate::{
+    error::{WalleError, WalleResult},
+    ActionHandler, EventHandler, OneBot, WALLE_CORE,
+};
+
+/// 配置文件格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// 按扩展名判断格式
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "toml" => Some(Self::Toml),
+            "json" => Some(Self::Json),
+            "yaml" | "yml" => Some(Self::Yaml),
+            _ => None,
+        }
+    }
+
+    fn parse(self, s: &str) -> Result<Value, String> {
+        match self {
+            Self::Toml => toml::from_str(s).map_err(|e| e.to_string()),
+            Self::Json => serde_json::from_str(s).map_err(|e| e.to_string()),
+            Self::Yaml => serde_yaml::from_str(s).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// 配置加载器
+///
+/// 依次叠加默认设置、配置文件与环境变量，最后进行校验，未写出的项使用默认设置。
+///
+/// 环境变量名为 `{env_prefix}_` 加以 `__` 分隔的字段路径，数组以下标表示，如
+/// `WALLE_HEARTBEAT__INTERVAL=10`、`WALLE_WEBSOCKET_REV__0__URL=ws://127.0.0.1:8844`；
+/// 值按 json 解析，不符合字段类型时视为字符串
+#[derive(Debug, Clone)]
+pub struct ConfigLoader {
+    /// 未设置时仅使用默认设置与环境变量
+    pub path: Option<PathBuf>,
+    /// 未设置时按扩展名判断
+    pub format: Option<ConfigFormat>,
+    /// None 为不读取环境变量
+    pub env_prefix: Option<String>,
+}
+
+impl Default for ConfigLoader {
+    fn default() -> Self {
+        Self {
+            path: None,
+            format: None,
+            env_prefix: Some("WALLE".to_string()),
+        }
+    }
+}
+
+fn config_error<S: std::fmt::Display, E: std::fmt::Display>(source: S, e: E) -> WalleError {
+    WalleError::Config(vec![format!("{}: {}", source, e)])
+}
+
+impl ConfigLoader {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: Some(path.into()),
+            ..Default::default()
+        }
+    }
+
+    pub fn load<T>(&self) -> WalleResult<T>
+    where
+        T: DeserializeOwned + Serialize + Default + Validate,
+    {
+        let mut value =
+            serde_json::to_value(T::default()).map_err(|e| config_error("default", e))?;
+        if let Some(path) = &self.path {
+            let format = self
+                .format
+                .or_else(|| ConfigFormat::from_path(path))
+                .ok_or_else(|| {
+                    config_error(
+                        path.display(),
+                        "unknown format, expect .toml, .json, .yaml or .yml",
+                    )
+                })?;
+            let s = std::fs::read_to_string(path).map_err(|e| config_error(path.display(), e))?;
+            let file = format
+                .parse(&s)
+                .map_err(|e| config_error(path.display(), e))?;
+            merge(&mut value, file);
+        }
+        if let Some(prefix) = &self.env_prefix {
+            let mut vars: Vec<_> = std::env::vars()
+                .filter(|(k, _)| k.starts_with(&format!("{}_", prefix)))
+                .collect();
+            vars.sort();
+            for (k, v) in vars {
+                let path: Vec<String> = k[prefix.len() + 1..]
+                    .split("__")
+                    .map(str::to_ascii_lowercase)
+                    .collect();
+                apply_env::<T>(&mut value, &path, v).map_err(|e| config_error(&k, e))?;
+            }
+        }
+        let config: T = serde_path_to_error::deserialize(value).map_err(|e| {
+            let source = self
+                .path
+                .as_ref()
+                .map(|p| p.display().to_string())
+                .unwrap_or_else(|| "config".to_string());
+            match e.path().to_string().as_str() {
+                "." => config_error(source, e.inner()),
+                path => config_error(format!("{} {}", source, path), e.inner()),
+            }
+        })?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        std::fs::metadata(self.path.as_ref()?).ok()?.modified().ok()
+    }
+
+    /// 每隔 interval 检查配置文件，内容变化且校验通过后以新配置重启 OneBot
+    ///
+    /// 重启会关闭整个 OneBot，所有连接都将断开并按新配置重新建立；
+    /// 仅需增删端点时可使用 `add_endpoint` / `remove_endpoint` 避免中断其余连接。
+    ///
+    /// `split` 将配置拆分为 `(ah_config, eh_config)`，新配置启动失败时以原配置重新启动；
+    /// OneBot 被关闭后停止监视。ActionHandler 与 EventHandler 需支持关闭后再次启动
+    pub fn watch<T, E, A, R, AH, EH, F>(
+        self,
+        ob: &Arc<OneBot<AH, EH>>,
+        current: T,
+        interval: Duration,
+        ah_first: bool,
+        split: F,
+    ) -> JoinHandle<()>
+    where
+        T: DeserializeOwned + Serialize + Default + Validate + Clone + Send + 'static,
+        E: Send + Sync + 'static,
+        A: Send + Sync + 'static,
+        R: Send + Sync + 'static,
+        AH: ActionHandler<E, A, R> + Send + Sync + 'static,
+        EH: EventHandler<E, A, R> + Send + Sync + 'static,
+        AH::Config: Send,
+        EH::Config: Send,
+        F: Fn(T) -> (AH::Config, EH::Config) + Send + Sync + 'static,
+    {
+        let ob = ob.clone();
+        // taken before spawning so changes made in the meantime are not missed
+        let mut modified = self.modified();
+        tokio::spawn(async move {
+            let mut current = current;
+            loop {
+                tokio::time::sleep(interval).await;
+                if !ob.started() {
+                    break;
+                }
+                let m = self.modified();
+                if m == modified {
+                    continue;
+                }
+                modified = m;
+                let config: T = match self.load() {
+                    Ok(config) => config,
+                    Err(e) => {
+                        warn!(target: WALLE_CORE, "config not reloaded: {}", e);
+                        continue;
+                    }
+                };
+                if serde_json::to_value(&config).ok() == serde_json::to_value(&current).ok() {
+                    continue;
+                }
+                info!(target: WALLE_CORE, "config changed, restarting");
+                ob.shutdown::<E, A, R>().await.ok();
+                let (ah_config, eh_config) = split(config.clone());
+                match ob.start(ah_config, eh_config, ah_first).await {
+                    Ok(_) => {
+                        current = config;
+                        continue;
+                    }
+                    Err(e) => warn!(target: WALLE_CORE, "start with new config failed: {}", e),
+                }
+                ob.shutdown::<E, A, R>().await.ok();
+                let (ah_config, eh_config) = split(current.clone());
+                if let Err(e) = ob.start(ah_config, eh_config, ah_first).await {
+                    error!(target: WALLE_CORE, "restart with previous config failed: {}", e);
+                    break;
+                }
+            }
+        })
+    }
+}
+
+/// 对象逐项合并，其余类型直接覆盖
+fn merge(base: &mut Value, other: Value) {
+    match (base, other) {
+        (Value::Object(base), Value::Object(other)) => {
+            for (k, v) in other {
+                merge(base.entry(k).or_insert(Value::Null), v);
+            }
+        }
+        (base, other) => *base = other,
+    }
+}
+
+fn set(value: &mut Value, path: &[String], new: Value) -> Result<(), String> {
+    let mut value = value;
+    for key in path {
+        if value.is_null() {
+            *value = Value::Object(Default::default());
+        }
+        value = match value {
+            Value::Object(map) => map.entry(key.as_str()).or_insert(Value::Null),
+            Value::Array(array) => match key.parse::<usize>() {
+                Ok(i) if i < array.len() => &mut array[i],
+                Ok(i) if i == array.len() => {
+                    array.push(Value::Null);
+                    &mut array[i]
+                }
+                _ => return Err(format!("invalid index `{}`", key)),
+            },
+            _ => return Err(format!("`{}` is not a table", key)),
+        };
+    }
+    *value = new;
+    Ok(())
+}
+
+fn apply_env<T: DeserializeOwned>(
+    value: &mut Value,
+    path: &[String],
+    raw: String,
+) -> Result<(), String> {
+    let parsed = serde_json::from_str(&raw).unwrap_or_else(|_| Value::String(raw.clone()));
+    let is_str = parsed.is_string();
+    set(value, path, parsed)?;
+    if !is_str && serde_json::from_value::<T>(value.clone()).is_err() {
+        let mut fallback = value.clone();
+        set(&mut fallback, path, Value::String(raw))?;
+        if serde_json::from_value::<T>(fallback.clone()).is_ok() {
+            *value = fallback;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn loader_test() {
+    use super::*;
+
+    let dir = std::env::temp_dir().join(format!("walle-loader-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let toml = dir.join("impl.toml");
+    std::fs::write(
+        &toml,
+        "websocket_rev = []\n[[websocket]]\nhost = \"0.0.0.0\"\nport = 9000\naccess_token = \"a\"\n",
+    )
+    .unwrap();
+    let yaml = dir.join("app.yml");
+    std::fs::write(&yaml, "websocket:\n  - url: ws://127.0.0.1:9000\n").unwrap();
+
+    std::env::set_var("WALLE_LOADER_TEST_HEARTBEAT__INTERVAL", "10");
+    std::env::set_var("WALLE_LOADER_TEST_WEBSOCKET__0__ACCESS_TOKEN", "123");
+    let loader = ConfigLoader {
+        env_prefix: Some("WALLE_LOADER_TEST".to_string()),
+        ..ConfigLoader::new(&toml)
+    };
+    let config: ImplConfig = loader.load().unwrap();
+    assert_eq!(config.heartbeat.interval, 10);
+    assert!(config.heartbeat.enabled);
+    assert!(config.websocket_rev.is_empty());
+    assert_eq!(config.websocket[0].port, 9000);
+    assert_eq!(config.websocket[0].access_token.as_deref(), Some("123"));
+
+    let config: AppConfig = ConfigLoader::new(&yaml).load().unwrap();
+    assert_eq!(config.websocket[0].url, "ws://127.0.0.1:9000");
+    assert_eq!(config.websocket[0].reconnect.initial, 1.0);
+
+    std::env::set_var("WALLE_LOADER_TEST_WEBSOCKET__0__PORT", "not a port");
+    let e = loader.load::<ImplConfig>().unwrap_err().to_string();
+    assert!(e.contains("websocket[0].port"), "{}", e);
+    std::env::set_var("WALLE_LOADER_TEST_WEBSOCKET__0__PORT", "6700");
+    std::env::set_var(
+        "WALLE_LOADER_TEST_HTTP",
+        r#"[{"host": "0.0.0.0", "port": 6700}]"#,
+    );
+    let e = loader.load::<ImplConfig>().unwrap_err().to_string();
+    assert!(
+        e.contains("address 0.0.0.0:6700 is already used by http[0]"),
+        "{}",
+        e
+    );
+    assert!(ConfigLoader::new(dir.join("impl.ini"))
+        .load::<ImplConfig>()
+        .is_err());
+    for key in [
+        "HEARTBEAT__INTERVAL",
+        "WEBSOCKET__0__ACCESS_TOKEN",
+        "WEBSOCKET__0__PORT",
+        "HTTP",
+    ] {
+        std::env::remove_var(format!("WALLE_LOADER_TEST_{}", key));
+    }
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[cfg(all(feature = "impl-obc", feature = "websocket"))]
+#[test]
+fn watch_test() {
+    use super::*;
+    use crate::{event::Event, obc::FakeOneBot};
+
+    let dir = std::env::temp_dir().join(format!("walle-watch-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("impl.toml");
+    let write = |servers: usize| {
+        let mut s = "http = []\nhttp_webhook = []\nwebsocket_rev = []\n".to_string();
+        for _ in 0..servers {
+            s.push_str("[[websocket]]\nhost = \"127.0.0.1\"\nport = 0\n");
+        }
+        std::fs::write(&path, s).unwrap();
+    };
+    write(1);
+    let loader = ConfigLoader {
+        env_prefix: None,
+        ..ConfigLoader::new(&path)
+    };
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    rt.block_on(async {
+        let config: ImplConfig = loader.load().unwrap();
+        // FakePlatform takes its event receiver at start, the reload must start it again
+        let fake = Arc::new(FakeOneBot::fake("bot", "test"));
+        fake.start((), config.clone(), true).await.unwrap();
+        assert_eq!(fake.event_handler.endpoints().len(), 1);
+        let watch = loader.watch::<_, Event, _, _, _, _, _>(
+            &fake,
+            config,
+            Duration::from_millis(10),
+            true,
+            |c: ImplConfig| ((), c),
+        );
+
+        write(2);
+        let reloaded = async {
+            while fake.event_handler.endpoints().len() != 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), reloaded)
+            .await
+            .unwrap();
+        let mut event_rx = fake.event_handler.event_tx.subscribe();
+        let id = fake.action_handler.receive_private("user", "hello");
+        let event = tokio::time::timeout(Duration::from_secs(1), event_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.extra.get("message_id"), Some(&id.into()));
+        // invalid config is ignored
+        std::fs::write(&path, "websocket = 1\n").unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(fake.started());
+        assert_eq!(fake.event_handler.endpoints().len(), 2);
+
+        fake.shutdown::<Event, _, _>().await.unwrap();
+        watch.await.unwrap();
+    });
+    std::fs::remove_dir_all(&dir).ok();
+}
@@ -0,0 +1,641 @@
+use std::{collections::HashMap, fmt::Debug, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "filter")]
+use crate::filter::EventFilter;
+use crate::util::{ContentType, Transport};
+
+#[cfg(feature = "config-loader")]
+mod loader;
+mod validate;
+#[cfg(feature = "config-loader")]
+pub use loader::*;
+pub use validate::Validate;
+
+/// OneBot 实现端设置项
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ImplConfig {
+    pub http: Vec<HttpServer>,
+    pub http_webhook: Vec<HttpClient>,
+    pub websocket: Vec<WebSocketServer>,
+    pub websocket_rev: Vec<WebSocketClient>,
+    pub heartbeat: Heartbeat,
+}
+
+impl Default for ImplConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat: Heartbeat::default(),
+            http: vec![],
+            http_webhook: vec![],
+            websocket: vec![],
+            websocket_rev: vec![WebSocketClient::default()],
+        }
+    }
+}
+
+/// OneBot 心跳设置
+///
+/// 间隔为 0 则默认为 4
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Heartbeat {
+    pub enabled: bool,
+    pub interval: u32,
+}
+
+impl Heartbeat {
+    /// 间隔为 0 时使用的默认间隔
+    pub const DEFAULT_INTERVAL: u32 = 4;
+
+    /// 实际使用的心跳间隔
+    pub fn interval(&self) -> u32 {
+        match self.interval {
+            0 => Self::DEFAULT_INTERVAL,
+            interval => interval,
+        }
+    }
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval: Self::DEFAULT_INTERVAL,
+        }
+    }
+}
+
+/// OneBot 应用端设置项
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AppConfig {
+    pub http_webhook: Vec<HttpServer>,
+    pub websocket: Vec<WebSocketClient>,
+    pub websocket_rev: Vec<WebSocketServer>,
+    pub http: HashMap<String, HttpClient>,
+    #[serde(default)]
+    pub load_balance: LoadBalance,
+    /// 是否将连接与 Bot 生命周期同时以 meta 事件上报
+    ///
+    /// meta 事件的 impl 与 platform 取自连接收到的事件，Http 连接不上报
+    #[serde(default)]
+    pub lifecycle_meta: bool,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            http: HashMap::default(),
+            http_webhook: vec![],
+            websocket: vec![],
+            websocket_rev: vec![WebSocketServer::default()],
+            load_balance: LoadBalance::default(),
+            lifecycle_meta: false,
+        }
+    }
+}
+
+impl AppConfig {
+    pub fn empty() -> Self {
+        Self {
+            http: HashMap::default(),
+            http_webhook: vec![],
+            websocket: vec![],
+            websocket_rev: vec![],
+            load_balance: LoadBalance::default(),
+            lifecycle_meta: false,
+        }
+    }
+}
+
+/// 协议中继设置
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RelayConfig {
+    /// 连接上游实现端的应用端设置
+    pub upstream: AppConfig,
+    /// 向下游应用端提供服务的实现端设置
+    pub downstream: ImplConfig,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            upstream: AppConfig::default(),
+            downstream: ImplConfig {
+                websocket: vec![WebSocketServer {
+                    port: 8845,
+                    ..Default::default()
+                }],
+                websocket_rev: vec![],
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// 运行时添加的实现端端点
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type")]
+pub enum ImplEndpoint {
+    #[serde(rename = "http")]
+    Http(HttpServer),
+    #[serde(rename = "http_webhook")]
+    HttpWebhook(HttpClient),
+    #[serde(rename = "websocket")]
+    WebSocket(WebSocketServer),
+    #[serde(rename = "websocket_rev")]
+    WebSocketRev(WebSocketClient),
+}
+
+impl ImplEndpoint {
+    pub fn transport(&self) -> Transport {
+        match self {
+            Self::Http(_) => Transport::Http,
+            Self::HttpWebhook(_) => Transport::HttpWebhook,
+            Self::WebSocket(_) => Transport::WebSocket,
+            Self::WebSocketRev(_) => Transport::WebSocketRev,
+        }
+    }
+}
+
+/// 运行时添加的应用端端点
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type")]
+pub enum AppEndpoint {
+    #[serde(rename = "http")]
+    Http {
+        self_id: String,
+        #[serde(flatten)]
+        client: HttpClient,
+    },
+    #[serde(rename = "http_webhook")]
+    HttpWebhook(HttpServer),
+    #[serde(rename = "websocket")]
+    WebSocket(WebSocketClient),
+    #[serde(rename = "websocket_rev")]
+    WebSocketRev(WebSocketServer),
+}
+
+impl AppEndpoint {
+    pub fn transport(&self) -> Transport {
+        match self {
+            Self::Http { .. } => Transport::Http,
+            Self::HttpWebhook(_) => Transport::HttpWebhook,
+            Self::WebSocket(_) => Transport::WebSocket,
+            Self::WebSocketRev(_) => Transport::WebSocketRev,
+        }
+    }
+}
+
+/// 同一 Bot 存在多个连接时，Action 连接选择策略
+///
+/// 发送失败时会依次尝试下一个连接
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadBalance {
+    /// 轮流使用各连接
+    #[default]
+    RoundRobin,
+    /// 优先使用 WebSocket 连接
+    PreferWebsocket,
+    /// 优先使用等待响应数最少的连接
+    LeastPending,
+}
+
+/// OneBot Impl Http 通讯设置
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HttpServer {
+    pub host: std::net::IpAddr,
+    pub port: u16,
+    pub access_token: Option<String>,
+    /// 额外接受的 access_token，用于无停机更换密钥或限定权限
+    #[serde(default)]
+    pub access_tokens: Vec<AccessToken>,
+    /// 推送签名密钥，设置后校验 `X-Signature` 请求头
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// 签名时间戳允许的误差秒数
+    #[serde(default = "default_signature_window")]
+    pub signature_window: u64,
+    /// 事件处理结束后等待快速操作的时间（秒），默认不等待，仅应用端有效
+    #[serde(default)]
+    pub quick_reply_window: u64,
+    /// 是否等待实现端回报快速操作结果，否则快速操作不会得到响应
+    #[serde(default)]
+    pub quick_reply_results: bool,
+    /// 启用 https
+    #[serde(default)]
+    pub tls: Option<TlsServer>,
+    /// 设置后监听 Unix socket 而非 host:port
+    #[serde(default)]
+    pub unix: Option<UnixSocket>,
+    /// 事件过滤表达式，仅应用端有效
+    #[cfg(feature = "filter")]
+    #[serde(default)]
+    pub filter: Option<EventFilter>,
+    #[cfg(feature = "impl")]
+    pub event_enable: bool,
+    #[cfg(feature = "impl")]
+    pub event_buffer_size: usize,
+}
+
+impl Default for HttpServer {
+    fn default() -> Self {
+        Self {
+            host: std::net::IpAddr::from([127, 0, 0, 1]),
+            port: 6700,
+            access_token: None,
+            access_tokens: vec![],
+            secret: None,
+            signature_window: default_signature_window(),
+            quick_reply_window: 0,
+            quick_reply_results: false,
+            tls: None,
+            unix: None,
+            #[cfg(feature = "filter")]
+            filter: None,
+            #[cfg(feature = "impl")]
+            event_enable: true,
+            #[cfg(feature = "impl")]
+            event_buffer_size: 16,
+        }
+    }
+}
+
+impl HttpServer {
+    /// 全部接受的 access_token
+    pub fn tokens(&self) -> Vec<AccessToken> {
+        self.access_token
+            .iter()
+            .cloned()
+            .map(AccessToken::Token)
+            .chain(self.access_tokens.iter().cloned())
+            .collect()
+    }
+}
+
+fn default_signature_window() -> u64 {
+    300
+}
+
+/// 服务端接受的 access_token
+///
+/// 可以是单纯的字符串，也可以附带权限范围
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum AccessToken {
+    Token(String),
+    Scoped {
+        token: String,
+        #[serde(flatten)]
+        scope: Scope,
+        /// 无法识别的字段，配置校验时报错
+        #[serde(flatten, skip_serializing)]
+        unknown: std::collections::BTreeMap<String, serde_json::Value>,
+    },
+}
+
+impl AccessToken {
+    pub fn token(&self) -> &str {
+        match self {
+            Self::Token(token) => token,
+            Self::Scoped { token, .. } => token,
+        }
+    }
+
+    pub fn scope(&self) -> Scope {
+        match self {
+            Self::Token(_) => Scope::default(),
+            Self::Scoped { scope, .. } => scope.clone(),
+        }
+    }
+}
+
+/// access_token 或连接的权限范围，仅对实现端生效
+///
+/// 各项为空时不做限制，支持 `*` 通配，如 `get_*`、`message.*`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scope {
+    /// 允许调用的 action
+    pub actions: Vec<String>,
+    /// 允许操作与接收的 self_id
+    pub self_ids: Vec<String>,
+    /// 允许接收的事件，格式为 `type.detail_type`
+    pub events: Vec<String>,
+}
+
+impl Scope {
+    pub fn is_unrestricted(&self) -> bool {
+        self.actions.is_empty() && self.self_ids.is_empty() && self.events.is_empty()
+    }
+
+    pub fn allow_action(&self, action: &str, self_id: &str) -> bool {
+        allow(&self.actions, action) && allow(&self.self_ids, self_id)
+    }
+
+    pub fn allow_self_id(&self, self_id: &str) -> bool {
+        allow(&self.self_ids, self_id)
+    }
+
+    pub fn allow_event(&self, ty: &str, detail_type: &str, self_id: &str) -> bool {
+        allow(&self.events, &format!("{}.{}", ty, detail_type)) && allow(&self.self_ids, self_id)
+    }
+}
+
+fn allow(patterns: &[String], s: &str) -> bool {
+    patterns.is_empty() || patterns.iter().any(|p| wildcard_match(p, s))
+}
+
+/// `*` 匹配任意长度字符
+pub(crate) fn wildcard_match(pattern: &str, s: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match s.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let parts: Vec<&str> = parts.collect();
+    match parts.split_last() {
+        None => rest.is_empty(),
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(idx) => rest = &rest[idx + part.len()..],
+                    None => return false,
+                }
+            }
+            rest.len() >= last.len() && rest.ends_with(last)
+        }
+    }
+}
+
+/// OneBot Impl Http Webhook 通讯设置
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HttpClient {
+    pub url: String,
+    pub access_token: Option<String>,
+    pub timeout: u64,
+    /// 推送编码，默认为 json
+    #[serde(default)]
+    pub content_type: Option<ContentType>,
+    /// 推送签名密钥，设置后附带 `X-Signature` 与 `X-Timestamp` 请求头
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// 是否将快速操作的执行结果回报给应用端
+    #[serde(default)]
+    pub report_results: bool,
+    /// url 为 https 时的 TLS 设置
+    #[serde(default)]
+    pub tls: TlsClient,
+    /// 设置后经由该 Unix socket 连接，url 仅用于请求路径
+    #[serde(default)]
+    pub unix: Option<PathBuf>,
+    /// 推送事件的过滤范围，仅实现端有效
+    #[serde(default)]
+    pub scope: Scope,
+    /// 推送事件的过滤表达式，仅实现端有效
+    #[cfg(feature = "filter")]
+    #[serde(default)]
+    pub filter: Option<EventFilter>,
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        Self {
+            url: "http://127.0.0.1:6700".to_owned(),
+            access_token: None,
+            timeout: 4,
+            content_type: None,
+            secret: None,
+            report_results: false,
+            tls: TlsClient::default(),
+            unix: None,
+            scope: Scope::default(),
+            #[cfg(feature = "filter")]
+            filter: None,
+        }
+    }
+}
+
+/// OneBot WebSocket 服务器设置
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WebSocketServer {
+    pub host: std::net::IpAddr,
+    pub port: u16,
+    pub access_token: Option<String>,
+    /// 额外接受的 access_token，用于无停机更换密钥或限定权限
+    #[serde(default)]
+    pub access_tokens: Vec<AccessToken>,
+    #[serde(default)]
+    pub keepalive: Keepalive,
+    /// 连接编码，未指定时由对端首个数据帧决定
+    #[serde(default)]
+    pub content_type: Option<ContentType>,
+    /// 启用 wss
+    #[serde(default)]
+    pub tls: Option<TlsServer>,
+    /// 设置后监听 Unix socket 而非 host:port
+    #[serde(default)]
+    pub unix: Option<UnixSocket>,
+    /// 事件过滤表达式
+    #[cfg(feature = "filter")]
+    #[serde(default)]
+    pub filter: Option<EventFilter>,
+}
+
+impl Default for WebSocketServer {
+    fn default() -> Self {
+        Self {
+            host: std::net::IpAddr::from([127, 0, 0, 1]),
+            port: 8844,
+            access_token: None,
+            access_tokens: vec![],
+            keepalive: Keepalive::default(),
+            content_type: None,
+            tls: None,
+            unix: None,
+            #[cfg(feature = "filter")]
+            filter: None,
+        }
+    }
+}
+
+impl WebSocketServer {
+    /// 全部接受的 access_token
+    pub fn tokens(&self) -> Vec<AccessToken> {
+        self.access_token
+            .iter()
+            .cloned()
+            .map(AccessToken::Token)
+            .chain(self.access_tokens.iter().cloned())
+            .collect()
+    }
+}
+
+/// OneBot Impl 反向 WebSocket 通讯设置
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WebSocketClient {
+    pub url: String,
+    pub access_token: Option<String>,
+    /// 同时以 `access_token` 查询参数发送 token，用于无法读取请求头的对端
+    ///
+    /// token 可能因此出现在代理与服务端的访问日志中
+    #[serde(default)]
+    pub token_in_query: bool,
+    #[serde(default)]
+    pub reconnect: Backoff,
+    /// 已弃用，等同于 `reconnect.initial`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reconnect_interval: Option<u32>,
+    #[serde(default)]
+    pub keepalive: Keepalive,
+    /// 连接编码，未指定时由对端首个数据帧决定
+    #[serde(default)]
+    pub content_type: Option<ContentType>,
+    /// url 为 wss 时的 TLS 设置
+    #[serde(default)]
+    pub tls: TlsClient,
+    /// 设置后经由该 Unix socket 连接，url 仅用于请求路径
+    #[serde(default)]
+    pub unix: Option<PathBuf>,
+    /// 推送事件与接受 action 的权限范围，仅实现端有效
+    #[serde(default)]
+    pub scope: Scope,
+    /// 事件过滤表达式
+    #[cfg(feature = "filter")]
+    #[serde(default)]
+    pub filter: Option<EventFilter>,
+}
+
+impl Default for WebSocketClient {
+    fn default() -> Self {
+        Self {
+            url: "ws://127.0.0.1:8844".to_owned(),
+            access_token: None,
+            token_in_query: false,
+            reconnect: Backoff::default(),
+            reconnect_interval: None,
+            keepalive: Keepalive::default(),
+            content_type: None,
+            tls: TlsClient::default(),
+            unix: None,
+            scope: Scope::default(),
+            #[cfg(feature = "filter")]
+            filter: None,
+        }
+    }
+}
+
+impl WebSocketClient {
+    /// 实际生效的重连设置，兼容已弃用的 `reconnect_interval`
+    pub fn backoff(&self) -> Backoff {
+        let mut backoff = self.reconnect.clone();
+        if let Some(interval) = self.reconnect_interval {
+            tracing::warn!(
+                target: crate::WALLE_CORE,
+                "reconnect_interval is deprecated, use reconnect.initial instead"
+            );
+            backoff.initial = interval as f64;
+            backoff.max = backoff.max.max(backoff.initial);
+        }
+        backoff
+    }
+}
+
+/// Unix socket 监听设置，仅 unix 平台可用
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UnixSocket {
+    pub path: PathBuf,
+    /// socket 文件权限，如 0o660
+    #[serde(default)]
+    pub mode: Option<u32>,
+}
+
+/// 服务端 TLS 设置，需启用 `tls` feature
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TlsServer {
+    /// PEM 格式证书链
+    pub cert: PathBuf,
+    /// PEM 格式私钥
+    pub key: PathBuf,
+    /// 设置后要求客户端提供由该 CA 签发的证书
+    #[serde(default)]
+    pub client_ca: Option<PathBuf>,
+}
+
+/// 客户端 TLS 设置，需启用 `tls` feature
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TlsClient {
+    /// 自定义 CA 根证书，未设置时使用 webpki 内置根证书
+    pub ca: Option<PathBuf>,
+    /// 客户端证书，用于双向认证
+    pub cert: Option<PathBuf>,
+    /// 客户端证书私钥
+    pub key: Option<PathBuf>,
+}
+
+/// WebSocket 心跳保活设置
+///
+/// 每隔 ping_interval 秒发送 Ping，发送后 pong_timeout 秒内未收到对端任何消息视为连接已断开
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Keepalive {
+    /// 0 为不发送 Ping
+    pub ping_interval: u64,
+    /// 0 为不检测超时
+    pub pong_timeout: u64,
+}
+
+impl Default for Keepalive {
+    fn default() -> Self {
+        Self {
+            ping_interval: 30,
+            pong_timeout: 10,
+        }
+    }
+}
+
+/// WebSocket 重连退避设置
+///
+/// 第 n 次重连前等待 min(initial * multiplier ^ n, max) 秒，并附加 ±jitter 比例的随机抖动
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Backoff {
+    /// 首次重连间隔（秒）
+    pub initial: f64,
+    /// 最大重连间隔（秒）
+    pub max: f64,
+    pub multiplier: f64,
+    /// 0 ~ 1
+    pub jitter: f64,
+    /// 连续重连失败次数上限，None 为不限
+    pub max_attempts: Option<u32>,
+    /// 是否同时以 meta 事件上报重连
+    pub meta_event: bool,
+}
+
+impl Backoff {
+    /// 重连间隔上限（秒）
+    pub const MAX_DELAY: f64 = 24.0 * 60.0 * 60.0;
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: 1.0,
+            max: 60.0,
+            multiplier: 2.0,
+            jitter: 0.1,
+            max_attempts: None,
+            meta_event: false,
+        }
+    }
+}
+
+#[test]
+fn toml_test() {
+    let config = AppConfig::default();
+    println!("{:?}", toml::to_string(&config));
+}
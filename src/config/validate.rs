@@ -0,0 +1,443 @@
+use std::{net::SocketAddr, path::Path};
+
+use super::*;
+use crate::error::{WalleError, WalleResult};
+
+/// 启动前的配置校验
+///
+/// 错误以 `字段路径: 原因` 的形式汇总于 `WalleError::Config`
+pub trait Validate {
+    fn validate(&self) -> WalleResult<()>;
+}
+
+impl Validate for ImplConfig {
+    fn validate(&self) -> WalleResult<()> {
+        let mut checker = Checker::default();
+        self.check("", &mut checker);
+        checker.finish()
+    }
+}
+
+impl Validate for AppConfig {
+    fn validate(&self) -> WalleResult<()> {
+        let mut checker = Checker::default();
+        self.check("", &mut checker);
+        checker.finish()
+    }
+}
+
+/// 上下游在同一进程内监听，同时检查两者的地址冲突
+impl Validate for RelayConfig {
+    fn validate(&self) -> WalleResult<()> {
+        let mut checker = Checker::default();
+        self.upstream.check("upstream.", &mut checker);
+        self.downstream.check("downstream.", &mut checker);
+        checker.finish()
+    }
+}
+
+impl Validate for ImplEndpoint {
+    fn validate(&self) -> WalleResult<()> {
+        let mut checker = Checker::default();
+        match self {
+            Self::Http(http) => http.check("http", &mut checker),
+            Self::HttpWebhook(webhook) => webhook.check("http_webhook", &mut checker),
+            Self::WebSocket(ws) => ws.check("websocket", &mut checker),
+            Self::WebSocketRev(wsr) => wsr.check("websocket_rev", &mut checker),
+        }
+        checker.finish()
+    }
+}
+
+impl Validate for AppEndpoint {
+    fn validate(&self) -> WalleResult<()> {
+        let mut checker = Checker::default();
+        match self {
+            Self::Http { self_id, client } => {
+                let path = format!("http.{}", self_id);
+                if self_id.is_empty() {
+                    checker.error(&path, "self_id is empty");
+                }
+                client.check(&path, &mut checker)
+            }
+            Self::HttpWebhook(webhook) => webhook.check("http_webhook", &mut checker),
+            Self::WebSocket(ws) => ws.check("websocket", &mut checker),
+            Self::WebSocketRev(wsr) => wsr.check("websocket_rev", &mut checker),
+        }
+        checker.finish()
+    }
+}
+
+#[derive(Default)]
+struct Checker {
+    errors: Vec<String>,
+    listening: Vec<(SocketAddr, String)>,
+}
+
+impl Checker {
+    fn error<T: std::fmt::Display>(&mut self, path: &str, msg: T) {
+        self.errors.push(format!("{}: {}", path, msg));
+    }
+
+    /// 端口为 0 时由系统分配，不会冲突
+    fn listen(&mut self, path: &str, addr: SocketAddr) {
+        if addr.port() == 0 {
+            return;
+        }
+        match self.listening.iter().find(|(a, _)| *a == addr) {
+            Some((_, used)) => {
+                let msg = format!("address {} is already used by {}", addr, used);
+                self.error(path, msg)
+            }
+            None => self.listening.push((addr, path.to_string())),
+        }
+    }
+
+    fn file(&mut self, path: &str, file: &Path) {
+        if let Err(e) = std::fs::metadata(file) {
+            self.error(path, format!("{}: {}", file.display(), e));
+        }
+    }
+
+    fn finish(self) -> WalleResult<()> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(WalleError::Config(self.errors))
+        }
+    }
+}
+
+impl ImplConfig {
+    fn check(&self, prefix: &str, c: &mut Checker) {
+        for (i, http) in self.http.iter().enumerate() {
+            http.check(&format!("{}http[{}]", prefix, i), c);
+        }
+        for (i, webhook) in self.http_webhook.iter().enumerate() {
+            webhook.check(&format!("{}http_webhook[{}]", prefix, i), c);
+        }
+        for (i, ws) in self.websocket.iter().enumerate() {
+            ws.check(&format!("{}websocket[{}]", prefix, i), c);
+        }
+        for (i, wsr) in self.websocket_rev.iter().enumerate() {
+            wsr.check(&format!("{}websocket_rev[{}]", prefix, i), c);
+        }
+    }
+}
+
+impl AppConfig {
+    fn check(&self, prefix: &str, c: &mut Checker) {
+        for (i, webhook) in self.http_webhook.iter().enumerate() {
+            webhook.check(&format!("{}http_webhook[{}]", prefix, i), c);
+        }
+        for (i, ws) in self.websocket.iter().enumerate() {
+            ws.check(&format!("{}websocket[{}]", prefix, i), c);
+        }
+        for (i, wsr) in self.websocket_rev.iter().enumerate() {
+            wsr.check(&format!("{}websocket_rev[{}]", prefix, i), c);
+        }
+        let mut http: Vec<_> = self.http.iter().collect();
+        http.sort_by(|a, b| a.0.cmp(b.0));
+        for (self_id, client) in http {
+            let path = format!("{}http.{}", prefix, self_id);
+            if self_id.is_empty() {
+                c.error(&path, "self_id is empty");
+            }
+            client.check(&path, c);
+        }
+    }
+}
+
+impl HttpServer {
+    fn check(&self, path: &str, c: &mut Checker) {
+        check_server(
+            path,
+            c,
+            (self.host, self.port),
+            &self.tokens(),
+            &self.tls,
+            &self.unix,
+        );
+    }
+}
+
+impl WebSocketServer {
+    fn check(&self, path: &str, c: &mut Checker) {
+        check_server(
+            path,
+            c,
+            (self.host, self.port),
+            &self.tokens(),
+            &self.tls,
+            &self.unix,
+        );
+    }
+}
+
+impl HttpClient {
+    fn check(&self, path: &str, c: &mut Checker) {
+        check_client(path, c, &self.url, ["http", "https"], &self.tls, &self.unix);
+        if self.timeout == 0 {
+            c.error(&format!("{}.timeout", path), "must be greater than 0");
+        }
+    }
+}
+
+impl WebSocketClient {
+    fn check(&self, path: &str, c: &mut Checker) {
+        check_client(path, c, &self.url, ["ws", "wss"], &self.tls, &self.unix);
+        self.backoff().check(&format!("{}.reconnect", path), c);
+    }
+}
+
+impl Backoff {
+    fn check(&self, path: &str, c: &mut Checker) {
+        if !(self.initial.is_finite() && self.initial > 0.0) {
+            c.error(&format!("{}.initial", path), "must be greater than 0");
+        }
+        if !self.max.is_finite() || self.max < self.initial {
+            c.error(&format!("{}.max", path), "must not be less than initial");
+        } else if self.max > Backoff::MAX_DELAY {
+            c.error(
+                &format!("{}.max", path),
+                format!("must not be greater than {}", Backoff::MAX_DELAY),
+            );
+        }
+        if !(self.multiplier.is_finite() && self.multiplier >= 1.0) {
+            c.error(&format!("{}.multiplier", path), "must not be less than 1");
+        }
+        if !(0.0..=1.0).contains(&self.jitter) {
+            c.error(&format!("{}.jitter", path), "must be between 0 and 1");
+        }
+    }
+}
+
+fn check_server(
+    path: &str,
+    c: &mut Checker,
+    addr: (std::net::IpAddr, u16),
+    tokens: &[AccessToken],
+    tls: &Option<TlsServer>,
+    unix: &Option<UnixSocket>,
+) {
+    for token in tokens {
+        if token.token().is_empty() {
+            c.error(path, "access_token is empty");
+        }
+        if let AccessToken::Scoped { unknown, .. } = token {
+            for key in unknown.keys() {
+                c.error(path, format!("unknown access_token field `{}`", key));
+            }
+        }
+    }
+    if let Some(tls) = tls {
+        let path = format!("{}.tls", path);
+        if !cfg!(feature = "tls") {
+            c.error(&path, "tls feature is not enabled");
+        }
+        c.file(&format!("{}.cert", path), &tls.cert);
+        c.file(&format!("{}.key", path), &tls.key);
+        if let Some(ca) = &tls.client_ca {
+            c.file(&format!("{}.client_ca", path), ca);
+        }
+    }
+    match unix {
+        Some(_) if tls.is_some() => c.error(path, "tls is not supported over unix socket"),
+        Some(unix) => check_unix(&format!("{}.unix.path", path), c, &unix.path),
+        None => c.listen(path, addr.into()),
+    }
+}
+
+fn check_client(
+    path: &str,
+    c: &mut Checker,
+    url: &str,
+    schemes: [&str; 2],
+    tls: &TlsClient,
+    unix: &Option<std::path::PathBuf>,
+) {
+    let url_path = format!("{}.url", path);
+    match parse_url(url, schemes) {
+        Ok(true) if unix.is_some() => c.error(
+            &url_path,
+            format!("{} is not supported over unix socket", schemes[1]),
+        ),
+        Ok(secure) if secure && !cfg!(feature = "tls") => {
+            c.error(&url_path, format!("{} requires tls feature", schemes[1]))
+        }
+        Ok(_) => {}
+        Err(msg) => c.error(&url_path, format!("{} ({})", msg, url)),
+    }
+    let tls_path = format!("{}.tls", path);
+    for (name, file) in [("ca", &tls.ca), ("cert", &tls.cert), ("key", &tls.key)] {
+        if let Some(file) = file {
+            c.file(&format!("{}.{}", tls_path, name), file);
+        }
+    }
+    if tls.cert.is_some() != tls.key.is_some() {
+        c.error(&tls_path, "cert and key must be set together");
+    }
+    if let Some(unix) = unix {
+        check_unix(&format!("{}.unix", path), c, unix);
+    }
+}
+
+fn check_unix(path: &str, c: &mut Checker, file: &Path) {
+    if !cfg!(unix) {
+        c.error(path, "unix socket is not supported on this platform");
+    } else if file.as_os_str().is_empty() {
+        c.error(path, "is empty");
+    }
+}
+
+/// 以连接时相同的 `Uri` 解析 url，返回是否为加密连接
+fn parse_url(url: &str, schemes: [&str; 2]) -> Result<bool, String> {
+    let uri: http::Uri = url.parse().map_err(|e| format!("invalid url: {}", e))?;
+    let scheme = uri.scheme_str().ok_or_else(|| {
+        format!(
+            "missing scheme, expect {}:// or {}://",
+            schemes[0], schemes[1]
+        )
+    })?;
+    let secure = match scheme.to_ascii_lowercase() {
+        s if s == schemes[0] => false,
+        s if s == schemes[1] => true,
+        _ => {
+            return Err(format!(
+                "unsupported scheme `{}`, expect {} or {}",
+                scheme, schemes[0], schemes[1]
+            ))
+        }
+    };
+    if !matches!(uri.host(), Some(host) if !host.is_empty()) {
+        return Err("missing host".to_string());
+    }
+    // Uri accepts any digits as port, but port_u16 is None when it overflows
+    let authority = uri.authority().map(|a| a.as_str()).unwrap_or_default();
+    match authority.rsplit_once(':') {
+        Some((_, port)) if !port.contains(']') && !port.is_empty() && uri.port().is_none() => {
+            Err(format!("invalid port `{}`", port))
+        }
+        _ => Ok(secure),
+    }
+}
+
+#[test]
+fn validate_test() {
+    assert!(ImplConfig::default().validate().is_ok());
+    assert!(AppConfig::default().validate().is_ok());
+    assert!(RelayConfig::default().validate().is_ok());
+    assert!(serde_json::from_str::<Scope>(r#"{"action":["get_*"]}"#).is_err());
+    let client: WebSocketClient =
+        serde_json::from_str(r#"{"url":"ws://127.0.0.1","reconnect_interval":120}"#).unwrap();
+    let backoff = client.backoff();
+    assert_eq!((backoff.initial, backoff.max), (120.0, 120.0));
+    for (max, expect) in [
+        (f64::INFINITY, "must not be less than initial"),
+        (1e9, "must not be greater than 86400"),
+    ] {
+        let mut client = WebSocketClient::default();
+        client.reconnect.max = max;
+        let e = ImplEndpoint::WebSocketRev(client).validate().unwrap_err();
+        assert!(e.to_string().contains(expect), "{}", e);
+    }
+
+    let config = ImplConfig {
+        http: vec![HttpServer {
+            port: 8844,
+            access_tokens: vec![
+                serde_json::from_str(r#"{"token":"t","action":["get_*"]}"#).unwrap()
+            ],
+            ..Default::default()
+        }],
+        websocket: vec![WebSocketServer::default()],
+        websocket_rev: vec![
+            WebSocketClient {
+                url: "127.0.0.1:8844".to_string(),
+                ..Default::default()
+            },
+            WebSocketClient {
+                url: "ws://:80/".to_string(),
+                reconnect: Backoff {
+                    max: 0.5,
+                    jitter: 2.0,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            WebSocketClient {
+                url: "ws://[::1]:65536".to_string(),
+                tls: TlsClient {
+                    cert: Some("cert.pem".into()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        ],
+        http_webhook: vec![HttpClient {
+            url: "ws://127.0.0.1".to_string(),
+            timeout: 0,
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    let errors = match config.validate() {
+        Err(WalleError::Config(errors)) => errors,
+        r => panic!("{:?}", r),
+    };
+    for expect in [
+        "http[0]: unknown access_token field `action`",
+        "http_webhook[0].url: unsupported scheme `ws`",
+        "http_webhook[0].timeout: must be greater than 0",
+        "websocket[0]: address 127.0.0.1:8844 is already used by http[0]",
+        "websocket_rev[0].url: missing scheme",
+        "websocket_rev[1].url: missing host",
+        "websocket_rev[1].reconnect.max: must not be less than initial",
+        "websocket_rev[1].reconnect.jitter: must be between 0 and 1",
+        "websocket_rev[2].url: invalid port `65536`",
+        "websocket_rev[2].tls.cert: cert.pem:",
+        "websocket_rev[2].tls: cert and key must be set together",
+    ] {
+        assert!(
+            errors.iter().any(|e| e.starts_with(expect)),
+            "{} not in {:#?}",
+            expect,
+            errors
+        );
+    }
+    assert_eq!(errors.len(), 11, "{:#?}", errors);
+
+    let tls = TlsServer {
+        cert: "cert.pem".into(),
+        key: "key.pem".into(),
+        client_ca: None,
+    };
+    let unix = UnixSocket {
+        path: "walle.sock".into(),
+        mode: None,
+    };
+    let server = HttpServer {
+        tls: Some(tls),
+        unix: Some(unix),
+        ..Default::default()
+    };
+    let e = ImplEndpoint::Http(server).validate().unwrap_err();
+    assert!(
+        e.to_string()
+            .contains("http: tls is not supported over unix socket"),
+        "{}",
+        e
+    );
+    let client = WebSocketClient {
+        url: "wss://localhost".to_string(),
+        unix: Some("walle.sock".into()),
+        ..Default::default()
+    };
+    let e = ImplEndpoint::WebSocketRev(client).validate().unwrap_err();
+    assert!(
+        e.to_string()
+            .contains("websocket_rev.url: wss is not supported over unix socket"),
+        "{}",
+        e
+    );
+}
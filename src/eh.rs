@@ -3,6 +3,7 @@ use std::sync::Arc;
 use async_trait::async_trait;
 
 use crate::error::WalleResult;
+use crate::lifecycle::Lifecycle;
 use crate::ActionHandler;
 use crate::OneBot;
 
@@ -31,6 +32,8 @@ pub trait EventHandler<E, A, R>: Sync {
     {
         Ok(resp)
     }
+    /// 连接或 Bot 生命周期变化时调用
+    async fn on_lifecycle(&self, _lifecycle: &Lifecycle) {}
     async fn shutdown(&self) {}
 }
 
@@ -74,6 +77,10 @@ where
         self.0.call(event.clone()).await?;
         self.1.call(event).await
     }
+    async fn on_lifecycle(&self, lifecycle: &Lifecycle) {
+        self.0.on_lifecycle(lifecycle).await;
+        self.1.on_lifecycle(lifecycle).await;
+    }
     async fn shutdown(&self) {
         self.0.shutdown().await;
         self.1.shutdown().await;
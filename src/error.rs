@@ -16,6 +16,8 @@ pub enum WalleError {
     // resp
     #[error("Action Response Timeout")]
     ResponseTimeout,
+    #[error("Connection disconnected")]
+    Disconnected,
     #[error("RespMissmatch")]
     RespNotMatch, //todo
     #[error("{0:?}")]
@@ -40,6 +42,10 @@ pub enum WalleError {
     // OBC
     #[error("Bot not exist")]
     BotNotExist,
+    #[error("Invalid event filter: {0}")]
+    Filter(String),
+    #[error("Invalid config: {}", .0.join("; "))]
+    Config(Vec<String>),
 
     #[error("{0}")]
     Other(String),
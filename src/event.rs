@@ -316,6 +316,20 @@ pub struct Heartbeat {
 }
 pub type HeartbeatEvent<S = (), P = (), I = ()> = BaseEvent<Meta, Heartbeat, S, P, I>;
 
+#[derive(Debug, Clone, PartialEq, Eq, OneBot, PushToValueMap)]
+#[event(detail_type)]
+pub struct Connect {
+    pub version: crate::structs::Version,
+}
+pub type ConnectEvent<S = (), P = (), I = ()> = BaseEvent<Meta, Connect, S, P, I>;
+
+#[derive(Debug, Clone, PartialEq, Eq, OneBot, PushToValueMap)]
+#[event(detail_type)]
+pub struct StatusUpdate {
+    pub status: crate::structs::BotsStatus,
+}
+pub type StatusUpdateEvent<S = (), P = (), I = ()> = BaseEvent<Meta, StatusUpdate, S, P, I>;
+
 #[derive(Debug, Clone, PartialEq, Eq, OneBot, PushToValueMap)]
 #[event(detail_type)]
 pub struct GroupMemberIncrease {
@@ -0,0 +1,360 @@
+//! 事件过滤表达式
+//!
+//! ```text
+//! type == message && (detail_type == "group" && group_id == "123" || not user_id =~ "^10\d+$")
+//! ```
+//!
+//! - 字段为事件 json 中的键，嵌套字段以 `.` 连接，如 `version.impl`
+//! - `==` `!=` 比较字符串形式的值，`=~` `!~` 以正则匹配
+//! - `&&` `||` `!` 可写作 `and` `or` `not`，支持括号
+//! - 单独的字段表示该字段存在且不为空、null 或 false
+//! - 值可以使用单双引号，不含空白与符号时可省略引号
+use std::fmt;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{WalleError, WalleResult};
+
+/// 事件过滤器，以表达式字符串形式序列化
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct EventFilter {
+    source: String,
+    expr: Expr,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Eq(Vec<String>, String),
+    Match(Vec<String>, Regex),
+    Exists(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Eq,
+    Ne,
+    Match,
+    NotMatch,
+    And,
+    Or,
+    Not,
+    LParen,
+    RParen,
+}
+
+impl EventFilter {
+    pub fn new(source: &str) -> WalleResult<Self> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.or()?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            return Err(filter_error(format!("unexpected token {:?}", token)));
+        }
+        Ok(Self {
+            source: source.to_string(),
+            expr,
+        })
+    }
+
+    /// 事件序列化失败时视为不匹配
+    pub fn matches<E: Serialize>(&self, event: &E) -> bool {
+        match serde_json::to_value(event) {
+            Ok(value) => self.expr.eval(&value),
+            Err(_) => false,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+}
+
+impl fmt::Debug for EventFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("EventFilter").field(&self.source).finish()
+    }
+}
+
+impl fmt::Display for EventFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl PartialEq for EventFilter {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl std::str::FromStr for EventFilter {
+    type Err = WalleError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s)
+    }
+}
+
+impl TryFrom<String> for EventFilter {
+    type Error = WalleError;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Self::new(&s)
+    }
+}
+
+impl From<EventFilter> for String {
+    fn from(filter: EventFilter) -> Self {
+        filter.source
+    }
+}
+
+fn filter_error(msg: String) -> WalleError {
+    WalleError::Filter(msg)
+}
+
+fn tokenize(source: &str) -> WalleResult<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        let two = |chars: &mut std::iter::Peekable<std::str::Chars>, next: char| {
+            chars.next();
+            chars.next_if_eq(&next).is_some()
+        };
+        let token = match c {
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '(' => {
+                chars.next();
+                Token::LParen
+            }
+            ')' => {
+                chars.next();
+                Token::RParen
+            }
+            '&' if two(&mut chars, '&') => Token::And,
+            '|' if two(&mut chars, '|') => Token::Or,
+            '=' => {
+                chars.next();
+                match chars.next() {
+                    Some('=') => Token::Eq,
+                    Some('~') => Token::Match,
+                    _ => return Err(filter_error("expect == or =~".to_string())),
+                }
+            }
+            '!' => {
+                chars.next();
+                match chars.peek() {
+                    Some('=') => {
+                        chars.next();
+                        Token::Ne
+                    }
+                    Some('~') => {
+                        chars.next();
+                        Token::NotMatch
+                    }
+                    _ => Token::Not,
+                }
+            }
+            '"' | '\'' => {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some(q) if q == c => break,
+                        Some('\\') => match chars.next() {
+                            Some(e) if e == c || e == '\\' => s.push(e),
+                            // keep other escapes for regex, e.g. `\d`
+                            Some(e) => {
+                                s.push('\\');
+                                s.push(e);
+                            }
+                            None => return Err(filter_error("unterminated string".to_string())),
+                        },
+                        Some(ch) => s.push(ch),
+                        None => return Err(filter_error("unterminated string".to_string())),
+                    }
+                }
+                Token::Str(s)
+            }
+            c if is_word_char(c) => {
+                let mut s = String::new();
+                while let Some(ch) = chars.next_if(|c| is_word_char(*c)) {
+                    s.push(ch);
+                }
+                match s.as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    _ => Token::Ident(s),
+                }
+            }
+            c => return Err(filter_error(format!("unexpected character {:?}", c))),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':' | '*')
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.tokens.get(self.pos) == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn or(&mut self) -> WalleResult<Expr> {
+        let mut expr = self.and()?;
+        while self.eat(&Token::Or) {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> WalleResult<Expr> {
+        let mut expr = self.unary()?;
+        while self.eat(&Token::And) {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> WalleResult<Expr> {
+        if self.eat(&Token::Not) {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> WalleResult<Expr> {
+        let field = match self.next() {
+            Some(Token::LParen) => {
+                let expr = self.or()?;
+                if !self.eat(&Token::RParen) {
+                    return Err(filter_error("expect )".to_string()));
+                }
+                return Ok(expr);
+            }
+            Some(Token::Ident(field)) => field.split('.').map(str::to_string).collect(),
+            Some(token) => return Err(filter_error(format!("unexpected token {:?}", token))),
+            None => return Err(filter_error("unexpected end of expression".to_string())),
+        };
+        let op = match self.tokens.get(self.pos) {
+            Some(op @ (Token::Eq | Token::Ne | Token::Match | Token::NotMatch)) => op.clone(),
+            _ => return Ok(Expr::Exists(field)),
+        };
+        self.pos += 1;
+        let value = match self.next() {
+            Some(Token::Ident(s) | Token::Str(s)) => s,
+            _ => return Err(filter_error("expect value".to_string())),
+        };
+        Ok(match op {
+            Token::Eq => Expr::Eq(field, value),
+            Token::Ne => Expr::Not(Box::new(Expr::Eq(field, value))),
+            _ => {
+                let regex = Regex::new(&value).map_err(|e| filter_error(e.to_string()))?;
+                match op {
+                    Token::Match => Expr::Match(field, regex),
+                    _ => Expr::Not(Box::new(Expr::Match(field, regex))),
+                }
+            }
+        })
+    }
+}
+
+impl Expr {
+    fn eval(&self, event: &serde_json::Value) -> bool {
+        match self {
+            Self::And(l, r) => l.eval(event) && r.eval(event),
+            Self::Or(l, r) => l.eval(event) || r.eval(event),
+            Self::Not(e) => !e.eval(event),
+            Self::Eq(field, value) => get_str(event, field).as_deref() == Some(value),
+            Self::Match(field, regex) => get_str(event, field)
+                .map(|s| regex.is_match(&s))
+                .unwrap_or(false),
+            Self::Exists(field) => match get(event, field) {
+                None | Some(serde_json::Value::Null | serde_json::Value::Bool(false)) => false,
+                Some(serde_json::Value::String(s)) => !s.is_empty(),
+                Some(_) => true,
+            },
+        }
+    }
+}
+
+fn get<'a>(event: &'a serde_json::Value, field: &[String]) -> Option<&'a serde_json::Value> {
+    field.iter().try_fold(event, |v, key| match v {
+        serde_json::Value::Array(a) => key.parse::<usize>().ok().and_then(|i| a.get(i)),
+        v => v.get(key),
+    })
+}
+
+/// 字段的字符串形式，数组与对象以 json 表示
+fn get_str(event: &serde_json::Value, field: &[String]) -> Option<String> {
+    match get(event, field)? {
+        serde_json::Value::Null => None,
+        serde_json::Value::String(s) => Some(s.clone()),
+        v => Some(v.to_string()),
+    }
+}
+
+#[test]
+fn filter_test() {
+    let event = serde_json::json!({
+        "type": "message",
+        "detail_type": "group",
+        "self_id": "bot",
+        "group_id": "123",
+        "user_id": 10086,
+        "message": [{ "type": "text", "data": { "text": "hello" } }],
+        "muted": false,
+    });
+    let check = |s: &str| EventFilter::new(s).unwrap().matches(&event);
+    assert!(check("type == message"));
+    assert!(check(r#"type == "message" && detail_type == 'group'"#));
+    assert!(check("detail_type == private || group_id == 123"));
+    assert!(check(r#"user_id =~ "^10\d+$" and not muted"#));
+    assert!(check("message.0.data.text == hello"));
+    assert!(check("!(self_id != bot) && group_id && !guild_id"));
+    assert!(!check("type == notice or (group_id !~ '^1')"));
+    for bad in [
+        "",
+        "type ==",
+        "(type == a",
+        "type = a",
+        "a == b c",
+        "a =~ '('",
+    ] {
+        assert!(EventFilter::new(bad).is_err(), "{}", bad);
+    }
+    let filter: EventFilter = serde_json::from_str(r#""type == message""#).unwrap();
+    assert_eq!(
+        serde_json::to_string(&filter).unwrap(),
+        r#""type == message""#
+    );
+    assert!(serde_json::from_str::<EventFilter>(r#""type ==""#).is_err());
+}
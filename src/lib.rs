@@ -10,6 +10,11 @@ pub mod alt;
 pub mod config;
 pub mod error;
 pub mod event;
+#[cfg(feature = "filter")]
+pub mod filter;
+pub mod lifecycle;
+#[cfg(any(feature = "impl-obc", feature = "app-obc"))]
+pub mod record;
 pub mod resp;
 pub mod segment;
 pub mod structs;
@@ -44,20 +49,38 @@ pub struct OneBot<AH, EH> {
     pub event_handler: EH,
     // Some for running, None for stopped
     signal: std::sync::Mutex<Option<tokio::sync::broadcast::Sender<()>>>,
+    // true from start until shutdown finished
+    running: tokio::sync::watch::Sender<bool>,
+    tasks: util::Tasks,
+    drain_timeout: Duration,
 }
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use crate::error::{WalleError, WalleResult};
 
+/// 关闭时等待连接排空的默认时限
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
 impl<AH, EH> OneBot<AH, EH> {
     pub fn new(action_handler: AH, event_handler: EH) -> Self {
         Self {
             action_handler,
             event_handler,
             signal: std::sync::Mutex::new(None),
+            running: tokio::sync::watch::channel(false).0,
+            tasks: Default::default(),
+            drain_timeout: DRAIN_TIMEOUT,
         }
     }
+    /// 设置关闭时等待连接排空的时限
+    pub fn with_drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+    pub fn drain_timeout(&self) -> Duration {
+        self.drain_timeout
+    }
     pub async fn start<E, A, R>(
         self: &Arc<Self>,
         ah_config: AH::Config,
@@ -71,33 +94,46 @@ impl<AH, EH> OneBot<AH, EH> {
         AH: ActionHandler<E, A, R> + Send + Sync + 'static,
         EH: EventHandler<E, A, R> + Send + Sync + 'static,
     {
-        let mut signal = self.signal.lock().unwrap();
-        if signal.is_none() {
-            let (tx, _) = tokio::sync::broadcast::channel(1);
-            *signal = Some(tx);
-        } else {
-            return Err(WalleError::AlreadyStarted);
+        {
+            let mut signal = self.signal.lock().unwrap();
+            if signal.is_none() {
+                let (tx, _) = tokio::sync::broadcast::channel(1);
+                *signal = Some(tx);
+                self.running.send_replace(true);
+            } else {
+                return Err(WalleError::AlreadyStarted);
+            }
         }
-        drop(signal);
-        let mut tasks = vec![];
-        if ah_first {
-            tasks.extend(
-                self.action_handler
-                    .start(self, ah_config)
-                    .await?
-                    .into_iter(),
-            );
-            tasks.extend(self.event_handler.start(self, eh_config).await?.into_iter());
-        } else {
-            tasks.extend(self.event_handler.start(self, eh_config).await?.into_iter());
-            tasks.extend(
-                self.action_handler
-                    .start(self, ah_config)
-                    .await?
-                    .into_iter(),
-            );
+        let r = async {
+            let mut tasks = vec![];
+            if ah_first {
+                tasks.extend(
+                    self.action_handler
+                        .start(self, ah_config)
+                        .await?
+                        .into_iter(),
+                );
+                tasks.extend(self.event_handler.start(self, eh_config).await?.into_iter());
+            } else {
+                tasks.extend(self.event_handler.start(self, eh_config).await?.into_iter());
+                tasks.extend(
+                    self.action_handler
+                        .start(self, ah_config)
+                        .await?
+                        .into_iter(),
+                );
+            }
+            Ok(tasks)
+        }
+        .await;
+        if r.is_err() {
+            // 停止已启动的部分并恢复未启动状态，以免 wait 等永远阻塞
+            if let Some(tx) = self.signal.lock().unwrap().take() {
+                tx.send(()).ok();
+            }
+            self.running.send_replace(false);
         }
-        Ok(tasks)
+        r
     }
     pub fn started(&self) -> bool {
         self.signal.lock().unwrap().is_some()
@@ -111,6 +147,18 @@ impl<AH, EH> OneBot<AH, EH> {
             .ok_or(WalleError::NotStarted)?
             .subscribe())
     }
+    /// 在 OneBot 中运行任务，关闭时将等待其结束
+    pub fn spawn<F>(&self, future: F) -> tokio::task::JoinHandle<F::Output>
+    where
+        F: std::future::Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.tasks.spawn(future)
+    }
+    /// 关闭 OneBot
+    ///
+    /// 停止接受新的连接与请求，等待连接在 drain_timeout 内完成处理中的 Action、
+    /// 推送已产生的事件并正常关闭，超时后不再等待
     pub async fn shutdown<E, A, R>(&self) -> WalleResult<()>
     where
         E: Send + Sync + 'static,
@@ -126,10 +174,58 @@ impl<AH, EH> OneBot<AH, EH> {
             .take()
             .ok_or(WalleError::NotStarted)?;
         tx.send(()).ok();
+        drop(tx);
+        if tokio::time::timeout(self.drain_timeout, self.tasks.wait())
+            .await
+            .is_err()
+        {
+            tracing::warn!(
+                target: WALLE_CORE,
+                "{} tasks still running after drain timeout",
+                self.tasks.count()
+            );
+        }
         self.action_handler.shutdown().await;
         self.event_handler.shutdown().await;
+        self.running.send_replace(false);
         Ok(())
     }
+    /// 等待 OneBot 关闭完成，未启动时立即返回
+    pub async fn wait(&self) {
+        let mut running = self.running.subscribe();
+        while *running.borrow_and_update() {
+            if running.changed().await.is_err() {
+                break;
+            }
+        }
+    }
+    /// 运行至收到 SIGINT 或 SIGTERM 后关闭 OneBot，或 OneBot 在别处被关闭
+    #[cfg(feature = "signal")]
+    pub async fn run_until_shutdown<E, A, R>(&self) -> WalleResult<()>
+    where
+        E: Send + Sync + 'static,
+        A: Send + Sync + 'static,
+        R: Send + Sync + 'static,
+        AH: ActionHandler<E, A, R> + Send + Sync + 'static,
+        EH: EventHandler<E, A, R> + Send + Sync + 'static,
+    {
+        if !self.started() {
+            return Err(WalleError::NotStarted);
+        }
+        tokio::select! {
+            _ = self.wait() => return Ok(()),
+            _ = util::shutdown_signal() => {}
+        }
+        tracing::info!(target: WALLE_CORE, "Shutdown signal received, draining");
+        match self.shutdown().await {
+            // shutdown by others at the same time
+            Err(WalleError::NotStarted) => {
+                self.wait().await;
+                Ok(())
+            }
+            r => r,
+        }
+    }
     pub async fn handle_event<E, A, R>(self: &Arc<Self>, event: E) -> WalleResult<()>
     where
         AH: ActionHandler<E, A, R> + Send + Sync + 'static,
@@ -141,6 +237,15 @@ impl<AH, EH> OneBot<AH, EH> {
             .await?;
         self.action_handler.after_call_event().await
     }
+    /// 通知双方 Handler 连接或 Bot 的生命周期变化
+    pub async fn handle_lifecycle<E, A, R>(self: &Arc<Self>, lifecycle: lifecycle::Lifecycle)
+    where
+        AH: ActionHandler<E, A, R> + Send + Sync + 'static,
+        EH: EventHandler<E, A, R> + Send + Sync + 'static,
+    {
+        self.action_handler.on_lifecycle(&lifecycle).await;
+        self.event_handler.on_lifecycle(&lifecycle).await;
+    }
     pub async fn handle_action<E, A, R>(self: &Arc<Self>, action: A) -> WalleResult<R>
     where
         AH: ActionHandler<E, A, R> + Send + Sync + 'static,
@@ -0,0 +1,56 @@
+use std::net::SocketAddr;
+
+use crate::util::Transport;
+
+/// OBC 连接与 Bot 的生命周期事件
+///
+/// 通过 `ActionHandler::on_lifecycle` 与 `EventHandler::on_lifecycle` 通知
+///
+/// HTTP Webhook 的 Bot 仅在单次请求期间注册，不会触发生命周期通知
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lifecycle {
+    /// 建立连接
+    Connect {
+        conn_id: u64,
+        transport: Transport,
+        peer: Option<SocketAddr>,
+    },
+    /// 连接断开
+    Disconnect {
+        conn_id: u64,
+        transport: Transport,
+        peer: Option<SocketAddr>,
+    },
+    /// Bot 首次出现在任一连接上
+    BotOnline {
+        self_id: String,
+        conn_id: u64,
+        transport: Transport,
+    },
+    /// Bot 的最后一个连接已移除
+    BotOffline {
+        self_id: String,
+        conn_id: u64,
+        transport: Transport,
+    },
+}
+
+impl Lifecycle {
+    pub fn conn_id(&self) -> u64 {
+        match self {
+            Self::Connect { conn_id, .. }
+            | Self::Disconnect { conn_id, .. }
+            | Self::BotOnline { conn_id, .. }
+            | Self::BotOffline { conn_id, .. } => *conn_id,
+        }
+    }
+
+    pub fn transport(&self) -> Transport {
+        match self {
+            Self::Connect { transport, .. }
+            | Self::Disconnect { transport, .. }
+            | Self::BotOnline { transport, .. }
+            | Self::BotOffline { transport, .. } => *transport,
+        }
+    }
+}
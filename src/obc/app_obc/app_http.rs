@@ -1,23 +1,61 @@
-use std::{collections::HashMap, convert::Infallible, sync::Arc, time::Duration};
+use std::{convert::Infallible, sync::Arc, time::Duration};
 
 use crate::{
     config::{HttpClient, HttpServer},
     error::{WalleError, WalleResult},
-    util::{AuthReqHeaderExt, Echo, ProtocolItem, SelfId},
+    lifecycle::Lifecycle,
+    util::{
+        verify_access_token, verify_signature, AuthReqHeaderExt, ContentType, Echo, EchoMap,
+        ProtocolItem, SelfId, Transport,
+    },
     ActionHandler, EventHandler, OneBot,
 };
 use hyper::{
-    body::Buf,
-    client::HttpConnector,
-    header::{AUTHORIZATION, CONTENT_TYPE},
-    server::conn::Http,
-    service::service_fn,
-    Body, Client as HyperClient, Method, Request, Response,
+    header::CONTENT_TYPE, server::conn::Http, service::service_fn, Body, Client as HyperClient,
+    Method, Request, Response,
 };
-use tokio::{net::TcpListener, sync::mpsc, task::JoinHandle};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::mpsc, task::JoinHandle};
 use tracing::{info, warn};
 
-use super::{AppOBC, BotMapExt, EchoMap};
+use super::{AppOBC, BotConn, BotMapExt, LifecycleNotifier};
+use crate::obc::{
+    filter_allow_event,
+    net::{hyper_client, Acceptor, Connector, Listener},
+    next_conn_id,
+};
+
+/// Webhook 收到的推送，事件或实现端回报的快速操作结果
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+enum WebhookItem<E, R> {
+    Event(E),
+    Results(Vec<Echo<R>>),
+}
+
+/// 收集事件处理期间发出的快速操作
+///
+/// 处理结束时尚无操作，则至多等待 window 以接收首个操作及其后紧随的操作；
+/// window 为 0 时立即返回
+async fn quick_replies<A>(
+    action_rx: &mut mpsc::UnboundedReceiver<Echo<A>>,
+    window: Duration,
+) -> Vec<Echo<A>> {
+    let mut actions = vec![];
+    while let Ok(a) = action_rx.try_recv() {
+        actions.push(a);
+    }
+    if actions.is_empty() && !window.is_zero() {
+        if let Ok(Some(a)) = tokio::time::timeout(window, action_rx.recv()).await {
+            actions.push(a);
+            tokio::task::yield_now().await;
+            while let Ok(a) = action_rx.try_recv() {
+                actions.push(a);
+            }
+        }
+    }
+    actions
+}
 
 impl<A, R> AppOBC<A, R>
 where
@@ -27,172 +65,312 @@ where
     pub(crate) async fn webhook<E, AH, EH>(
         &self,
         ob: &Arc<OneBot<AH, EH>>,
-        config: Vec<HttpServer>,
-        tasks: &mut Vec<JoinHandle<()>>,
-    ) -> WalleResult<()>
+        webhook: HttpServer,
+    ) -> WalleResult<(u64, JoinHandle<()>)>
     where
         E: ProtocolItem + SelfId + Clone,
         AH: ActionHandler<E, A, R> + Send + Sync + 'static,
         EH: EventHandler<E, A, R> + Send + Sync + 'static,
     {
-        for webhook in config {
-            let bot_map = self.bots.clone();
-            let echo_map = self.echos.clone();
-            let access_token = webhook.access_token.clone();
-            let mut signal_rx = ob.get_signal_rx()?;
-            let ob = ob.clone();
-            let addr = std::net::SocketAddr::new(webhook.host, webhook.port);
-            info!(
-                target: crate::WALLE_CORE,
-                "Starting HTTP Webhook server on http://{}", addr
-            );
-            let listener = TcpListener::bind(&addr).await.map_err(WalleError::from)?;
-            let serv = service_fn(move |req: Request<Body>| {
-                let access_token = access_token.clone();
-                let ob = ob.clone();
-                let bot_map = bot_map.clone();
-                let echo_map = echo_map.clone();
-                async move {
-                    if let Some(token) = access_token.as_ref() {
-                        if let Some(header_token) = req
-                            .headers()
-                            .get(AUTHORIZATION)
-                            .and_then(|v| v.to_str().ok())
-                        {
-                            if header_token != format!("Bearer {}", token) {
-                                return Ok(Response::builder()
-                                    .status(403)
-                                    .body("Authorization Header is invalid".into())
-                                    .unwrap());
+        let bot_map = self.bots.clone();
+        let echo_map = self.echos.clone();
+        let access_tokens = webhook.tokens();
+        let secret = webhook.secret.clone();
+        let window = webhook.signature_window;
+        let quick_reply_window = Duration::from_secs(webhook.quick_reply_window);
+        let quick_reply_results = webhook.quick_reply_results;
+        let filter = webhook.filter.clone();
+        let addr = std::net::SocketAddr::new(webhook.host, webhook.port);
+        let acceptor = Acceptor::new(&webhook.tls)?;
+        let listener = Listener::bind(addr, &webhook.unix).await?;
+        let url = listener.url(if acceptor.is_tls() { "https" } else { "http" });
+        info!(target: crate::WALLE_CORE, "Starting HTTP Webhook server on {}", url);
+        let mut endpoint = self
+            .registry
+            .add_endpoint(ob, Transport::HttpWebhook, url)?;
+        let ob_ = ob.clone();
+        let serv = service_fn(move |req: Request<Body>| {
+            let access_tokens = access_tokens.clone();
+            let secret = secret.clone();
+            let filter = filter.clone();
+            let ob = ob_.clone();
+            let bot_map = bot_map.clone();
+            let echo_map = echo_map.clone();
+            async move {
+                if let Err(msg) = verify_access_token(&access_tokens, req.headers(), req.uri()) {
+                    return Ok(Response::builder().status(403).body(msg.into()).unwrap());
+                }
+                let content_type = req
+                    .headers()
+                    .get(CONTENT_TYPE)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(ContentType::new)
+                    .unwrap_or(ContentType::Json);
+                let headers = req.headers().clone();
+                let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                if let Some(secret) = &secret {
+                    if let Err(msg) = verify_signature(secret, window, &headers, &body) {
+                        return Ok(Response::builder().status(401).body(msg.into()).unwrap());
+                    }
+                }
+                match WebhookItem::<E, R>::decode(&body, &content_type) {
+                    Ok(WebhookItem::Event(event)) if !filter_allow_event(&filter, &event) => {}
+                    Ok(WebhookItem::Event(event)) => {
+                        let (action_tx, mut action_rx) = mpsc::unbounded_channel();
+                        let conn = BotConn::new(next_conn_id(), Transport::HttpWebhook, action_tx);
+                        let self_id = event.self_id();
+                        // meta 事件不属于任何 Bot
+                        if !self_id.is_empty() {
+                            bot_map.ensure_bot(&self_id, &conn);
+                        }
+                        if let Err(e) = ob.handle_event(event).await {
+                            warn!(target: super::OBC, "{}", e);
+                        }
+                        let actions = quick_replies(&mut action_rx, quick_reply_window).await;
+                        bot_map.remove_bot(&self_id, conn.id);
+                        if !actions.is_empty() {
+                            if !quick_reply_results {
+                                // quick operation will never get a response
+                                for a in &actions {
+                                    echo_map.cancel(&a.get_echo(), WalleError::Disconnected);
+                                }
                             }
-                        } else {
                             return Ok(Response::builder()
-                                .status(403)
-                                .body("Missing Authorization Header".into())
+                                .header(CONTENT_TYPE, content_type.to_string())
+                                .body(actions.to_body(&content_type))
                                 .unwrap());
                         }
                     }
-                    let body = String::from_utf8(
-                        hyper::body::to_bytes(req.into_body())
-                            .await
-                            .unwrap()
-                            .to_vec(),
-                    )
-                    .unwrap();
-                    match E::json_decode(&body) {
-                        Ok(event) => {
-                            let (action_tx, mut action_rx) = mpsc::unbounded_channel();
-                            let self_id = event.self_id();
-                            bot_map.ensure_bot(&self_id, &action_tx);
-                            if let Err(e) = ob.handle_event(event).await {
-                                warn!(target: super::OBC, "{}", e);
-                            }
-                            if let Ok(Some(a)) = tokio::time::timeout(
-                                std::time::Duration::from_secs(8),
-                                action_rx.recv(),
-                            )
-                            .await
-                            {
-                                let echo_s = a.get_echo();
-                                echo_map.remove(&echo_s);
-                                bot_map.remove_bot(&self_id, &action_tx);
-                                return Ok(Response::new(a.json_encode().into()));
-                            }
+                    Ok(WebhookItem::Results(results)) => {
+                        for result in results {
+                            let (r, echo) = result.unpack();
+                            echo_map.resolve(&echo, r);
                         }
-                        Err(s) => warn!(target: crate::WALLE_CORE, "Webhook json error: {}", s),
                     }
-                    Ok::<Response<Body>, Infallible>(Response::new("".into()))
+                    Err(s) => warn!(target: crate::WALLE_CORE, "Webhook decode error: {}", s),
                 }
-            });
-            tasks.push(tokio::spawn(async move {
-                loop {
-                    let service = serv.clone();
-                    tokio::select! {
-                        _ = signal_rx.recv() => break,
-                        Ok((tcp_stream, _)) = listener.accept() => {
-                            tokio::spawn(async move {
-                                Http::new()
-                                    .serve_connection(tcp_stream, service)
-                                    .await
-                                    .unwrap();
-                            });
-                        }
+                Ok::<Response<Body>, Infallible>(Response::new("".into()))
+            }
+        });
+        let ob = ob.clone();
+        let id = endpoint.id;
+        let task = ob.clone().spawn(async move {
+            loop {
+                let service = serv.clone();
+                tokio::select! {
+                    _ = endpoint.stop.recv() => break,
+                    Ok((stream, addr)) = listener.accept() => {
+                        let acceptor = acceptor.clone();
+                        let mut stop = endpoint.stop.resubscribe();
+                        ob.spawn(async move {
+                            let stream = match acceptor.accept(stream).await {
+                                Ok(stream) => stream,
+                                Err(e) => {
+                                    warn!(target: super::OBC, "TLS handshake with {} failed: {}", addr, e);
+                                    return;
+                                }
+                            };
+                            let conn = Http::new().serve_connection(stream, service);
+                            tokio::pin!(conn);
+                            tokio::select! {
+                                r = &mut conn => r.unwrap(),
+                                _ = stop.recv() => {
+                                    // finish requests in progress, then close
+                                    conn.as_mut().graceful_shutdown();
+                                    conn.await.unwrap();
+                                }
+                            }
+                        });
                     }
                 }
-            }));
-        }
-        Ok(())
+            }
+        });
+        Ok((id, task))
     }
 
     pub(crate) async fn http<E, AH, EH>(
         &self,
         ob: &Arc<OneBot<AH, EH>>,
-        config: HashMap<String, HttpClient>,
-        tasks: &mut Vec<JoinHandle<()>>,
-    ) -> WalleResult<()>
+        bot_id: String,
+        http: HttpClient,
+    ) -> WalleResult<(u64, JoinHandle<()>)>
     where
         E: ProtocolItem + SelfId + Clone,
         AH: ActionHandler<E, A, R> + Send + Sync + 'static,
         EH: EventHandler<E, A, R> + Send + Sync + 'static,
     {
-        let client = Arc::new(HyperClient::new());
-        for (bot_id, http) in config {
-            let (tx, mut rx) = mpsc::unbounded_channel();
-            self.bots.ensure_bot(&bot_id, &tx);
-            let ob = ob.clone();
-            let cli = client.clone();
-            let echo_map = self.echos.clone();
-            let mut signal_rx = ob.get_signal_rx()?;
-            tasks.push(tokio::spawn(async move {
-                loop {
-                    tokio::select! {
-                        _ = signal_rx.recv() => break,
-                        Some(action) = rx.recv() => {
-                            tokio::spawn(http_push(
-                                action,
-                                cli.clone(),
-                                http.clone(),
-                                echo_map.clone(),
-                            ));
-                        }
+        let cli = Arc::new(hyper_client(&http)?);
+        let mut endpoint = self
+            .registry
+            .add_endpoint(ob, Transport::Http, http.url.clone())?;
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let conn = BotConn::new(next_conn_id(), Transport::Http, tx);
+        let content_type = http.content_type.unwrap_or(ContentType::Json);
+        let record = endpoint.conn_ctx().open(
+            conn.id,
+            conn.transport,
+            None,
+            content_type,
+            Default::default(),
+        );
+        record.add_bot(&bot_id);
+        let online = self.bots.ensure_bot(&bot_id, &conn);
+        let ob = ob.clone();
+        let echo_map = self.echos.clone();
+        let bot_map = self.bots.clone();
+        let id = endpoint.id;
+        let task = ob.clone().spawn(async move {
+            // http 连接收不到事件，无从得知对端实现与平台名称，不以 meta 事件上报
+            let mut notifier = LifecycleNotifier::new(false);
+            if online {
+                let online = Lifecycle::BotOnline {
+                    self_id: bot_id.clone(),
+                    conn_id: conn.id,
+                    transport: conn.transport,
+                };
+                notifier.notify(&ob, online).await;
+            }
+            loop {
+                tokio::select! {
+                    _ = endpoint.stop.recv() => break,
+                    Some(action) = rx.recv() => {
+                        ob.spawn(http_push(
+                            action,
+                            cli.clone(),
+                            http.clone(),
+                            echo_map.clone(),
+                        ));
                     }
                 }
-            }));
-        }
-        Ok(())
+            }
+            // send queued actions, shutdown waits for their responses
+            rx.close();
+            while let Ok(action) = rx.try_recv() {
+                ob.spawn(http_push(
+                    action,
+                    cli.clone(),
+                    http.clone(),
+                    echo_map.clone(),
+                ));
+            }
+            // http bots stay online until shutdown
+            if bot_map.remove_bot(&bot_id, conn.id) {
+                let offline = Lifecycle::BotOffline {
+                    self_id: bot_id,
+                    conn_id: conn.id,
+                    transport: conn.transport,
+                };
+                notifier.notify(&ob, offline).await;
+            }
+            drop(record);
+        });
+        Ok((id, task))
     }
 }
 
 async fn http_push<A, R>(
     action: Echo<A>,
-    client: Arc<HyperClient<HttpConnector, Body>>,
+    client: Arc<HyperClient<Connector, Body>>,
     http: HttpClient,
-    echo_map: EchoMap<R>,
+    echo_map: Arc<EchoMap<R>>,
 ) where
     A: ProtocolItem,
     R: ProtocolItem,
 {
     let (action, echo_s) = action.unpack();
+    let content_type = http.content_type.unwrap_or(ContentType::Json);
     let req = Request::builder()
         .method(Method::POST)
         .uri(&http.url)
         .header_auth_token(&http.access_token)
-        .header(CONTENT_TYPE, crate::util::ContentType::Json.to_string())
-        .body(action.to_body(&crate::util::ContentType::Json)) //todo
+        .header(CONTENT_TYPE, content_type.to_string())
+        .body(action.to_body(&content_type))
         .unwrap();
-    match tokio::time::timeout(Duration::from_secs(http.timeout), client.request(req)).await {
-        Ok(Ok(resp)) => {
-            let body = hyper::body::aggregate(resp).await.unwrap(); //todo
-            let r: R = serde_json::from_reader(body.reader()).unwrap();
-            if let Some((_, r_tx)) = echo_map.remove(&echo_s) {
-                r_tx.send(r).ok();
+    let resp =
+        match tokio::time::timeout(Duration::from_secs(http.timeout), client.request(req)).await {
+            Ok(Ok(resp)) => resp,
+            Ok(Err(e)) => {
+                warn!(target: crate::WALLE_CORE, "HTTP push error: {}", e);
+                // only a failed connect guarantees the request was never delivered
+                let error = if e.is_connect() {
+                    WalleError::ActionSendError
+                } else {
+                    WalleError::Disconnected
+                };
+                echo_map.cancel(&echo_s, error);
+                return;
             }
-        }
-        Ok(Err(e)) => {
-            warn!(target: crate::WALLE_CORE, "HTTP push error: {}", e);
-        }
+            Err(e) => {
+                warn!(target: crate::WALLE_CORE, "HTTP push timeout: {}", e);
+                echo_map.cancel(&echo_s, WalleError::ResponseTimeout);
+                return;
+            }
+        };
+    let content_type = resp
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(ContentType::new)
+        .unwrap_or(content_type);
+    match hyper::body::to_bytes(resp.into_body()).await {
+        Ok(body) => match R::decode(&body, &content_type) {
+            Ok(r) => {
+                echo_map.resolve(&echo_s, r);
+            }
+            Err(e) => {
+                warn!(target: crate::WALLE_CORE, "HTTP push resp decode error: {}", e);
+                echo_map.cancel(&echo_s, WalleError::RespNotMatch);
+            }
+        },
         Err(e) => {
-            warn!(target: crate::WALLE_CORE, "HTTP push timeout: {}", e);
+            warn!(target: crate::WALLE_CORE, "HTTP push resp error: {}", e);
+            echo_map.cancel(&echo_s, WalleError::Disconnected);
         }
     }
 }
+
+#[test]
+fn quick_replies_test() {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    rt.block_on(async {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        // no quick reply and no window, the webhook response is not held
+        let start = std::time::Instant::now();
+        assert!(quick_replies::<()>(&mut rx, Duration::ZERO)
+            .await
+            .is_empty());
+        assert!(start.elapsed() < Duration::from_millis(10));
+        let window = Duration::from_millis(50);
+        assert!(quick_replies::<()>(&mut rx, window).await.is_empty());
+        assert!(start.elapsed() >= window);
+        tx.send(Echo {
+            inner: (),
+            echo: None,
+        })
+        .unwrap();
+        tx.send(Echo {
+            inner: (),
+            echo: None,
+        })
+        .unwrap();
+        assert_eq!(quick_replies(&mut rx, window).await.len(), 2);
+        let tx2 = tx.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            tx2.send(Echo {
+                inner: (),
+                echo: None,
+            })
+            .unwrap();
+            tx2.send(Echo {
+                inner: (),
+                echo: None,
+            })
+            .unwrap();
+        });
+        assert_eq!(quick_replies(&mut rx, window).await.len(), 2);
+    });
+}
@@ -1,24 +1,36 @@
 use crate::{
-    config::{WebSocketClient, WebSocketServer},
+    config::{Keepalive, Scope, WebSocketClient, WebSocketServer},
     error::{WalleError, WalleResult},
-    util::{AuthReqHeaderExt, Echo, ProtocolItem, SelfId},
+    filter::EventFilter,
+    lifecycle::Lifecycle,
+    util::{AuthReqHeaderExt, Echo, EchoMap, ProtocolItem, SelfId, Transport},
     ActionHandler, EventHandler, OneBot,
 };
 use crate::{
     obc::{
-        ws_util::{try_connect, upgrade_websocket},
-        AppOBC, BotMap, BotMapExt, EchoMap,
+        downcast_event,
+        endpoint::{Conn, ConnCtx},
+        filter_allow_event,
+        net::{Acceptor, Listener, Stream},
+        next_conn_id,
+        ws_util::{
+            try_connect, upgrade_websocket, wait_reconnect, BackoffTimer, KeepaliveTick,
+            KeepaliveTimer, WsContentType,
+        },
+        AppOBC, BotConn, BotMap, BotMapExt, LifecycleNotifier,
     },
     util::ContentType,
 };
 
-use std::{collections::HashSet, sync::Arc};
+use std::{
+    collections::HashSet,
+    sync::{atomic::Ordering, Arc},
+};
 
 use futures_util::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
-use tokio::net::TcpStream;
+use tokio::sync::mpsc;
 use tokio::task::JoinHandle;
-use tokio::{net::TcpListener, sync::mpsc};
 use tokio_tungstenite::tungstenite::http::{header::USER_AGENT, Request};
 use tokio_tungstenite::tungstenite::Message as WsMsg;
 use tokio_tungstenite::WebSocketStream;
@@ -32,99 +44,146 @@ where
     pub(crate) async fn ws<E, AH, EH>(
         &self,
         ob: &Arc<OneBot<AH, EH>>,
-        config: Vec<WebSocketClient>,
-        tasks: &mut Vec<JoinHandle<()>>,
-    ) -> WalleResult<()>
+        wsc: WebSocketClient,
+    ) -> WalleResult<(u64, JoinHandle<()>)>
     where
         E: ProtocolItem + SelfId + Clone,
         AH: ActionHandler<E, A, R> + Send + Sync + 'static,
         EH: EventHandler<E, A, R> + Send + Sync + 'static,
     {
-        for wsc in config {
-            info!(target: super::OBC, "Start try connect to {}", wsc.url);
-            let ob = ob.clone();
-            let echo_map = self.echos.clone();
-            let bot_map = self.bots.clone();
-            let mut signal_rx = ob.get_signal_rx()?;
-            tasks.push(tokio::spawn(async move {
-                while signal_rx.try_recv().is_err() {
-                    let ob = ob.clone();
-                    let echo_map = echo_map.clone();
-                    let bot_map = bot_map.clone();
-                    let req = Request::builder()
-                        .header(
-                            USER_AGENT,
-                            format!("OneBot/12 Walle-App/{}", crate::VERSION),
-                        )
-                        .header_auth_token(&wsc.access_token);
-                    match try_connect(&wsc, req).await {
-                        Some(ws_stream) => {
-                            ws_loop(ob, ws_stream, echo_map, bot_map).await;
-                            warn!(target: crate::WALLE_CORE, "Disconnected from {}", wsc.url);
-                        }
-                        None => {
-                            tokio::time::sleep(std::time::Duration::from_secs(
-                                wsc.reconnect_interval as u64,
-                            ))
-                            .await;
-                        }
+        info!(target: super::OBC, "Start try connect to {}", wsc.url);
+        let mut endpoint = self
+            .registry
+            .add_endpoint(ob, Transport::WebSocket, wsc.url.clone())?;
+        let ob = ob.clone();
+        let echo_map = self.echos.clone();
+        let bot_map = self.bots.clone();
+        let meta = self.lifecycle_meta.load(Ordering::Relaxed);
+        let id = endpoint.id;
+        let task = ob.clone().spawn(async move {
+            let backoff = wsc.backoff();
+            let mut timer = BackoffTimer::new(&backoff);
+            loop {
+                let req = Request::builder()
+                    .header(
+                        USER_AGENT,
+                        format!("OneBot/12 Walle-App/{}", crate::VERSION),
+                    )
+                    .header_auth_token(&wsc.access_token);
+                if let Some(ws_stream) = try_connect(&wsc, req).await {
+                    timer.reset();
+                    ws_loop(
+                        ob.clone(),
+                        ws_stream,
+                        echo_map.clone(),
+                        bot_map.clone(),
+                        Transport::WebSocket,
+                        wsc.keepalive.clone(),
+                        wsc.content_type,
+                        wsc.filter.clone(),
+                        meta,
+                        endpoint.conn_ctx(),
+                    )
+                    .await;
+                    warn!(target: crate::WALLE_CORE, "Disconnected from {}", wsc.url);
+                }
+                let on_meta = |event| {
+                    if let Some(event) = downcast_event::<E>(event) {
+                        let ob_ = ob.clone();
+                        ob.spawn(async move { ob_.handle_event(event).await });
                     }
+                };
+                if !wait_reconnect(&wsc, &mut timer, &mut endpoint.stop, ("", ""), on_meta).await {
+                    break;
                 }
-            }));
-        }
-        Ok(())
+            }
+        });
+        Ok((id, task))
     }
     pub(crate) async fn wsr<E, AH, EH>(
         &self,
         ob: &Arc<OneBot<AH, EH>>,
-        config: Vec<WebSocketServer>,
-        tasks: &mut Vec<JoinHandle<()>>,
-    ) -> WalleResult<()>
+        wss: WebSocketServer,
+    ) -> WalleResult<(u64, JoinHandle<()>)>
     where
         E: ProtocolItem + SelfId + Clone,
         AH: ActionHandler<E, A, R> + Send + Sync + 'static,
         EH: EventHandler<E, A, R> + Send + Sync + 'static,
     {
-        for wss in config {
-            let addr = std::net::SocketAddr::new(wss.host, wss.port);
-            let tcp_listener = TcpListener::bind(&addr).await.map_err(WalleError::IO)?;
-            info!(
-                target: super::OBC,
-                "Websocket server listening on ws://{}", addr
-            );
-            let ob = ob.clone();
-            let mut signal_rx = ob.get_signal_rx()?;
-            let echo_map = self.echos.clone();
-            let bot_map = self.bots.clone();
-            tasks.push(tokio::spawn(async move {
-                loop {
-                    tokio::select! {
-                        _ = signal_rx.recv() => {
-                            info!(target: super::OBC, "Stop listening on ws://{}", addr);
-                            break;
-                        }
-                        Ok((stream, _)) = tcp_listener.accept() => {
-                            if let Some(ws_stream) =
-                                upgrade_websocket(&wss.access_token, stream)
-                                    .await
-                            {
-                                let ob = ob.clone();
-                                tokio::spawn(ws_loop(ob.clone(), ws_stream, echo_map.clone(), bot_map.clone()));
+        let addr = std::net::SocketAddr::new(wss.host, wss.port);
+        let acceptor = Acceptor::new(&wss.tls)?;
+        let listener = Listener::bind(addr, &wss.unix).await?;
+        let url = listener.url(if acceptor.is_tls() { "wss" } else { "ws" });
+        info!(target: super::OBC, "Websocket server listening on {}", url);
+        let mut endpoint = self
+            .registry
+            .add_endpoint(ob, Transport::WebSocketRev, url.clone())?;
+        let ob = ob.clone();
+        let echo_map = self.echos.clone();
+        let bot_map = self.bots.clone();
+        let meta = self.lifecycle_meta.load(Ordering::Relaxed);
+        let access_tokens = wss.tokens();
+        let id = endpoint.id;
+        let task = ob.clone().spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = endpoint.stop.recv() => {
+                        info!(target: super::OBC, "Stop listening on {}", url);
+                        break;
+                    }
+                    Ok((stream, addr)) = listener.accept() => {
+                        let acceptor = acceptor.clone();
+                        let access_tokens = access_tokens.clone();
+                        let ob_ = ob.clone();
+                        let echo_map = echo_map.clone();
+                        let bot_map = bot_map.clone();
+                        let keepalive = wss.keepalive.clone();
+                        let content_type = wss.content_type;
+                        let filter = wss.filter.clone();
+                        let ctx = endpoint.conn_ctx();
+                        let mut stop = endpoint.stop.resubscribe();
+                        // handshake in its own task, a slow peer must not block accepting
+                        ob.spawn(async move {
+                            let upgrade = tokio::select! {
+                                r = upgrade_websocket(&acceptor, &access_tokens, stream, &addr) => r,
+                                _ = stop.recv() => None,
+                            };
+                            if let Some((ws_stream, _)) = upgrade {
+                                ws_loop(
+                                    ob_,
+                                    ws_stream,
+                                    echo_map,
+                                    bot_map,
+                                    Transport::WebSocketRev,
+                                    keepalive,
+                                    content_type,
+                                    filter,
+                                    meta,
+                                    ctx,
+                                )
+                                .await;
                             }
-                        }
+                        });
                     }
                 }
-            }));
-        }
-        Ok(())
+            }
+        });
+        Ok((id, task))
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn ws_loop<E, A, R, AH, EH>(
     ob: Arc<OneBot<AH, EH>>,
-    mut ws_stream: WebSocketStream<TcpStream>,
-    echo_map: EchoMap<R>,
+    mut ws_stream: WebSocketStream<Stream>,
+    echo_map: Arc<EchoMap<R>>,
     bot_map: BotMap<A>,
+    transport: Transport,
+    keepalive: Keepalive,
+    content_type: Option<ContentType>,
+    filter: Option<EventFilter>,
+    meta: bool,
+    mut ctx: ConnCtx,
 ) where
     E: ProtocolItem + SelfId + Clone,
     A: ProtocolItem,
@@ -133,29 +192,75 @@ async fn ws_loop<E, A, R, AH, EH>(
     EH: EventHandler<E, A, R> + Send + Sync + 'static,
 {
     let (action_tx, mut action_rx) = mpsc::unbounded_channel::<Echo<A>>();
-    let mut signal_rx = ob.get_signal_rx().unwrap(); //todo
     let mut bot_set = HashSet::default();
+    let conn = BotConn::new(next_conn_id(), transport, action_tx);
+    let peer = ws_stream.get_ref().peer_addr().ok();
+    let mut content_type = WsContentType::new(content_type);
+    let record = ctx.open(
+        conn.id,
+        transport,
+        peer,
+        content_type.get(),
+        Scope::default(),
+    );
+    let mut notifier = LifecycleNotifier::new(meta);
+    notifier
+        .notify(
+            &ob,
+            Lifecycle::Connect {
+                conn_id: conn.id,
+                transport,
+                peer,
+            },
+        )
+        .await;
+    let mut keepalive = KeepaliveTimer::new(&keepalive);
+    let mut drain = false;
     loop {
         tokio::select! {
-            _ = signal_rx.recv() => break,
+            _ = ctx.stop.recv() => {
+                drain = true;
+                break;
+            }
+            tick = keepalive.tick() => match tick {
+                KeepaliveTick::Ping => if ws_stream.send(WsMsg::Ping(vec![])).await.is_err() {
+                    break;
+                },
+                KeepaliveTick::Timeout => {
+                    warn!(target: super::OBC, "Websocket peer not responding, close connection");
+                    break;
+                }
+            },
             Some(action) = action_rx.recv() => {
-                if ws_stream.send(action.to_ws_msg(&ContentType::Json)).await.is_err() { //todo
+                let echo = action.get_echo();
+                if ws_stream.send(action.to_ws_msg(&content_type.get())).await.is_err() {
+                    // action never reached the peer, caller may retry on another connection
+                    echo_map.cancel(&echo, WalleError::ActionSendError);
                     break;
                 }
             },
             Some(msg) = ws_stream.next() => {
+                keepalive.alive();
                 match msg {
-                    Ok(msg) => if ws_recv(
-                        msg,
-                        &ob,
-                        &mut ws_stream,
-                        &echo_map,
-                        &bot_map,
-                        &action_tx,
-                        &mut bot_set,
-                    ).await {
-                        break;
-                    },
+                    Ok(msg) => {
+                        if content_type.detect(&msg) {
+                            record.set_content_type(content_type.get());
+                        }
+                        if ws_recv(
+                            msg,
+                            &ob,
+                            &mut ws_stream,
+                            &echo_map,
+                            &bot_map,
+                            &conn,
+                            &record,
+                            &mut bot_set,
+                            &filter,
+                            &mut notifier,
+                        ).await {
+                            break;
+                        }
+                    }
                     Err(_) => {
                         break;
                     }
@@ -163,20 +268,95 @@ async fn ws_loop<E, A, R, AH, EH>(
             }
         }
     }
+    if drain {
+        // no more actions accepted, send queued ones and wait for all responses
+        action_rx.close();
+        let flush = async {
+            let mut queued = true;
+            while queued || echo_map.conn_pending(conn.id) > 0 {
+                tokio::select! {
+                    action = action_rx.recv(), if queued => match action {
+                        Some(action) => {
+                            let echo = action.get_echo();
+                            if ws_stream.send(action.to_ws_msg(&content_type.get())).await.is_err() {
+                                echo_map.cancel(&echo, WalleError::ActionSendError);
+                                return;
+                            }
+                        }
+                        None => queued = false,
+                    },
+                    msg = ws_stream.next() => match msg {
+                        Some(Ok(msg)) => {
+                            if ws_recv(
+                                msg,
+                                &ob,
+                                &mut ws_stream,
+                                &echo_map,
+                                &bot_map,
+                                &conn,
+                                &record,
+                                &mut bot_set,
+                                &filter,
+                                &mut notifier,
+                            )
+                            .await
+                            {
+                                return;
+                            }
+                        }
+                        _ => return,
+                    },
+                }
+            }
+        };
+        if tokio::time::timeout(ob.drain_timeout(), flush)
+            .await
+            .is_err()
+        {
+            warn!(target: super::OBC, "Websocket connection drain timeout");
+        }
+    }
     ws_stream.send(WsMsg::Close(None)).await.ok();
-    for bot in bot_set {
-        bot_map.remove_bot(&bot, &action_tx);
+    for self_id in bot_set {
+        if bot_map.remove_bot(&self_id, conn.id) {
+            let offline = Lifecycle::BotOffline {
+                self_id,
+                conn_id: conn.id,
+                transport,
+            };
+            notifier.notify(&ob, offline).await;
+        }
+    }
+    // fail all actions waiting on this connection immediately
+    action_rx.close();
+    while let Ok(action) = action_rx.try_recv() {
+        echo_map.cancel(&action.get_echo(), WalleError::ActionSendError);
     }
+    echo_map.cancel_conn(conn.id);
+    notifier
+        .notify(
+            &ob,
+            Lifecycle::Disconnect {
+                conn_id: conn.id,
+                transport,
+                peer,
+            },
+        )
+        .await;
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn ws_recv<E, A, R, AH, EH>(
     msg: WsMsg,
     ob: &Arc<OneBot<AH, EH>>,
-    ws_stream: &mut WebSocketStream<TcpStream>,
-    echo_map: &EchoMap<R>,
+    ws_stream: &mut WebSocketStream<Stream>,
+    echo_map: &Arc<EchoMap<R>>,
     bot_map: &BotMap<A>,
-    action_tx: &mpsc::UnboundedSender<Echo<A>>,
+    conn: &BotConn<A>,
+    record: &Conn,
     bot_set: &mut HashSet<String>,
+    filter: &Option<EventFilter>,
+    notifier: &mut LifecycleNotifier,
 ) -> bool
 where
     E: ProtocolItem + Clone + SelfId,
@@ -195,17 +375,30 @@ where
     let handle_ok = |item: Result<ReceiveItem<E, R>, String>| async move {
         match item {
             Ok(ReceiveItem::Event(event)) => {
+                notifier.identify(ob, &event).await;
                 let self_id = event.self_id();
-                bot_map.ensure_bot(&self_id, &action_tx);
-                bot_set.insert(self_id);
-                let ob = ob.clone();
-                tokio::spawn(async move { ob.handle_event(event).await });
+                // meta 事件不属于任何 Bot
+                if !self_id.is_empty() {
+                    if bot_map.ensure_bot(&self_id, conn) {
+                        let online = Lifecycle::BotOnline {
+                            self_id: self_id.clone(),
+                            conn_id: conn.id,
+                            transport: conn.transport,
+                        };
+                        notifier.notify(ob, online).await;
+                    }
+                    if bot_set.insert(self_id.clone()) {
+                        record.add_bot(&self_id);
+                    }
+                }
+                if filter_allow_event(filter, &event) {
+                    let ob_ = ob.clone();
+                    ob.spawn(async move { ob_.handle_event(event).await });
+                }
             }
             Ok(ReceiveItem::Resp(resp)) => {
                 let (r, echos) = resp.unpack();
-                if let Some((_, tx)) = echo_map.remove(&echos) {
-                    tx.send(r).ok();
-                }
+                echo_map.resolve(&echos, r);
             }
             Err(s) => warn!(target: super::OBC, "serde failed: {}", s),
         }
@@ -1,13 +1,23 @@
-use std::sync::{atomic::AtomicU64, Arc};
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
 
+use super::endpoint::{ConnInfo, EndpointInfo, Registry};
 use super::OBC;
-use crate::util::{Echo, EchoInner, EchoS, ProtocolItem, SelfId, SelfIds};
+use crate::config::{AppConfig, AppEndpoint, LoadBalance, Validate};
+#[cfg(any(feature = "impl-obc", feature = "http", feature = "websocket"))]
+use crate::lifecycle::Lifecycle;
+use crate::util::{
+    Echo, EchoMap, EchoS, ProtocolItem, SelfId, SelfIds, Transport, Value, SENT_ECHO,
+};
 use crate::{ActionHandler, EventHandler, GetStatus, OneBot};
 use crate::{WalleError, WalleResult};
 
 use async_trait::async_trait;
 use dashmap::DashMap;
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::mpsc;
 use tokio::task::JoinHandle;
 use tracing::{info, warn};
 
@@ -16,19 +26,48 @@ mod app_http;
 #[cfg(feature = "websocket")]
 mod app_ws;
 
-pub(crate) type EchoMap<R> = Arc<DashMap<EchoS, oneshot::Sender<R>>>;
-pub(crate) type BotMap<A> = Arc<DashMap<String, Vec<mpsc::UnboundedSender<Echo<A>>>>>;
+/// Action 等待响应的超时时间
+pub(crate) const ACTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub(crate) type BotMap<A> = Arc<DashMap<String, Vec<BotConn<A>>>>;
+
+/// Bot 的一个可用连接
+pub struct BotConn<A> {
+    pub id: u64,
+    pub transport: Transport,
+    pub tx: mpsc::UnboundedSender<Echo<A>>,
+}
+
+impl<A> Clone for BotConn<A> {
+    fn clone(&self) -> Self {
+        Self {
+            id: self.id,
+            transport: self.transport,
+            tx: self.tx.clone(),
+        }
+    }
+}
+
+impl<A> BotConn<A> {
+    pub fn new(id: u64, transport: Transport, tx: mpsc::UnboundedSender<Echo<A>>) -> Self {
+        Self { id, transport, tx }
+    }
+}
 
 /// OneBotConnect 应用端实现
 ///
 /// AppOBC impl ActionHandler 接收 Action 并外发处理
 ///
 /// Event 泛型要求实现 Clone + SelfId trait
-/// Action 泛型要求实现 SelfId + ActionType trait
+/// Action 泛型要求实现 Clone + SelfId trait
 pub struct AppOBC<A, R> {
-    pub(crate) echos: EchoMap<R>, // echo channel sender 暂存 Map
-    pub(crate) seq: AtomicU64,    // 用于生成 echo
-    pub bots: BotMap<A>,          // Bot action channel map
+    pub(crate) echos: Arc<EchoMap<R>>, // echo channel sender 暂存 Map
+    pub(crate) seq: AtomicU64,         // 用于生成 echo
+    pub(crate) load_balance: Mutex<LoadBalance>, // 多连接选择策略
+    pub(crate) round_robin: AtomicUsize, // 轮询计数
+    pub(crate) lifecycle_meta: AtomicBool, // 生命周期是否以 meta 事件上报
+    pub(crate) registry: Arc<Registry>, // 端点与连接登记表
+    pub bots: BotMap<A>,               // Bot action channel map
 }
 
 impl<A, R> AppOBC<A, R> {
@@ -40,8 +79,12 @@ impl<A, R> AppOBC<A, R> {
 impl<A, R> Default for AppOBC<A, R> {
     fn default() -> Self {
         Self {
-            echos: Arc::new(DashMap::new()),
+            echos: Arc::new(EchoMap::default()),
             seq: AtomicU64::default(),
+            load_balance: Mutex::new(LoadBalance::default()),
+            round_robin: AtomicUsize::default(),
+            lifecycle_meta: AtomicBool::default(),
+            registry: Arc::default(),
             bots: Arc::new(Default::default()),
         }
     }
@@ -49,114 +92,369 @@ impl<A, R> Default for AppOBC<A, R> {
 
 impl<A, R> AppOBC<A, R> {
     pub(crate) fn next_seg(&self) -> EchoS {
-        EchoS(Some(EchoInner::S(
-            self.seq
-                .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
-                .to_string(),
+        EchoS(Some(Value::Str(
+            self.seq.fetch_add(1, Ordering::Relaxed).to_string(),
         )))
     }
+
+    /// 当前等待响应的 Action 数量
+    pub fn pending_echos(&self) -> usize {
+        self.echos.len()
+    }
+
+    /// 停止并移除端点及其全部连接，端点不存在时返回 false
+    pub fn remove_endpoint(&self, id: u64) -> bool {
+        self.registry.remove_endpoint(id)
+    }
+
+    /// 运行中的端点，OneBot 关闭后全部移除
+    pub fn endpoints(&self) -> Vec<EndpointInfo> {
+        self.registry.endpoints()
+    }
+
+    /// 活动连接
+    pub fn connections(&self) -> Vec<ConnInfo> {
+        self.registry.conns().into_iter().map(|(c, _)| c).collect()
+    }
+
+    /// 按选择策略排列 Bot 的可用连接
+    fn sort_conns(&self, mut conns: Vec<BotConn<A>>) -> Vec<BotConn<A>> {
+        match *self.load_balance.lock().unwrap() {
+            LoadBalance::RoundRobin => {
+                let n = self.round_robin.fetch_add(1, Ordering::Relaxed) % conns.len();
+                conns.rotate_left(n);
+            }
+            LoadBalance::PreferWebsocket => {
+                conns.sort_by_key(|conn| !conn.transport.is_websocket());
+            }
+            LoadBalance::LeastPending => {
+                conns.sort_by_cached_key(|conn| self.echos.conn_pending(conn.id));
+            }
+        }
+        conns
+    }
+
+    async fn call_conn(&self, conn: &BotConn<A>, action: A) -> WalleResult<R> {
+        let seq = self.next_seg();
+        let rx = self
+            .echos
+            .insert(seq.clone(), Some(conn.id), ACTION_TIMEOUT);
+        if let Err(e) = conn.tx.send(seq.pack(action)) {
+            warn!(target: super::OBC, "send action error: {}", e);
+            self.echos.cancel(&seq, WalleError::ActionSendError);
+            return Err(WalleError::ActionSendError);
+        }
+        SENT_ECHO
+            .try_with(|e| *e.borrow_mut() = Some(seq.clone()))
+            .ok();
+        match tokio::time::timeout(ACTION_TIMEOUT, rx).await {
+            Ok(Ok(res)) => res,
+            Ok(Err(_)) => Err(WalleError::Disconnected),
+            Err(_) => {
+                warn!(target: super::OBC, "resp timeout");
+                self.echos.cancel(&seq, WalleError::ResponseTimeout);
+                Err(WalleError::ResponseTimeout)
+            }
+        }
+    }
 }
 
 #[async_trait]
 impl<E, A, R> ActionHandler<E, A, R> for AppOBC<A, R>
 where
     E: ProtocolItem + Clone + SelfId,
-    A: ProtocolItem + SelfId,
+    A: ProtocolItem + Clone + SelfId,
     R: ProtocolItem,
 {
-    type Config = crate::config::AppConfig;
+    type Config = AppConfig;
     async fn start<AH, EH>(
         &self,
         ob: &Arc<OneBot<AH, EH>>,
-        config: crate::config::AppConfig,
+        config: AppConfig,
     ) -> WalleResult<Vec<JoinHandle<()>>>
     where
         AH: ActionHandler<E, A, R> + Send + Sync + 'static,
         EH: EventHandler<E, A, R> + Send + Sync + 'static,
     {
-        let mut tasks = vec![];
+        *self.load_balance.lock().unwrap() = config.load_balance;
+        self.lifecycle_meta
+            .store(config.lifecycle_meta, Ordering::Relaxed);
+        let mut endpoints = vec![];
         #[cfg(feature = "websocket")]
         {
-            self.wsr(ob, config.websocket_rev, &mut tasks).await?;
-            self.ws(ob, config.websocket, &mut tasks).await?;
+            endpoints.extend(
+                config
+                    .websocket_rev
+                    .into_iter()
+                    .map(AppEndpoint::WebSocketRev),
+            );
+            endpoints.extend(config.websocket.into_iter().map(AppEndpoint::WebSocket));
         }
         #[cfg(feature = "http")]
         {
-            self.webhook(ob, config.http_webhook, &mut tasks).await?;
-            self.http(ob, config.http, &mut tasks).await?;
+            endpoints.extend(
+                config
+                    .http_webhook
+                    .into_iter()
+                    .map(AppEndpoint::HttpWebhook),
+            );
+            endpoints.extend(
+                config
+                    .http
+                    .into_iter()
+                    .map(|(self_id, client)| AppEndpoint::Http { self_id, client }),
+            );
         }
+        let mut tasks = vec![];
+        for endpoint in endpoints {
+            tasks.push(self.start_endpoint(ob, endpoint).await?.1);
+        }
+        tasks.push(clear_expired_echos(self.echos.clone(), ob.get_signal_rx()?));
         Ok(tasks)
     }
+    /// 按选择策略依次尝试 Bot 的各个连接
+    ///
+    /// 仅 `ActionSendError` 表示 action 确定未送达对端，此时改用下一个连接重试；
+    /// 其余错误（如发送后连接断开、响应超时）可能已被执行，直接返回以免重复执行
     async fn call(&self, action: A) -> WalleResult<R> {
-        match self.bots.get_bot(&action.self_id()) {
-            Some(action_txs) => {
-                let (tx, rx) = oneshot::channel();
-                let seq = self.next_seg();
-                self.echos.insert(seq.clone(), tx);
-                action_txs
-                    .first()
-                    .unwrap() //todo
-                    .send(seq.pack(action))
-                    .map_err(|e| {
-                        warn!(target: super::OBC, "send action error: {}", e);
-                        WalleError::Other(e.to_string())
-                    })?;
-                match tokio::time::timeout(std::time::Duration::from_secs(10), rx).await {
-                    Ok(Ok(res)) => Ok(res),
-                    Ok(Err(e)) => {
-                        warn!(target: super::OBC, "resp recv error: {:?}", e);
-                        Err(WalleError::Other(e.to_string()))
-                    }
-                    Err(_) => {
-                        warn!(target: super::OBC, "resp timeout");
-                        Err(WalleError::Other("resp timeout".to_string()))
-                    }
+        let self_id = action.self_id();
+        let conns = match self.bots.get_bot(&self_id) {
+            Some(conns) if !conns.is_empty() => self.sort_conns(conns),
+            _ => {
+                warn!(target: super::OBC, "bot not found");
+                return Err(WalleError::BotNotExist);
+            }
+        };
+        let mut action = Some(action);
+        let mut conns = conns.iter().peekable();
+        while let Some(conn) = conns.next() {
+            // only clone action when there is another connection to retry
+            let a = match conns.peek() {
+                Some(_) => action.clone(),
+                None => action.take(),
+            };
+            match self.call_conn(conn, a.unwrap()).await {
+                Err(WalleError::ActionSendError) if conns.peek().is_some() => {
+                    warn!(
+                        target: super::OBC,
+                        "send action to {} via {} failed, retry next connection",
+                        self_id,
+                        conn.transport
+                    );
                 }
+                r => return r,
             }
-            None => {
-                warn!(target: super::OBC, "bot not found");
-                Err(WalleError::BotNotExist)
+        }
+        Err(WalleError::ActionSendError)
+    }
+}
+
+impl<A, R> AppOBC<A, R>
+where
+    A: ProtocolItem + Clone + SelfId,
+    R: ProtocolItem,
+{
+    /// 在运行中的 OneBot 上添加端点，返回端点 id
+    pub async fn add_endpoint<E, AH, EH>(
+        &self,
+        ob: &Arc<OneBot<AH, EH>>,
+        endpoint: AppEndpoint,
+    ) -> WalleResult<u64>
+    where
+        E: ProtocolItem + Clone + SelfId,
+        AH: ActionHandler<E, A, R> + Send + Sync + 'static,
+        EH: EventHandler<E, A, R> + Send + Sync + 'static,
+    {
+        endpoint.validate()?;
+        Ok(self.start_endpoint(ob, endpoint).await?.0)
+    }
+
+    #[cfg_attr(
+        not(any(feature = "http", feature = "websocket")),
+        allow(unused_variables)
+    )]
+    async fn start_endpoint<E, AH, EH>(
+        &self,
+        ob: &Arc<OneBot<AH, EH>>,
+        endpoint: AppEndpoint,
+    ) -> WalleResult<(u64, JoinHandle<()>)>
+    where
+        E: ProtocolItem + Clone + SelfId,
+        AH: ActionHandler<E, A, R> + Send + Sync + 'static,
+        EH: EventHandler<E, A, R> + Send + Sync + 'static,
+    {
+        match endpoint {
+            #[cfg(feature = "websocket")]
+            AppEndpoint::WebSocket(ws) => self.ws(ob, ws).await,
+            #[cfg(feature = "websocket")]
+            AppEndpoint::WebSocketRev(wsr) => self.wsr(ob, wsr).await,
+            #[cfg(feature = "http")]
+            AppEndpoint::Http { self_id, client } => self.http(ob, self_id, client).await,
+            #[cfg(feature = "http")]
+            AppEndpoint::HttpWebhook(webhook) => self.webhook(ob, webhook).await,
+            #[allow(unreachable_patterns)]
+            endpoint => Err(WalleError::Other(format!(
+                "{} is not enabled",
+                endpoint.transport()
+            ))),
+        }
+    }
+}
+
+/// 应用端连接的生命周期通知
+///
+/// 生命周期 meta 事件需要对端的实现与平台名称，二者由连接收到的首个事件得知，
+/// 此前的 meta 事件暂存至得知后再上报；始终未收到事件的连接（如 Http）仅调用 on_lifecycle
+#[cfg(any(feature = "impl-obc", feature = "http", feature = "websocket"))]
+pub(crate) struct LifecycleNotifier {
+    meta: bool,
+    peer: Option<(String, String)>,
+    pending: Vec<Lifecycle>,
+}
+
+#[cfg(any(feature = "impl-obc", feature = "http", feature = "websocket"))]
+impl LifecycleNotifier {
+    /// `meta` 为 true 时同时以 meta 事件上报
+    #[cfg(any(feature = "http", feature = "websocket"))]
+    pub(crate) fn new(meta: bool) -> Self {
+        Self {
+            meta,
+            peer: None,
+            pending: vec![],
+        }
+    }
+
+    /// 已知对端实现与平台名称
+    #[cfg(feature = "impl-obc")]
+    pub(crate) fn with_peer(meta: bool, implt: String, platform: String) -> Self {
+        Self {
+            meta,
+            peer: Some((implt, platform)),
+            pending: vec![],
+        }
+    }
+
+    /// 由收到的事件得知对端的实现与平台名称，并上报暂存的 meta 事件
+    #[cfg(feature = "websocket")]
+    pub(crate) async fn identify<E, A, R, AH, EH>(&mut self, ob: &Arc<OneBot<AH, EH>>, event: &E)
+    where
+        E: ProtocolItem,
+        AH: ActionHandler<E, A, R> + Send + Sync + 'static,
+        EH: EventHandler<E, A, R> + Send + Sync + 'static,
+    {
+        #[derive(serde::Deserialize)]
+        struct EventPeek {
+            #[serde(rename = "impl")]
+            implt: String,
+            platform: String,
+        }
+        if !self.meta || self.peer.is_some() {
+            return;
+        }
+        if let Ok(peek) = serde_json::to_value(event).and_then(serde_json::from_value::<EventPeek>)
+        {
+            self.peer = Some((peek.implt, peek.platform));
+            for lifecycle in std::mem::take(&mut self.pending) {
+                self.report(ob, &lifecycle).await;
+            }
+        }
+    }
+
+    /// 通知生命周期变化
+    pub(crate) async fn notify<E, A, R, AH, EH>(
+        &mut self,
+        ob: &Arc<OneBot<AH, EH>>,
+        lifecycle: Lifecycle,
+    ) where
+        E: ProtocolItem,
+        AH: ActionHandler<E, A, R> + Send + Sync + 'static,
+        EH: EventHandler<E, A, R> + Send + Sync + 'static,
+    {
+        ob.handle_lifecycle(lifecycle.clone()).await;
+        match (self.meta, &self.peer) {
+            (false, _) => {}
+            (true, Some(_)) => self.report(ob, &lifecycle).await,
+            (true, None) => self.pending.push(lifecycle),
+        }
+    }
+
+    async fn report<E, A, R, AH, EH>(&self, ob: &Arc<OneBot<AH, EH>>, lifecycle: &Lifecycle)
+    where
+        E: ProtocolItem,
+        AH: ActionHandler<E, A, R> + Send + Sync + 'static,
+        EH: EventHandler<E, A, R> + Send + Sync + 'static,
+    {
+        let (implt, platform) = match &self.peer {
+            Some(peer) => peer,
+            None => return,
+        };
+        let event =
+            super::lifecycle_event(implt, platform, lifecycle).and_then(super::downcast_event::<E>);
+        if let Some(event) = event {
+            if let Err(e) = ob.handle_event(event).await {
+                warn!(target: super::OBC, "handle lifecycle event error: {}", e);
             }
         }
     }
 }
 
+fn clear_expired_echos<R>(
+    echos: Arc<EchoMap<R>>,
+    mut signal_rx: tokio::sync::broadcast::Receiver<()>,
+) -> JoinHandle<()>
+where
+    R: Send + Sync + 'static,
+{
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            tokio::select! {
+                _ = signal_rx.recv() => break,
+                _ = interval.tick() => {
+                    let cleared = echos.clear_expired();
+                    if cleared > 0 {
+                        warn!(target: super::OBC, "{} action resp timeout", cleared);
+                    }
+                }
+            }
+        }
+    })
+}
+
 pub trait BotMapExt<A> {
-    fn ensure_bot(&self, bot_id: &str, tx: &mpsc::UnboundedSender<Echo<A>>);
-    fn remove_bot(&self, bot_id: &str, tx: &mpsc::UnboundedSender<Echo<A>>);
-    fn get_bot(&self, bot_id: &str) -> Option<Vec<mpsc::UnboundedSender<Echo<A>>>>;
+    /// 登记 Bot 连接，Bot 首次上线时返回 true
+    fn ensure_bot(&self, bot_id: &str, conn: &BotConn<A>) -> bool;
+    /// 移除 Bot 连接，Bot 已无可用连接时返回 true
+    fn remove_bot(&self, bot_id: &str, conn_id: u64) -> bool;
+    fn get_bot(&self, bot_id: &str) -> Option<Vec<BotConn<A>>>;
 }
 
-impl<A> BotMapExt<A> for DashMap<String, Vec<mpsc::UnboundedSender<Echo<A>>>> {
-    fn ensure_bot(&self, bot_id: &str, tx: &mpsc::UnboundedSender<Echo<A>>) {
+impl<A> BotMapExt<A> for DashMap<String, Vec<BotConn<A>>> {
+    fn ensure_bot(&self, bot_id: &str, conn: &BotConn<A>) -> bool {
         let mut refmut = self.entry(bot_id.to_string()).or_default();
-        for x in refmut.value() {
-            if tx.same_channel(&x) {
-                return;
-            }
+        if refmut.iter().any(|c| c.id == conn.id) {
+            return false;
         }
-        refmut.push(tx.clone());
-        info!(target: super::OBC, "New Bot connected: {}", bot_id);
+        refmut.push(conn.clone());
+        info!(
+            target: super::OBC,
+            "New Bot connected: {} via {}", bot_id, conn.transport
+        );
+        refmut.len() == 1
     }
-    fn remove_bot(&self, bot_id: &str, tx: &mpsc::UnboundedSender<Echo<A>>) {
+    fn remove_bot(&self, bot_id: &str, conn_id: u64) -> bool {
         let mut empty = false;
-        if let Some(mut txs) = self.get_mut(bot_id) {
-            for i in 0..txs.len() {
-                if tx.same_channel(&txs[i]) {
-                    txs.remove(i);
-                    break;
-                }
-            }
-            if txs.value().is_empty() {
-                empty = true;
-            }
+        if let Some(mut conns) = self.get_mut(bot_id) {
+            conns.retain(|c| c.id != conn_id);
+            empty = conns.is_empty();
         };
         if empty {
             self.remove(bot_id);
             info!(target: super::OBC, "Bot disconnected: {}", bot_id);
         }
+        empty
     }
-    fn get_bot(&self, bot_id: &str) -> Option<Vec<mpsc::UnboundedSender<Echo<A>>>> {
+    fn get_bot(&self, bot_id: &str) -> Option<Vec<BotConn<A>>> {
         self.get(bot_id).as_deref().cloned()
     }
 }
@@ -179,4 +477,75 @@ impl<A, R> GetStatus for AppOBC<A, R> {
             online: true,
         }
     }
+    fn get_bot_status(&self, self_id: &str) -> crate::structs::Status {
+        crate::structs::Status {
+            good: true,
+            online: self.bots.contains_key(self_id),
+        }
+    }
+}
+
+#[test]
+fn failover_test() {
+    use crate::{action::Action, event::Event, resp::Resp, value_map};
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    rt.block_on(async {
+        let ob = AppOBC::<Action, Resp>::new();
+        let conn = |id: u64, transport: Transport| {
+            let (tx, rx) = mpsc::unbounded_channel();
+            (BotConn::new(id, transport, tx), rx)
+        };
+        let ids = |conns: Vec<BotConn<Action>>| conns.iter().map(|c| c.id).collect::<Vec<_>>();
+        let (c0, _rx0) = conn(0, Transport::Http);
+        let (c1, mut rx1) = conn(1, Transport::WebSocket);
+        let (c2, rx2) = conn(2, Transport::Http);
+        let conns = vec![c0, c1, c2];
+
+        assert_eq!(ids(ob.sort_conns(conns.clone())), vec![0, 1, 2]);
+        assert_eq!(ids(ob.sort_conns(conns.clone())), vec![1, 2, 0]);
+        *ob.load_balance.lock().unwrap() = LoadBalance::PreferWebsocket;
+        assert_eq!(ids(ob.sort_conns(conns.clone()))[0], 1);
+        *ob.load_balance.lock().unwrap() = LoadBalance::LeastPending;
+        let _pending = ob.echos.insert(ob.next_seg(), Some(0), ACTION_TIMEOUT);
+        assert_eq!(ids(ob.sort_conns(conns.clone()))[2], 0);
+
+        // conn 2 is closed before sending, retry on conn 1
+        drop(rx2);
+        for c in &conns[1..] {
+            ob.bots.ensure_bot("bot", c);
+        }
+        *ob.load_balance.lock().unwrap() = LoadBalance::RoundRobin;
+        ob.round_robin.store(1, Ordering::Relaxed);
+        let action = || Action {
+            action: "get_self_info".to_string(),
+            params: value_map! { "self_id": "bot" },
+        };
+        let call = SENT_ECHO.scope(std::cell::RefCell::new(None), async {
+            let resp = ActionHandler::<Event, _, _>::call(&ob, action()).await;
+            (resp, SENT_ECHO.with(|e| e.take()))
+        });
+        let respond = async {
+            let (_, echo) = rx1.recv().await.unwrap().unpack();
+            ob.echos.resolve(&echo, Resp::from(value_map! {}));
+            echo
+        };
+        let ((resp, sent), echo) = tokio::join!(call, respond);
+        assert_eq!(resp.unwrap().retcode, 0);
+        // the echo actually delivered is reported
+        assert_eq!(sent, Some(echo));
+
+        // the action may have been delivered, no retry
+        ob.round_robin.store(0, Ordering::Relaxed);
+        let call = ActionHandler::<Event, _, _>::call(&ob, action());
+        let disconnect = async {
+            rx1.recv().await.unwrap();
+            ob.echos.cancel_conn(1);
+        };
+        let (resp, _) = tokio::join!(call, disconnect);
+        assert!(matches!(resp, Err(WalleError::Disconnected)));
+    });
 }
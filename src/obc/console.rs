@@ -0,0 +1,359 @@
+use std::io::Write;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+
+use async_trait::async_trait;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader, Lines};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use super::ImplOBC;
+use crate::{
+    action::*,
+    error::WalleError,
+    event::*,
+    resp::{resp_error, Resp, RespError},
+    segment::{alt, Mention, MentionAll, MessageSegment, Segments},
+    structs::{GroupInfo, SendMessageResp, Status, UserInfo, Version},
+    util::{new_uuid, timestamp_nano_f64, PushToValueMap, SelfIds, Value},
+    ActionHandler, EventHandler, GetStatus, OneBot, WalleResult,
+};
+use walle_macro::_OneBot as OneBot;
+
+/// 以 ConsolePlatform 为 ActionHandler 的实现端
+pub type ConsoleOneBot = OneBot<ConsolePlatform, ImplOBC<Event>>;
+
+const HELP: &str = "\
+输入文本以当前用户身份发送消息，`@user_id` 提及用户，`@all` 提及全体
+/user <user_id>   切换发送者
+/group <group_id> 切换至群聊
+/private          切换至私聊
+/help             显示帮助";
+
+/// 平台支持的动作
+#[derive(Debug, OneBot)]
+#[action]
+enum ConsoleAction {
+    GetSelfInfo,
+    GetUserInfo(GetUserInfo),
+    SendMessage(SendMessage),
+    DeleteMessage(DeleteMessage),
+    GetGroupInfo(GetGroupInfo),
+    GetStatus,
+    GetVersion,
+}
+
+/// 当前的发送者与会话
+#[derive(Debug, Clone)]
+struct Scene {
+    user_id: String,
+    group_id: Option<String>,
+}
+
+/// 控制台虚拟平台，用于脱离真实平台开发 Bot
+///
+/// 输入的每行文本都会成为当前用户发出的私聊或群消息事件，
+/// 收到的 send_message 以 alt 形式输出
+///
+/// 输入流与事件在运行期间由任务独占，停止后可重新启动
+pub struct ConsolePlatform {
+    console: Arc<Console>,
+    input: Arc<tokio::sync::Mutex<Lines<Box<dyn AsyncBufRead + Send + Unpin>>>>,
+    event_rx: Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<Event>>>,
+}
+
+/// 输入读取任务与 ActionHandler 共享的状态
+struct Console {
+    self_id: String,
+    implt: String,
+    platform: String,
+    scene: Mutex<Scene>,
+    seq: AtomicU64,
+    output: Mutex<Box<dyn Write + Send>>,
+    event_tx: mpsc::UnboundedSender<Event>,
+}
+
+impl ConsolePlatform {
+    /// 读取 stdin，输出至 stdout
+    pub fn new(self_id: &str, platform: &str) -> Self {
+        Self::with_io(
+            self_id,
+            platform,
+            BufReader::new(tokio::io::stdin()),
+            std::io::stdout(),
+        )
+    }
+
+    pub fn with_io<I, O>(self_id: &str, platform: &str, input: I, output: O) -> Self
+    where
+        I: AsyncBufRead + Send + Unpin + 'static,
+        O: Write + Send + 'static,
+    {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        Self {
+            console: Arc::new(Console {
+                self_id: self_id.to_string(),
+                implt: "walle-console".to_string(),
+                platform: platform.to_string(),
+                scene: Mutex::new(Scene {
+                    user_id: "user".to_string(),
+                    group_id: None,
+                }),
+                seq: AtomicU64::new(1),
+                output: Mutex::new(Box::new(output)),
+                event_tx,
+            }),
+            input: Arc::new(tokio::sync::Mutex::new(
+                (Box::new(input) as Box<dyn AsyncBufRead + Send + Unpin>).lines(),
+            )),
+            event_rx: Arc::new(tokio::sync::Mutex::new(event_rx)),
+        }
+    }
+
+    /// 处理一行输入，与从输入流读入相同
+    pub fn input(&self, line: &str) {
+        self.console.input(line)
+    }
+}
+
+impl Console {
+    fn next_id(&self) -> String {
+        self.seq.fetch_add(1, Ordering::Relaxed).to_string()
+    }
+
+    fn print(&self, line: &str) {
+        let mut output = self.output.lock().unwrap();
+        writeln!(output, "{}", line).ok();
+        output.flush().ok();
+    }
+
+    fn push_event<D>(&self, user_id: String, message: Segments, detail_type: D)
+    where
+        D: DetailTypeDeclare + PushToValueMap,
+    {
+        let mut event: Event = BaseEvent {
+            id: new_uuid(),
+            self_id: self.self_id.clone(),
+            time: timestamp_nano_f64(),
+            implt: (),
+            platform: (),
+            ty: Message {
+                message_id: self.next_id(),
+                alt_message: alt(&message),
+                message,
+                user_id,
+            },
+            detail_type,
+            sub_type: (),
+            extra: Default::default(),
+        }
+        .into();
+        event.implt = self.implt.clone();
+        event.platform = self.platform.clone();
+        self.event_tx.send(event).ok();
+    }
+
+    fn input(&self, line: &str) {
+        let line = line.trim();
+        if line.is_empty() {
+            return;
+        }
+        let mut scene = self.scene.lock().unwrap();
+        if let Some(command) = line.strip_prefix('/') {
+            let mut args = command.split_whitespace();
+            match (args.next(), args.next()) {
+                (Some("user"), Some(user_id)) => scene.user_id = user_id.to_string(),
+                (Some("group"), Some(group_id)) => scene.group_id = Some(group_id.to_string()),
+                (Some("private"), None) => scene.group_id = None,
+                _ => {
+                    self.print(HELP);
+                    return;
+                }
+            }
+            match &scene.group_id {
+                Some(group_id) => {
+                    self.print(&format!("-> group {} as {}", group_id, scene.user_id))
+                }
+                None => self.print(&format!("-> private as {}", scene.user_id)),
+            }
+            return;
+        }
+        let scene = scene.clone();
+        let message = parse_message(line);
+        match scene.group_id {
+            Some(group_id) => self.push_event(scene.user_id, message, Group { group_id }),
+            None => self.push_event(scene.user_id, message, Private {}),
+        }
+    }
+
+    fn send_message(&self, a: SendMessage) -> Result<Value, RespError> {
+        let target = match a.detail_type.as_str() {
+            "private" => a.user_id.map(|id| format!("private {}", id)),
+            "group" => a.group_id.map(|id| format!("group {}", id)),
+            "channel" => a
+                .guild_id
+                .zip(a.channel_id)
+                .map(|(guild, channel)| format!("channel {}/{}", guild, channel)),
+            ty => return Err(resp_error::unsupported_param(ty)),
+        }
+        .ok_or_else(|| resp_error::bad_param("missing target id"))?;
+        self.print(&format!(
+            "[{}] {}: {}",
+            target,
+            self.self_id,
+            alt(&a.message)
+        ));
+        Ok(SendMessageResp {
+            message_id: self.next_id(),
+            time: timestamp_nano_f64(),
+        }
+        .into())
+    }
+
+    fn handle(&self, action: ConsoleAction) -> Result<Value, RespError> {
+        let user_info = |user_id: &str| -> Value {
+            UserInfo {
+                user_id: user_id.to_string(),
+                nickname: user_id.to_string(),
+            }
+            .into()
+        };
+        match action {
+            ConsoleAction::GetSelfInfo => Ok(user_info(&self.self_id)),
+            ConsoleAction::GetUserInfo(a) => Ok(user_info(&a.user_id)),
+            ConsoleAction::SendMessage(a) => self.send_message(a),
+            ConsoleAction::DeleteMessage(a) => {
+                self.print(&format!("[delete] {}", a.message_id));
+                Ok(Value::Null)
+            }
+            ConsoleAction::GetGroupInfo(a) => Ok(GroupInfo {
+                group_name: a.group_id.clone(),
+                group_id: a.group_id,
+            }
+            .into()),
+            ConsoleAction::GetStatus => Ok(Status {
+                good: true,
+                online: true,
+            }
+            .into()),
+            ConsoleAction::GetVersion => Ok(Version {
+                implt: self.implt.clone(),
+                platform: self.platform.clone(),
+                version: crate::VERSION.to_string(),
+                onebot_version: "12".to_string(),
+            }
+            .into()),
+        }
+    }
+}
+
+/// 将输入文本转换为消息，`@all` 转换为 mention_all，`@user_id` 转换为 mention
+fn parse_message(line: &str) -> Segments {
+    let mut segments: Segments = vec![];
+    let mut text = String::new();
+    for word in line.split_inclusive(char::is_whitespace) {
+        let trimmed = word.trim_end();
+        match trimmed.strip_prefix('@') {
+            Some(id) if !id.is_empty() => {
+                if !text.is_empty() {
+                    segments.push(std::mem::take(&mut text).into());
+                }
+                segments.push(match id {
+                    "all" => MessageSegment::from(MentionAll {}),
+                    id => Mention {
+                        user_id: id.to_string(),
+                    }
+                    .into(),
+                });
+                text.push_str(&word[trimmed.len()..]);
+            }
+            _ => text.push_str(word),
+        }
+    }
+    if !text.is_empty() {
+        segments.push(text.into());
+    }
+    segments
+}
+
+#[async_trait]
+impl SelfIds for ConsolePlatform {
+    async fn self_ids(&self) -> Vec<String> {
+        vec![self.console.self_id.clone()]
+    }
+}
+
+impl GetStatus for ConsolePlatform {
+    fn get_status(&self) -> Status {
+        Status {
+            good: true,
+            online: true,
+        }
+    }
+}
+
+#[async_trait]
+impl ActionHandler<Event, Action, Resp> for ConsolePlatform {
+    type Config = ();
+    async fn start<AH, EH>(
+        &self,
+        ob: &Arc<OneBot<AH, EH>>,
+        _: (),
+    ) -> WalleResult<Vec<JoinHandle<()>>>
+    where
+        AH: ActionHandler<Event, Action, Resp> + Send + Sync + 'static,
+        EH: EventHandler<Event, Action, Resp> + Send + Sync + 'static,
+    {
+        let event_rx = self.event_rx.clone();
+        let input = self.input.clone();
+        let mut signal_rx = ob.get_signal_rx()?;
+        let mut input_signal_rx = ob.get_signal_rx()?;
+        let console = self.console.clone();
+        let ob_ = ob.clone();
+        Ok(vec![
+            ob.spawn(async move {
+                let mut event_rx = event_rx.lock().await;
+                loop {
+                    tokio::select! {
+                        _ = signal_rx.recv() => break,
+                        Some(event) = event_rx.recv() => {
+                            ob_.handle_event(event).await.ok();
+                        }
+                    }
+                }
+            }),
+            ob.spawn(async move {
+                let mut lines = input.lock().await;
+                loop {
+                    tokio::select! {
+                        _ = input_signal_rx.recv() => break,
+                        line = lines.next_line() => match line {
+                            Ok(Some(line)) => console.input(&line),
+                            _ => break,
+                        },
+                    }
+                }
+            }),
+        ])
+    }
+    async fn call(&self, action: Action) -> WalleResult<Resp> {
+        let name = action.action.clone();
+        let resp = match ConsoleAction::try_from(action) {
+            Ok(action) => self.console.handle(action),
+            Err(WalleError::DeclareNotMatch(_, _)) => Err(resp_error::unsupported_action(name)),
+            Err(e) => Err(resp_error::bad_param(e)),
+        };
+        Ok(resp.map_or_else(Resp::from, Resp::from))
+    }
+}
+
+impl ConsoleOneBot {
+    /// 构造控制台实现端，启动时传入 `((), ImplConfig)`
+    pub fn console(self_id: &str, platform: &str) -> Self {
+        let console = ConsolePlatform::new(self_id, platform);
+        let implt = console.console.implt.clone();
+        OneBot::new(console, ImplOBC::new(implt, platform.to_string()))
+    }
+}
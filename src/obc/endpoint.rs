@@ -0,0 +1,233 @@
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use tokio::sync::broadcast;
+
+use crate::{
+    config::Scope,
+    util::{ContentType, Transport},
+};
+#[cfg(any(feature = "http", feature = "websocket"))]
+use crate::{error::WalleResult, OneBot};
+#[cfg(any(feature = "http", feature = "websocket"))]
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+/// 通讯端点，即一个服务器、反向 WebSocket 连接目标或 Webhook 推送目标
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EndpointInfo {
+    pub id: u64,
+    pub transport: Transport,
+    /// 监听地址或连接目标
+    pub addr: String,
+}
+
+/// 活动连接
+///
+/// 应用端 Http 端点以其 Bot 的连接列出，没有 peer；
+/// 其余 HTTP 请求与 Webhook 推送不维持连接，不会出现在列表中
+#[derive(Debug, Clone)]
+pub struct ConnInfo {
+    pub conn_id: u64,
+    /// 建立该连接的端点
+    pub endpoint_id: u64,
+    pub transport: Transport,
+    pub peer: Option<SocketAddr>,
+    pub content_type: ContentType,
+    /// 连接上的 Bot
+    pub self_ids: Vec<String>,
+    pub connected_at: Instant,
+}
+
+impl ConnInfo {
+    pub fn uptime(&self) -> Duration {
+        self.connected_at.elapsed()
+    }
+}
+
+/// 端点与连接登记表
+#[derive(Default)]
+pub(crate) struct Registry {
+    #[cfg(any(feature = "http", feature = "websocket"))]
+    next_id: AtomicU64,
+    endpoints: DashMap<u64, (EndpointInfo, broadcast::Sender<()>)>,
+    conns: DashMap<u64, (ConnInfo, Scope)>,
+}
+
+impl Registry {
+    /// 登记端点，返回的 Endpoint 释放时注销
+    #[cfg(any(feature = "http", feature = "websocket"))]
+    pub(crate) fn add_endpoint<AH, EH>(
+        self: &Arc<Self>,
+        ob: &OneBot<AH, EH>,
+        transport: Transport,
+        addr: String,
+    ) -> WalleResult<Endpoint> {
+        let signal = ob.get_signal_rx()?;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, endpoint) = broadcast::channel(1);
+        let info = EndpointInfo {
+            id,
+            transport,
+            addr,
+        };
+        self.endpoints.insert(id, (info, tx));
+        Ok(Endpoint {
+            id,
+            registry: self.clone(),
+            stop: Stop { signal, endpoint },
+        })
+    }
+
+    /// 移除端点，其任务与连接随即停止
+    pub(crate) fn remove_endpoint(&self, id: u64) -> bool {
+        self.endpoints.remove(&id).is_some()
+    }
+
+    pub(crate) fn endpoints(&self) -> Vec<EndpointInfo> {
+        let mut endpoints: Vec<_> = self.endpoints.iter().map(|e| e.0.clone()).collect();
+        endpoints.sort_by_key(|e| e.id);
+        endpoints
+    }
+
+    /// 连接及其 access_token 权限范围
+    pub(crate) fn conns(&self) -> Vec<(ConnInfo, Scope)> {
+        let mut conns: Vec<_> = self.conns.iter().map(|c| c.value().clone()).collect();
+        conns.sort_by_key(|c| c.0.conn_id);
+        conns
+    }
+}
+
+/// 停止信号，OneBot 关闭或所属端点被移除时触发
+#[cfg(any(feature = "http", feature = "websocket"))]
+pub(crate) struct Stop {
+    signal: broadcast::Receiver<()>,
+    endpoint: broadcast::Receiver<()>,
+}
+
+#[cfg(any(feature = "http", feature = "websocket"))]
+impl Stop {
+    pub(crate) async fn recv(&mut self) {
+        tokio::select! {
+            _ = self.signal.recv() => {}
+            _ = self.endpoint.recv() => {}
+        }
+    }
+
+    pub(crate) fn resubscribe(&self) -> Self {
+        Self {
+            signal: self.signal.resubscribe(),
+            endpoint: self.endpoint.resubscribe(),
+        }
+    }
+}
+
+/// 由端点任务持有，释放时注销端点并停止其全部连接
+#[cfg(any(feature = "http", feature = "websocket"))]
+pub(crate) struct Endpoint {
+    pub(crate) id: u64,
+    registry: Arc<Registry>,
+    pub(crate) stop: Stop,
+}
+
+#[cfg(any(feature = "http", feature = "websocket"))]
+impl Endpoint {
+    /// 供端点建立的连接使用
+    #[cfg(any(feature = "websocket", all(feature = "app-obc", feature = "http")))]
+    pub(crate) fn conn_ctx(&self) -> ConnCtx {
+        ConnCtx {
+            endpoint_id: self.id,
+            registry: self.registry.clone(),
+            #[cfg(feature = "websocket")]
+            stop: self.stop.resubscribe(),
+        }
+    }
+}
+
+#[cfg(any(feature = "http", feature = "websocket"))]
+impl Drop for Endpoint {
+    fn drop(&mut self) {
+        self.registry.remove_endpoint(self.id);
+    }
+}
+
+#[cfg(any(feature = "websocket", all(feature = "app-obc", feature = "http")))]
+pub(crate) struct ConnCtx {
+    endpoint_id: u64,
+    registry: Arc<Registry>,
+    #[cfg(feature = "websocket")]
+    pub(crate) stop: Stop,
+}
+
+#[cfg(any(feature = "websocket", all(feature = "app-obc", feature = "http")))]
+impl ConnCtx {
+    /// 登记连接，返回的 Conn 释放时注销
+    pub(crate) fn open(
+        &self,
+        conn_id: u64,
+        transport: Transport,
+        peer: Option<SocketAddr>,
+        content_type: ContentType,
+        scope: Scope,
+    ) -> Conn {
+        let info = ConnInfo {
+            conn_id,
+            endpoint_id: self.endpoint_id,
+            transport,
+            peer,
+            content_type,
+            self_ids: vec![],
+            connected_at: Instant::now(),
+        };
+        self.registry.conns.insert(conn_id, (info, scope));
+        Conn {
+            id: conn_id,
+            registry: self.registry.clone(),
+        }
+    }
+}
+
+#[cfg(any(feature = "websocket", all(feature = "app-obc", feature = "http")))]
+pub(crate) struct Conn {
+    id: u64,
+    registry: Arc<Registry>,
+}
+
+#[cfg(any(feature = "websocket", all(feature = "app-obc", feature = "http")))]
+impl Conn {
+    fn update(&self, f: impl FnOnce(&mut ConnInfo)) {
+        if let Some(mut conn) = self.registry.conns.get_mut(&self.id) {
+            f(&mut conn.0)
+        }
+    }
+
+    #[cfg(feature = "websocket")]
+    pub(crate) fn set_content_type(&self, content_type: ContentType) {
+        self.update(|c| c.content_type = content_type)
+    }
+
+    /// 忽略 self_id 为空的 meta 事件来源
+    #[cfg(feature = "app-obc")]
+    pub(crate) fn add_bot(&self, self_id: &str) {
+        if self_id.is_empty() {
+            return;
+        }
+        self.update(|c| {
+            if !c.self_ids.iter().any(|id| id == self_id) {
+                c.self_ids.push(self_id.to_string())
+            }
+        })
+    }
+}
+
+#[cfg(any(feature = "websocket", all(feature = "app-obc", feature = "http")))]
+impl Drop for Conn {
+    fn drop(&mut self) {
+        self.registry.conns.remove(&self.id);
+    }
+}
@@ -0,0 +1,883 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex, MutexGuard,
+};
+
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use super::ImplOBC;
+use crate::{
+    action::*,
+    error::WalleError,
+    event::*,
+    resp::{resp_error, Resp, RespError},
+    segment::{alt, IntoMessage, Segments},
+    structs::{
+        ChannelInfo, File, FileId, GroupInfo, GuildInfo, SendMessageResp, Status, UserInfo, Version,
+    },
+    util::{new_uuid, timestamp_nano_f64, PushToValueMap, SelfIds, Value},
+    value_map, ActionHandler, EventHandler, GetStatus, OneBot, WalleResult,
+};
+use walle_macro::_OneBot as OneBot;
+
+/// 以 FakePlatform 为 ActionHandler 的实现端
+pub type FakeOneBot = OneBot<FakePlatform, ImplOBC<Event>>;
+
+/// 虚拟平台群组
+#[derive(Debug, Clone, Default)]
+pub struct FakeGroup {
+    pub group_name: String,
+    pub members: BTreeSet<String>,
+}
+
+/// 虚拟平台频道组
+#[derive(Debug, Clone, Default)]
+pub struct FakeGuild {
+    pub guild_name: String,
+    pub members: BTreeSet<String>,
+    pub channels: BTreeMap<String, String>, // channel_id -> channel_name
+}
+
+/// 虚拟平台消息
+#[derive(Debug, Clone)]
+pub struct FakeMessage {
+    pub detail_type: String,
+    pub user_id: String,         // 发送者
+    pub peer_id: Option<String>, // 私聊对象
+    pub group_id: Option<String>,
+    pub guild_id: Option<String>,
+    pub channel_id: Option<String>,
+    pub message: Segments,
+    pub time: f64,
+}
+
+/// 虚拟平台文件
+#[derive(Debug, Clone)]
+pub struct FakeFile {
+    pub name: String,
+    pub data: Vec<u8>,
+    pub sha256: Option<String>,
+}
+
+/// 虚拟平台的全部状态
+#[derive(Debug, Default)]
+pub struct FakeState {
+    pub users: HashMap<String, String>, // user_id -> nickname
+    pub friends: BTreeSet<String>,
+    pub groups: BTreeMap<String, FakeGroup>,
+    pub guilds: BTreeMap<String, FakeGuild>,
+    pub messages: HashMap<String, FakeMessage>,
+    pub files: HashMap<String, FakeFile>,
+}
+
+/// 平台支持的标准动作
+#[derive(Debug, OneBot)]
+#[action]
+enum FakeAction {
+    GetSelfInfo,
+    GetUserInfo(GetUserInfo),
+    GetFriendList,
+    SendMessage(SendMessage),
+    DeleteMessage(DeleteMessage),
+    GetGroupInfo(GetGroupInfo),
+    GetGroupList,
+    GetGroupMemberInfo(GetGroupMemberInfo),
+    GetGroupMemberList(GetGroupMemberList),
+    SetGroupName(SetGroupName),
+    LeaveGroup(LeaveGroup),
+    GetGuildInfo(GetGuildInfo),
+    GetGuildList,
+    SetGuildName(SetGuildName),
+    GetGuildMemberInfo(GetGuildMemberInfo),
+    GetGuildMemberList(GetGuildMemberList),
+    LeaveGuild(LeaveGuild),
+    GetChannelInfo(GetChannelInfo),
+    GetChannelList(GetChannelList),
+    SetChannelName(SetChannelName),
+    UploadFile(UploadFile),
+    GetFile(GetFile),
+    GetStatus,
+    GetVersion,
+}
+
+/// 有状态的内存虚拟聊天平台，用于离线端到端测试
+///
+/// 标准动作会真实修改平台状态，并产生对应的通知事件；
+/// 测试可通过 `add_*`、`receive_*` 等方法模拟其他用户的行为
+pub struct FakePlatform {
+    pub self_id: String,
+    pub implt: String,
+    pub platform: String,
+    state: Mutex<FakeState>,
+    file_dir: Mutex<Option<PathBuf>>,
+    seq: AtomicU64,
+    event_tx: mpsc::UnboundedSender<Event>,
+    event_rx: Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<Event>>>,
+}
+
+type FakeResult = Result<Value, RespError>;
+
+fn not_found(what: &str, id: &str) -> RespError {
+    resp_error::bad_param(format!("{} {} not found", what, id))
+}
+
+impl FakePlatform {
+    pub fn new(self_id: &str, platform: &str) -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let mut state = FakeState::default();
+        state.users.insert(self_id.to_string(), self_id.to_string());
+        Self {
+            self_id: self_id.to_string(),
+            implt: "walle-fake".to_string(),
+            platform: platform.to_string(),
+            state: Mutex::new(state),
+            file_dir: Mutex::default(),
+            seq: AtomicU64::new(1),
+            event_tx,
+            event_rx: Arc::new(tokio::sync::Mutex::new(event_rx)),
+        }
+    }
+
+    /// 直接读写平台状态，不产生事件
+    pub fn state(&self) -> MutexGuard<'_, FakeState> {
+        self.state.lock().unwrap()
+    }
+
+    /// 设置文件目录：`path` 类型的上传只能读取该目录内的文件，
+    /// `path` 与 `url` 类型的下载会将文件写入该目录；未设置时均不支持
+    pub fn set_file_dir<P: Into<PathBuf>>(&self, dir: P) {
+        *self.file_dir.lock().unwrap() = Some(dir.into());
+    }
+
+    fn file_dir(&self, ty: &str) -> Result<PathBuf, RespError> {
+        self.file_dir
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| resp_error::unsupported_param(format!("{} requires a file dir", ty)))
+    }
+
+    fn next_id(&self) -> String {
+        self.seq.fetch_add(1, Ordering::Relaxed).to_string()
+    }
+
+    fn push_event<T, D>(&self, ty: T, detail_type: D, extra: crate::util::ValueMap)
+    where
+        T: TypeDeclare + PushToValueMap,
+        D: DetailTypeDeclare + PushToValueMap,
+    {
+        let mut event: Event = BaseEvent {
+            id: new_uuid(),
+            self_id: self.self_id.clone(),
+            time: timestamp_nano_f64(),
+            implt: (),
+            platform: (),
+            ty,
+            detail_type,
+            sub_type: (),
+            extra,
+        }
+        .into();
+        event.implt = self.implt.clone();
+        event.platform = self.platform.clone();
+        self.event_tx.send(event).ok();
+    }
+
+    fn notice<D>(&self, detail_type: D)
+    where
+        D: DetailTypeDeclare + PushToValueMap,
+    {
+        self.push_event(Notice {}, detail_type, Default::default())
+    }
+
+    fn store_message(&self, message: FakeMessage) -> String {
+        let message_id = self.next_id();
+        self.state().messages.insert(message_id.clone(), message);
+        message_id
+    }
+
+    /// 添加用户，不产生事件
+    pub fn add_user(&self, user_id: &str, nickname: &str) {
+        self.state()
+            .users
+            .insert(user_id.to_string(), nickname.to_string());
+    }
+
+    /// 添加好友，产生 friend_increase 事件
+    pub fn add_friend(&self, user_id: &str, nickname: &str) {
+        {
+            let mut state = self.state();
+            state
+                .users
+                .entry(user_id.to_string())
+                .or_insert_with(|| nickname.to_string());
+            state.friends.insert(user_id.to_string());
+        }
+        self.notice(FriendIncrease {
+            user_id: user_id.to_string(),
+        });
+    }
+
+    /// 删除好友，产生 friend_decrease 事件
+    pub fn remove_friend(&self, user_id: &str) {
+        if self.state().friends.remove(user_id) {
+            self.notice(FriendDecrease {
+                user_id: user_id.to_string(),
+            });
+        }
+    }
+
+    /// 添加 Bot 所在的群组，不产生事件
+    pub fn add_group(&self, group_id: &str, group_name: &str) {
+        let mut state = self.state();
+        let group = state.groups.entry(group_id.to_string()).or_default();
+        group.group_name = group_name.to_string();
+        group.members.insert(self.self_id.clone());
+    }
+
+    /// 用户加入群组，产生 group_member_increase 事件
+    pub fn join_group(&self, group_id: &str, user_id: &str, operator_id: &str) {
+        {
+            let mut state = self.state();
+            state
+                .users
+                .entry(user_id.to_string())
+                .or_insert_with(|| user_id.to_string());
+            let group = state.groups.entry(group_id.to_string()).or_default();
+            if !group.members.insert(user_id.to_string()) {
+                return;
+            }
+        }
+        self.notice(GroupMemberIncrease {
+            group_id: group_id.to_string(),
+            user_id: user_id.to_string(),
+            operator_id: operator_id.to_string(),
+        });
+    }
+
+    /// 用户离开群组，产生 group_member_decrease 事件
+    pub fn remove_group_member(&self, group_id: &str, user_id: &str, operator_id: &str) {
+        let removed = self
+            .state()
+            .groups
+            .get_mut(group_id)
+            .map(|g| g.members.remove(user_id))
+            .unwrap_or_default();
+        if removed {
+            self.notice(GroupMemberDecrease {
+                group_id: group_id.to_string(),
+                user_id: user_id.to_string(),
+                operator_id: operator_id.to_string(),
+            });
+        }
+    }
+
+    /// 添加 Bot 所在的频道组，不产生事件
+    pub fn add_guild(&self, guild_id: &str, guild_name: &str) {
+        let mut state = self.state();
+        let guild = state.guilds.entry(guild_id.to_string()).or_default();
+        guild.guild_name = guild_name.to_string();
+        guild.members.insert(self.self_id.clone());
+    }
+
+    /// 用户加入频道组，产生 guild_member_increase 事件
+    pub fn join_guild(&self, guild_id: &str, user_id: &str, operator_id: &str) {
+        {
+            let mut state = self.state();
+            state
+                .users
+                .entry(user_id.to_string())
+                .or_insert_with(|| user_id.to_string());
+            let guild = state.guilds.entry(guild_id.to_string()).or_default();
+            if !guild.members.insert(user_id.to_string()) {
+                return;
+            }
+        }
+        self.notice(GuildMemberIncrease {
+            guild_id: guild_id.to_string(),
+            user_id: user_id.to_string(),
+            operator_id: operator_id.to_string(),
+        });
+    }
+
+    /// 用户离开频道组，产生 guild_member_decrease 事件
+    pub fn remove_guild_member(&self, guild_id: &str, user_id: &str, operator_id: &str) {
+        let removed = self
+            .state()
+            .guilds
+            .get_mut(guild_id)
+            .map(|g| g.members.remove(user_id))
+            .unwrap_or_default();
+        if removed {
+            self.notice(GuildMemberDecrease {
+                guild_id: guild_id.to_string(),
+                user_id: user_id.to_string(),
+                operator_id: operator_id.to_string(),
+            });
+        }
+    }
+
+    /// 新建子频道，产生 channel_create 事件
+    pub fn create_channel(
+        &self,
+        guild_id: &str,
+        channel_id: &str,
+        channel_name: &str,
+        operator_id: &str,
+    ) {
+        self.state()
+            .guilds
+            .entry(guild_id.to_string())
+            .or_default()
+            .channels
+            .insert(channel_id.to_string(), channel_name.to_string());
+        self.notice(ChannelCreate {
+            guild_id: guild_id.to_string(),
+            channel_id: channel_id.to_string(),
+            operator_id: operator_id.to_string(),
+        });
+    }
+
+    /// 删除子频道，产生 channel_delete 事件
+    pub fn delete_channel(&self, guild_id: &str, channel_id: &str, operator_id: &str) {
+        let removed = self
+            .state()
+            .guilds
+            .get_mut(guild_id)
+            .and_then(|g| g.channels.remove(channel_id))
+            .is_some();
+        if removed {
+            self.notice(ChannelDelete {
+                guild_id: guild_id.to_string(),
+                channel_id: channel_id.to_string(),
+                operator_id: operator_id.to_string(),
+            });
+        }
+    }
+
+    /// 收到私聊消息，产生 message.private 事件并返回 message_id
+    pub fn receive_private<M: IntoMessage>(&self, user_id: &str, message: M) -> String {
+        let message = message.into_message();
+        let message_id = self.store_message(FakeMessage {
+            detail_type: "private".to_string(),
+            user_id: user_id.to_string(),
+            peer_id: Some(self.self_id.clone()),
+            group_id: None,
+            guild_id: None,
+            channel_id: None,
+            message: message.clone(),
+            time: timestamp_nano_f64(),
+        });
+        self.push_event(
+            Message {
+                message_id: message_id.clone(),
+                alt_message: alt(&message),
+                message,
+                user_id: user_id.to_string(),
+            },
+            Private {},
+            Default::default(),
+        );
+        message_id
+    }
+
+    /// 收到群消息，产生 message.group 事件并返回 message_id
+    pub fn receive_group<M: IntoMessage>(
+        &self,
+        group_id: &str,
+        user_id: &str,
+        message: M,
+    ) -> String {
+        let message = message.into_message();
+        let message_id = self.store_message(FakeMessage {
+            detail_type: "group".to_string(),
+            user_id: user_id.to_string(),
+            peer_id: None,
+            group_id: Some(group_id.to_string()),
+            guild_id: None,
+            channel_id: None,
+            message: message.clone(),
+            time: timestamp_nano_f64(),
+        });
+        self.push_event(
+            Message {
+                message_id: message_id.clone(),
+                alt_message: alt(&message),
+                message,
+                user_id: user_id.to_string(),
+            },
+            Group {
+                group_id: group_id.to_string(),
+            },
+            Default::default(),
+        );
+        message_id
+    }
+
+    /// 收到子频道消息，产生 message.channel 事件并返回 message_id
+    pub fn receive_channel<M: IntoMessage>(
+        &self,
+        guild_id: &str,
+        channel_id: &str,
+        user_id: &str,
+        message: M,
+    ) -> String {
+        let message = message.into_message();
+        let message_id = self.store_message(FakeMessage {
+            detail_type: "channel".to_string(),
+            user_id: user_id.to_string(),
+            peer_id: None,
+            group_id: None,
+            guild_id: Some(guild_id.to_string()),
+            channel_id: Some(channel_id.to_string()),
+            message: message.clone(),
+            time: timestamp_nano_f64(),
+        });
+        self.event_tx
+            .send(Event {
+                id: new_uuid(),
+                implt: self.implt.clone(),
+                platform: self.platform.clone(),
+                self_id: self.self_id.clone(),
+                time: timestamp_nano_f64(),
+                ty: "message".to_string(),
+                detail_type: "channel".to_string(),
+                sub_type: String::default(),
+                extra: value_map! {
+                    "message_id": message_id.clone(),
+                    "alt_message": alt(&message),
+                    "message": message,
+                    "user_id": user_id,
+                    "guild_id": guild_id,
+                    "channel_id": channel_id
+                },
+            })
+            .ok();
+        message_id
+    }
+
+    fn user_info(&self, state: &FakeState, user_id: &str) -> FakeResult {
+        state
+            .users
+            .get(user_id)
+            .map(|nickname| {
+                UserInfo {
+                    user_id: user_id.to_string(),
+                    nickname: nickname.clone(),
+                }
+                .into()
+            })
+            .ok_or_else(|| not_found("user", user_id))
+    }
+
+    fn member_info<'a>(
+        &self,
+        state: &FakeState,
+        members: impl Iterator<Item = &'a String>,
+    ) -> FakeResult {
+        members
+            .map(|user_id| self.user_info(state, user_id))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Value::from)
+    }
+
+    fn send_message(&self, a: SendMessage) -> FakeResult {
+        let require = |v: Option<String>, name: &str| {
+            v.ok_or_else(|| resp_error::bad_param(format!("{} is required", name)))
+        };
+        let mut message = FakeMessage {
+            detail_type: a.detail_type.clone(),
+            user_id: self.self_id.clone(),
+            peer_id: None,
+            group_id: None,
+            guild_id: None,
+            channel_id: None,
+            message: a.message,
+            time: timestamp_nano_f64(),
+        };
+        {
+            let state = self.state();
+            match a.detail_type.as_str() {
+                "private" => {
+                    let user_id = require(a.user_id, "user_id")?;
+                    if !state.users.contains_key(&user_id) {
+                        return Err(not_found("user", &user_id));
+                    }
+                    message.peer_id = Some(user_id);
+                }
+                "group" => {
+                    let group_id = require(a.group_id, "group_id")?;
+                    match state.groups.get(&group_id) {
+                        Some(g) if g.members.contains(&self.self_id) => {}
+                        _ => return Err(not_found("group", &group_id)),
+                    }
+                    message.group_id = Some(group_id);
+                }
+                "channel" => {
+                    let guild_id = require(a.guild_id, "guild_id")?;
+                    let channel_id = require(a.channel_id, "channel_id")?;
+                    match state.guilds.get(&guild_id) {
+                        Some(g) if g.channels.contains_key(&channel_id) => {}
+                        _ => return Err(not_found("channel", &channel_id)),
+                    }
+                    message.guild_id = Some(guild_id);
+                    message.channel_id = Some(channel_id);
+                }
+                ty => return Err(resp_error::unsupported_param(ty)),
+            }
+        }
+        let time = message.time;
+        Ok(SendMessageResp {
+            message_id: self.store_message(message),
+            time,
+        }
+        .into())
+    }
+
+    fn delete_message(&self, message_id: String) -> FakeResult {
+        let m = self
+            .state()
+            .messages
+            .remove(&message_id)
+            .ok_or_else(|| not_found("message", &message_id))?;
+        let operator_id = self.self_id.clone();
+        match m.detail_type.as_str() {
+            "group" => self.notice(GroupMessageDelete {
+                group_id: m.group_id.unwrap_or_default(),
+                message_id,
+                user_id: m.user_id,
+                operator_id,
+            }),
+            "channel" => self.notice(ChannelMessageDelete {
+                guild_id: m.guild_id.unwrap_or_default(),
+                channel_id: m.channel_id.unwrap_or_default(),
+                user_id: m.user_id,
+                operator_id,
+                message_id,
+            }),
+            _ => self.notice(PrivateMessageDelete {
+                message_id,
+                user_id: m.user_id,
+            }),
+        }
+        Ok(Value::Null)
+    }
+
+    fn upload_file(&self, a: UploadFile) -> FakeResult {
+        let data = match a.ty.as_str() {
+            "data" => {
+                a.data
+                    .ok_or_else(|| resp_error::bad_param("data is required"))?
+                    .0
+            }
+            "path" => {
+                let dir = self
+                    .file_dir("path")?
+                    .canonicalize()
+                    .map_err(resp_error::filesystem_error)?;
+                let path = a
+                    .path
+                    .ok_or_else(|| resp_error::bad_param("path is required"))?;
+                // 相对路径基于文件目录，解析符号链接与 .. 后仍须位于其中
+                let path = dir
+                    .join(path)
+                    .canonicalize()
+                    .map_err(resp_error::filesystem_error)?;
+                if !path.starts_with(&dir) {
+                    return Err(resp_error::bad_param("path is outside the file dir"));
+                }
+                std::fs::read(path).map_err(resp_error::filesystem_error)?
+            }
+            ty => return Err(resp_error::unsupported_param(ty)),
+        };
+        let file_id = self.next_id();
+        self.state().files.insert(
+            file_id.clone(),
+            FakeFile {
+                name: a.name,
+                data,
+                sha256: a.sha256,
+            },
+        );
+        Ok(FileId { file_id }.into())
+    }
+
+    fn get_file(&self, a: GetFile) -> FakeResult {
+        let file = self
+            .state()
+            .files
+            .get(&a.file_id)
+            .cloned()
+            .ok_or_else(|| not_found("file", &a.file_id))?;
+        let mut resp = File {
+            name: file.name,
+            url: None,
+            headers: None,
+            path: None,
+            data: None,
+            sha256: file.sha256,
+        };
+        match a.ty.as_str() {
+            "data" => resp.data = Some(file.data),
+            "path" | "url" => {
+                let path = self.file_dir(&a.ty)?.join(&a.file_id);
+                std::fs::write(&path, &file.data).map_err(resp_error::filesystem_error)?;
+                let path = path
+                    .canonicalize()
+                    .map_err(resp_error::filesystem_error)?
+                    .to_string_lossy()
+                    .into_owned();
+                if a.ty == "url" {
+                    resp.url = Some(format!("file://{}", path));
+                } else {
+                    resp.path = Some(path);
+                }
+            }
+            ty => return Err(resp_error::unsupported_param(ty)),
+        }
+        Ok(resp.into())
+    }
+
+    fn handle(&self, action: FakeAction) -> FakeResult {
+        match action {
+            FakeAction::GetSelfInfo => self.user_info(&self.state(), &self.self_id),
+            FakeAction::GetUserInfo(a) => self.user_info(&self.state(), &a.user_id),
+            FakeAction::GetFriendList => {
+                let state = self.state();
+                self.member_info(&state, state.friends.iter())
+            }
+            FakeAction::SendMessage(a) => self.send_message(a),
+            FakeAction::DeleteMessage(a) => self.delete_message(a.message_id),
+            FakeAction::GetGroupInfo(a) => self
+                .state()
+                .groups
+                .get(&a.group_id)
+                .map(|g| {
+                    GroupInfo {
+                        group_id: a.group_id.clone(),
+                        group_name: g.group_name.clone(),
+                    }
+                    .into()
+                })
+                .ok_or_else(|| not_found("group", &a.group_id)),
+            FakeAction::GetGroupList => Ok(self
+                .state()
+                .groups
+                .iter()
+                .map(|(group_id, g)| GroupInfo {
+                    group_id: group_id.clone(),
+                    group_name: g.group_name.clone(),
+                })
+                .collect::<Vec<_>>()
+                .into()),
+            FakeAction::GetGroupMemberInfo(a) => {
+                let state = self.state();
+                match state.groups.get(&a.group_id) {
+                    Some(g) if g.members.contains(&a.user_id) => self.user_info(&state, &a.user_id),
+                    Some(_) => Err(not_found("member", &a.user_id)),
+                    None => Err(not_found("group", &a.group_id)),
+                }
+            }
+            FakeAction::GetGroupMemberList(a) => {
+                let state = self.state();
+                let group = state
+                    .groups
+                    .get(&a.group_id)
+                    .ok_or_else(|| not_found("group", &a.group_id))?;
+                self.member_info(&state, group.members.iter())
+            }
+            FakeAction::SetGroupName(a) => {
+                self.state()
+                    .groups
+                    .get_mut(&a.group_id)
+                    .ok_or_else(|| not_found("group", &a.group_id))?
+                    .group_name = a.group_name;
+                Ok(Value::Null)
+            }
+            FakeAction::LeaveGroup(a) => {
+                self.state()
+                    .groups
+                    .remove(&a.group_id)
+                    .ok_or_else(|| not_found("group", &a.group_id))?;
+                self.notice(GroupMemberDecrease {
+                    group_id: a.group_id,
+                    user_id: self.self_id.clone(),
+                    operator_id: self.self_id.clone(),
+                });
+                Ok(Value::Null)
+            }
+            FakeAction::GetGuildInfo(a) => self
+                .state()
+                .guilds
+                .get(&a.guild_id)
+                .map(|g| {
+                    GuildInfo {
+                        guild_id: a.guild_id.clone(),
+                        guild_name: g.guild_name.clone(),
+                    }
+                    .into()
+                })
+                .ok_or_else(|| not_found("guild", &a.guild_id)),
+            FakeAction::GetGuildList => Ok(self
+                .state()
+                .guilds
+                .iter()
+                .map(|(guild_id, g)| GuildInfo {
+                    guild_id: guild_id.clone(),
+                    guild_name: g.guild_name.clone(),
+                })
+                .collect::<Vec<_>>()
+                .into()),
+            FakeAction::SetGuildName(a) => {
+                self.state()
+                    .guilds
+                    .get_mut(&a.guild_id)
+                    .ok_or_else(|| not_found("guild", &a.guild_id))?
+                    .guild_name = a.guild_name;
+                Ok(Value::Null)
+            }
+            FakeAction::GetGuildMemberInfo(a) => {
+                let state = self.state();
+                match state.guilds.get(&a.guild_id) {
+                    Some(g) if g.members.contains(&a.user_id) => self.user_info(&state, &a.user_id),
+                    Some(_) => Err(not_found("member", &a.user_id)),
+                    None => Err(not_found("guild", &a.guild_id)),
+                }
+            }
+            FakeAction::GetGuildMemberList(a) => {
+                let state = self.state();
+                let guild = state
+                    .guilds
+                    .get(&a.guild_id)
+                    .ok_or_else(|| not_found("guild", &a.guild_id))?;
+                self.member_info(&state, guild.members.iter())
+            }
+            FakeAction::LeaveGuild(a) => {
+                self.state()
+                    .guilds
+                    .remove(&a.guild_id)
+                    .ok_or_else(|| not_found("guild", &a.guild_id))?;
+                self.notice(GuildMemberDecrease {
+                    guild_id: a.guild_id,
+                    user_id: self.self_id.clone(),
+                    operator_id: self.self_id.clone(),
+                });
+                Ok(Value::Null)
+            }
+            FakeAction::GetChannelInfo(a) => self
+                .state()
+                .guilds
+                .get(&a.guild_id)
+                .and_then(|g| g.channels.get(&a.channel_id))
+                .map(|name| {
+                    ChannelInfo {
+                        channel_id: a.channel_id.clone(),
+                        channel_name: name.clone(),
+                    }
+                    .into()
+                })
+                .ok_or_else(|| not_found("channel", &a.channel_id)),
+            FakeAction::GetChannelList(a) => self
+                .state()
+                .guilds
+                .get(&a.guild_id)
+                .map(|g| {
+                    g.channels
+                        .iter()
+                        .map(|(channel_id, name)| ChannelInfo {
+                            channel_id: channel_id.clone(),
+                            channel_name: name.clone(),
+                        })
+                        .collect::<Vec<_>>()
+                        .into()
+                })
+                .ok_or_else(|| not_found("guild", &a.guild_id)),
+            FakeAction::SetChannelName(a) => {
+                *self
+                    .state()
+                    .guilds
+                    .get_mut(&a.guild_id)
+                    .and_then(|g| g.channels.get_mut(&a.channel_id))
+                    .ok_or_else(|| not_found("channel", &a.channel_id))? = a.channel_name;
+                Ok(Value::Null)
+            }
+            FakeAction::UploadFile(a) => self.upload_file(a),
+            FakeAction::GetFile(a) => self.get_file(a),
+            FakeAction::GetStatus => Ok(self.get_status().into()),
+            FakeAction::GetVersion => Ok(Version {
+                implt: self.implt.clone(),
+                platform: self.platform.clone(),
+                version: crate::VERSION.to_string(),
+                onebot_version: "12".to_string(),
+            }
+            .into()),
+        }
+    }
+}
+
+#[async_trait]
+impl SelfIds for FakePlatform {
+    async fn self_ids(&self) -> Vec<String> {
+        vec![self.self_id.clone()]
+    }
+}
+
+impl GetStatus for FakePlatform {
+    fn get_status(&self) -> Status {
+        Status {
+            good: true,
+            online: true,
+        }
+    }
+}
+
+#[async_trait]
+impl ActionHandler<Event, Action, Resp> for FakePlatform {
+    type Config = ();
+    async fn start<AH, EH>(
+        &self,
+        ob: &Arc<OneBot<AH, EH>>,
+        _: (),
+    ) -> WalleResult<Vec<JoinHandle<()>>>
+    where
+        AH: ActionHandler<Event, Action, Resp> + Send + Sync + 'static,
+        EH: EventHandler<Event, Action, Resp> + Send + Sync + 'static,
+    {
+        let event_rx = self.event_rx.clone();
+        let mut signal_rx = ob.get_signal_rx()?;
+        let ob_ = ob.clone();
+        Ok(vec![ob.spawn(async move {
+            let mut event_rx = event_rx.lock().await;
+            loop {
+                tokio::select! {
+                    _ = signal_rx.recv() => break,
+                    Some(event) = event_rx.recv() => {
+                        ob_.handle_event(event).await.ok();
+                    }
+                }
+            }
+        })])
+    }
+    async fn call(&self, action: Action) -> WalleResult<Resp> {
+        let name = action.action.clone();
+        let resp = match FakeAction::try_from(action) {
+            Ok(action) => self.handle(action),
+            Err(WalleError::DeclareNotMatch(_, _)) => Err(resp_error::unsupported_action(name)),
+            Err(e) => Err(resp_error::bad_param(e)),
+        };
+        Ok(resp.map_or_else(Resp::from, Resp::from))
+    }
+}
+
+impl FakeOneBot {
+    /// 构造虚拟平台实现端，启动时传入 `((), ImplConfig)`
+    pub fn fake(self_id: &str, platform: &str) -> Self {
+        let fake = FakePlatform::new(self_id, platform);
+        let implt = fake.implt.clone();
+        OneBot::new(fake, ImplOBC::new(implt, platform.to_string()))
+    }
+}
@@ -1,21 +1,25 @@
 use std::{convert::Infallible, sync::Arc, time::Duration};
 
 use hyper::{
-    body::Buf,
-    client::HttpConnector,
-    header::{AUTHORIZATION, CONTENT_TYPE},
-    server::conn::Http,
-    service::service_fn,
-    Body, Client as HyperClient, Method, Request, Response, StatusCode,
+    header::CONTENT_TYPE, server::conn::Http, service::service_fn, Body, Client as HyperClient,
+    Method, Request, Response, StatusCode,
 };
-use tokio::{net::TcpListener, task::JoinHandle};
+use tokio::task::JoinHandle;
 use tracing::{info, trace, warn};
 
 use crate::{
     config::{HttpClient, HttpServer},
-    error::{WalleError, WalleResult},
+    error::WalleResult,
+    obc::{
+        filter_allow_event,
+        net::{hyper_client, Acceptor, Connector, Listener},
+        scope_allow_event, scope_check_action,
+    },
     resp::{resp_error, Resp},
-    util::{AuthReqHeaderExt, ContentType, Echo, ProtocolItem},
+    util::{
+        sign_body, timestamp_nano, verify_access_token, AuthReqHeaderExt, ContentType, Echo,
+        ProtocolItem, Transport, RECV_ECHO, SIGNATURE_HEADER, TIMESTAMP_HEADER,
+    },
     ActionHandler, EventHandler, OneBot,
 };
 
@@ -52,122 +56,133 @@ where
     pub(crate) async fn http<A, R, AH, EH>(
         &self,
         ob: &Arc<OneBot<AH, EH>>,
-        config: Vec<HttpServer>,
-        tasks: &mut Vec<JoinHandle<()>>,
-    ) -> WalleResult<()>
+        http: HttpServer,
+    ) -> WalleResult<(u64, JoinHandle<()>)>
     where
         A: ProtocolItem,
         R: ProtocolItem,
         AH: ActionHandler<E, A, R> + Send + Sync + 'static,
         EH: EventHandler<E, A, R> + Send + Sync + 'static,
     {
-        for http in config {
-            let ob_ = ob.clone();
-            let addr = std::net::SocketAddr::new(http.host, http.port);
-            info!(
-                target: crate::WALLE_CORE,
-                "Starting HTTP server on http://{}", addr
-            );
-            let access_token = http.access_token.clone();
-            let serv = service_fn(move |req: Request<Body>| {
-                let access_token = access_token.clone();
-                let ob = ob_.clone();
-                async move {
-                    if req.method() != Method::POST {
-                        return Ok::<Response<Body>, Infallible>(empty_error_response(405));
-                    }
-                    if req.uri() != "/" {
-                        return Ok(empty_error_response(404));
-                    }
-                    let content_type = match req
-                        .headers()
-                        .get(CONTENT_TYPE)
-                        .and_then(|v| v.to_str().ok())
-                        .and_then(ContentType::new)
-                    {
-                        Some(t) => t,
-                        None => return Ok(empty_error_response(415)),
-                    };
+        let ob_ = ob.clone();
+        let addr = std::net::SocketAddr::new(http.host, http.port);
+        let acceptor = Acceptor::new(&http.tls)?;
+        let listener = Listener::bind(addr, &http.unix).await?;
+        let url = listener.url(if acceptor.is_tls() { "https" } else { "http" });
+        info!(target: crate::WALLE_CORE, "Starting HTTP server on {}", url);
+        let mut endpoint = self.registry.add_endpoint(ob, Transport::Http, url)?;
+        let access_tokens = http.tokens();
+        let serv = service_fn(move |req: Request<Body>| {
+            let access_tokens = access_tokens.clone();
+            let ob = ob_.clone();
+            async move {
+                if req.method() != Method::POST {
+                    return Ok::<Response<Body>, Infallible>(empty_error_response(405));
+                }
+                if req.uri() != "/" {
+                    return Ok(empty_error_response(404));
+                }
+                let content_type = match req
+                    .headers()
+                    .get(CONTENT_TYPE)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(ContentType::new)
+                {
+                    Some(t) => t,
+                    None => return Ok(empty_error_response(415)),
+                };
 
-                    if let Some(ref token) = access_token {
-                        if let Some(header_token) = req
-                            .headers()
-                            .get(AUTHORIZATION)
-                            .and_then(|a| a.to_str().ok())
+                let scope = match verify_access_token(&access_tokens, req.headers(), req.uri()) {
+                    Ok(scope) => scope,
+                    Err(msg) => return Ok(error_response(403, msg)),
+                };
+                let data = hyper::body::to_bytes(req).await.unwrap();
+                if let Err(echo) = scope_check_action(&scope, &data, &content_type) {
+                    warn!(target: super::OBC, "action denied by access token scope");
+                    let resp: Resp =
+                        resp_error::bad_request("action not allowed by access token").into();
+                    return Ok(encode2resp(echo.pack(resp), &content_type));
+                }
+                let action: Result<Echo<A>, _> = match content_type {
+                    ContentType::Json => {
+                        ProtocolItem::json_decode(&String::from_utf8(data.to_vec()).unwrap())
+                    }
+                    ContentType::MsgPack => ProtocolItem::rmp_decode(&data),
+                };
+                match action {
+                    Ok(action) => {
+                        let (action, echo) = action.unpack();
+                        match RECV_ECHO
+                            .scope(echo.clone(), ob.handle_action(action))
+                            .await
                         {
-                            if header_token != format!("Bearer {}", token).as_str() {
-                                return Ok(error_response(403, "Authorization Header is invalid"));
+                            Ok(r) => Ok(encode2resp(echo.pack(r), &content_type)),
+                            Err(e) => {
+                                warn!(target: super::OBC, "handle action error: {}", e);
+                                Ok(encode2resp::<Resp>(
+                                    resp_error::bad_handler(e).into(),
+                                    &content_type,
+                                ))
                             }
-                        } else {
-                            return Ok(error_response(403, "Missing Authorization Header"));
                         }
                     }
-                    let data = hyper::body::to_bytes(req).await.unwrap();
-                    let action: Result<Echo<A>, _> = match content_type {
-                        ContentType::Json => {
-                            ProtocolItem::json_decode(&String::from_utf8(data.to_vec()).unwrap())
-                        }
-                        ContentType::MsgPack => ProtocolItem::rmp_decode(&data),
-                    };
-                    match action {
-                        Ok(action) => {
-                            let (action, echo) = action.unpack();
-                            match ob.handle_action(action).await {
-                                Ok(r) => Ok(encode2resp(echo.pack(r), &content_type)),
+                    Err(e) => Ok(encode2resp(
+                        if e.starts_with("missing field") {
+                            trace!(
+                                target: crate::WALLE_CORE,
+                                "Http call action miss field: {e}",
+                            );
+                            Resp::from(resp_error::bad_segment_data(e))
+                        } else {
+                            warn!(target: crate::WALLE_CORE, "Http call action ser error: {e}",);
+                            resp_error::unsupported_action(e).into()
+                        },
+                        &content_type,
+                    )),
+                }
+            }
+        });
+        let ob = ob.clone();
+        let id = endpoint.id;
+        let task = ob.clone().spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = endpoint.stop.recv() => break,
+                    Ok((stream, addr)) = listener.accept() => {
+                        let serv = serv.clone();
+                        let acceptor = acceptor.clone();
+                        let mut stop = endpoint.stop.resubscribe();
+                        ob.spawn(async move {
+                            let stream = match acceptor.accept(stream).await {
+                                Ok(stream) => stream,
                                 Err(e) => {
-                                    warn!(target: super::OBC, "handle action error: {}", e);
-                                    Ok(encode2resp::<Resp>(
-                                        resp_error::bad_handler(e).into(),
-                                        &content_type,
-                                    ))
+                                    warn!(target: super::OBC, "TLS handshake with {} failed: {}", addr, e);
+                                    return;
+                                }
+                            };
+                            let conn = Http::new().serve_connection(stream, serv);
+                            tokio::pin!(conn);
+                            tokio::select! {
+                                r = &mut conn => r.unwrap(), //Infallible
+                                _ = stop.recv() => {
+                                    // finish requests in progress, then close
+                                    conn.as_mut().graceful_shutdown();
+                                    conn.await.unwrap();
                                 }
                             }
-                        }
-                        Err(e) => Ok(encode2resp(
-                            if e.starts_with("missing field") {
-                                trace!(
-                                    target: crate::WALLE_CORE,
-                                    "Http call action miss field: {e}",
-                                );
-                                Resp::from(resp_error::bad_segment_data(e))
-                            } else {
-                                warn!(target: crate::WALLE_CORE, "Http call action ser error: {e}",);
-                                resp_error::unsupported_action(e).into()
-                            },
-                            &content_type,
-                        )),
-                    }
-                }
-            });
-            let ob = ob.clone();
-            let listener = TcpListener::bind(&addr).await.map_err(WalleError::from)?;
-            let mut signal_rx = ob.get_signal_rx()?;
-            tasks.push(tokio::spawn(async move {
-                loop {
-                    tokio::select! {
-                        _ = signal_rx.recv() => break,
-                        Ok((tcp_stream, _)) = listener.accept() => {
-                            let serv = serv.clone();
-                            tokio::spawn(async move {
-                                Http::new()
-                                    .serve_connection(tcp_stream, serv)
-                                    .await
-                                    .unwrap(); //Infallible
-                            });
-                        }
+                        });
                     }
                 }
-            }));
-        }
-        Ok(())
+            }
+        });
+        Ok((id, task))
     }
 
     pub(crate) async fn webhook<A, R, AH, EH>(
         &self,
         ob: &Arc<OneBot<AH, EH>>,
-        config: Vec<HttpClient>,
-        tasks: &mut Vec<JoinHandle<()>>,
-    ) -> WalleResult<()>
+        webhook: HttpClient,
+    ) -> WalleResult<(u64, JoinHandle<()>)>
     where
         E: ProtocolItem + Clone,
         A: ProtocolItem,
@@ -175,10 +190,12 @@ where
         AH: ActionHandler<E, A, R> + Send + Sync + 'static,
         EH: EventHandler<E, A, R> + Send + Sync + 'static,
     {
-        let client = Arc::new(HyperClient::new());
+        let client = hyper_client(&webhook)?;
+        let mut endpoint =
+            self.registry
+                .add_endpoint(ob, Transport::HttpWebhook, webhook.url.clone())?;
         let ob = ob.clone();
         let mut event_rx = self.event_tx.subscribe();
-        let mut signal_rx = ob.get_signal_rx()?;
         let self_id = ob
             .action_handler
             .self_ids()
@@ -188,34 +205,83 @@ where
             .unwrap_or_default();
         let r#impl = self.implt.clone();
         let platform = self.platform.clone();
-        tasks.push(tokio::spawn(async move {
+        let id = endpoint.id;
+        let task = ob.clone().spawn(async move {
             loop {
                 tokio::select! {
-                    _ = signal_rx.recv() => break,
+                    _ = endpoint.stop.recv() => {
+                        // push events produced before shutdown
+                        while let Ok(event) = event_rx.try_recv() {
+                            webhook_push(
+                                &ob,
+                                event,
+                                &self_id,
+                                &r#impl,
+                                &platform,
+                                &client,
+                                &webhook,
+                            ).await
+                        }
+                        break;
+                    }
                     Ok(event) = event_rx.recv() => webhook_push(
                         &ob,
                         event,
                         &self_id,
                         &r#impl,
                         &platform,
-                        &config,
-                        &client
+                        &client,
+                        &webhook,
                     ).await
                 }
             }
-        }));
-        Ok(())
+        });
+        Ok((id, task))
     }
 }
 
+/// 快速操作的执行结果，被拒绝或执行失败的操作以错误响应代替
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+enum QuickResult<R> {
+    Ok(R),
+    Err(Resp),
+}
+
+fn webhook_request(
+    webhook: &HttpClient,
+    r#impl: &str,
+    platform: &str,
+    self_id: &str,
+    content_type: &ContentType,
+    body: Vec<u8>,
+) -> Request<Body> {
+    let mut req = Request::builder()
+        .method(Method::POST)
+        .uri(&webhook.url)
+        .header(CONTENT_TYPE, content_type.to_string())
+        .header("X-OneBot-Version", 12.to_string())
+        .header("X-Impl", r#impl)
+        .header("X-Platform", platform)
+        .header("X-Self-ID", self_id)
+        .header_auth_token(&webhook.access_token);
+    if let Some(secret) = &webhook.secret {
+        let timestamp = (timestamp_nano() / 1_000_000_000) as u64;
+        req = req
+            .header(SIGNATURE_HEADER, sign_body(secret, timestamp, &body))
+            .header(TIMESTAMP_HEADER, timestamp.to_string());
+    }
+    req.body(body.into()).unwrap()
+}
+
 async fn webhook_push<E, A, R, AH, EH>(
     ob: &Arc<OneBot<AH, EH>>,
     event: E,
     self_id: &str,
     r#impl: &str,
     platform: &str,
-    config: &Vec<HttpClient>,
-    client: &Arc<HyperClient<HttpConnector, Body>>,
+    client: &HyperClient<Connector, Body>,
+    webhook: &HttpClient,
 ) where
     E: ProtocolItem,
     A: ProtocolItem,
@@ -223,53 +289,101 @@ async fn webhook_push<E, A, R, AH, EH>(
     AH: ActionHandler<E, A, R> + Send + Sync + 'static,
     EH: EventHandler<E, A, R> + Send + Sync + 'static,
 {
-    let date = event.json_encode();
-    for webhook in config {
-        let req = Request::builder()
-            .method(Method::POST)
-            .uri(&webhook.url)
-            .header(CONTENT_TYPE, "application/json")
-            .header("X-OneBot-Version", 12.to_string())
-            .header("X-Impl", r#impl.clone())
-            .header("X-Platform", platform.clone())
-            .header("X-Self-ID", self_id.clone())
-            .header_auth_token(&webhook.access_token)
-            .body(date.clone().into())
-            .unwrap();
-        let ob = ob.clone();
-        let client = client.clone();
-        let timeout = webhook.timeout;
-        tokio::spawn(async move {
-            let resp = match tokio::time::timeout(Duration::from_secs(timeout), client.request(req))
-                .await
-            {
-                Ok(Ok(r)) => r,
-                Ok(Err(e)) => {
-                    warn!(target: crate::WALLE_CORE, "{}", e);
-                    return;
+    if !scope_allow_event(&webhook.scope, &event) || !filter_allow_event(&webhook.filter, &event) {
+        return;
+    }
+    let content_type = webhook.content_type.unwrap_or(ContentType::Json);
+    let date = match content_type {
+        ContentType::Json => event.json_encode().into_bytes(),
+        ContentType::MsgPack => event.rmp_encode(),
+    };
+    let req = webhook_request(webhook, r#impl, platform, self_id, &content_type, date);
+    let ob = ob.clone();
+    let client = client.clone();
+    let webhook = webhook.clone();
+    let (r#impl, platform, self_id) = (
+        r#impl.to_string(),
+        platform.to_string(),
+        self_id.to_string(),
+    );
+    ob.clone().spawn(async move {
+        let timeout = Duration::from_secs(webhook.timeout);
+        let resp = match tokio::time::timeout(timeout, client.request(req)).await {
+            Ok(Ok(r)) => r,
+            Ok(Err(e)) => {
+                warn!(target: crate::WALLE_CORE, "{}", e);
+                return;
+            }
+            Err(_) => {
+                warn!(target: crate::WALLE_CORE, "push event timeout");
+                return;
+            }
+        };
+        match resp.status() {
+            StatusCode::NO_CONTENT => (),
+            StatusCode::OK => {
+                let content_type = resp
+                    .headers()
+                    .get(CONTENT_TYPE)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(ContentType::new)
+                    .unwrap_or(content_type);
+                let body = match hyper::body::to_bytes(resp.into_body()).await {
+                    Ok(body) if !body.is_empty() => body,
+                    Ok(_) => return,
+                    Err(e) => {
+                        warn!(target: super::OBC, "read webhook response failed: {}", e);
+                        return;
+                    }
+                };
+                let actions: Vec<Echo<A>> = match ProtocolItem::decode(&body, &content_type) {
+                    Ok(actions) => actions,
+                    Err(e) => {
+                        warn!(target: super::OBC, "bad webhook quick reply: {}", e);
+                        return;
+                    }
+                };
+                let mut results = vec![];
+                for action in actions {
+                    let data = action.json_encode();
+                    let (action, echo) = action.unpack();
+                    if scope_check_action(&webhook.scope, data.as_bytes(), &ContentType::Json)
+                        .is_err()
+                    {
+                        warn!(target: super::OBC, "action denied by access token scope");
+                        let resp = resp_error::bad_request("action not allowed by access token");
+                        results.push(echo.pack(QuickResult::Err(resp.into())));
+                        continue;
+                    }
+                    match RECV_ECHO
+                        .scope(echo.clone(), ob.handle_action(action))
+                        .await
+                    {
+                        Ok(r) => results.push(echo.pack(QuickResult::Ok(r))),
+                        Err(e) => {
+                            warn!(target: super::OBC, "handle action error: {}", e);
+                            results.push(
+                                echo.pack(QuickResult::Err(resp_error::bad_handler(e).into())),
+                            );
+                        }
+                    }
                 }
-                Err(_) => {
-                    warn!(target: crate::WALLE_CORE, "push event timeout");
+                if !webhook.report_results || results.is_empty() {
                     return;
                 }
-            };
-            match resp.status() {
-                StatusCode::NO_CONTENT => (),
-                StatusCode::OK => {
-                    let body = hyper::body::aggregate(resp).await.unwrap();
-                    let actions: Vec<A> = match serde_json::from_reader(body.reader()) {
-                        Ok(e) => e,
-                        Err(_) => {
-                            panic!()
-                            // handle error here
-                        }
-                    };
-                    for a in actions {
-                        let _ = ob.handle_action(a).await;
-                    }
+                let body = match content_type {
+                    ContentType::Json => results.json_encode().into_bytes(),
+                    ContentType::MsgPack => results.rmp_encode(),
+                };
+                let req =
+                    webhook_request(&webhook, &r#impl, &platform, &self_id, &content_type, body);
+                match tokio::time::timeout(timeout, client.request(req)).await {
+                    Ok(Ok(_)) => (),
+                    Ok(Err(e)) => warn!(target: super::OBC, "report results failed: {}", e),
+                    Err(_) => warn!(target: super::OBC, "report results timeout"),
                 }
-                x => info!("unhandle webhook push status: {}", x),
             }
-        });
-    }
+            x => info!("unhandle webhook push status: {}", x),
+        }
+    });
 }
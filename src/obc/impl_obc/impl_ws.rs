@@ -1,19 +1,32 @@
 use crate::{
-    error::{WalleError, WalleResult},
+    config::{Keepalive, Scope},
+    error::WalleResult,
+    filter::EventFilter,
+    lifecycle::Lifecycle,
     resp::{resp_error, Resp},
-    util::{AuthReqHeaderExt, Echo, ProtocolItem, ValueMap},
+    util::{
+        AuthReqHeaderExt, ContentType, Echo, ProtocolItem, Transport, ValueMap, RECV_CONN,
+        RECV_ECHO,
+    },
     ActionHandler, EventHandler, OneBot,
 };
 use crate::{
     event::Event,
     obc::{
-        ws_util::{try_connect, upgrade_websocket},
+        connect_event,
+        endpoint::ConnCtx,
+        filter_allow_event,
+        net::{Acceptor, Listener, Stream},
+        next_conn_id, scope_allow_event, scope_check_action,
+        ws_util::{
+            try_connect, upgrade_websocket, wait_reconnect, BackoffTimer, KeepaliveTick,
+            KeepaliveTimer, WsContentType,
+        },
         ImplOBC,
     },
 };
 use futures_util::{SinkExt, StreamExt};
 use std::{sync::Arc, time::Duration};
-use tokio::net::TcpStream;
 use tokio::sync::broadcast;
 use tokio::task::JoinHandle;
 use tokio_tungstenite::tungstenite::http::{header::USER_AGENT, Request};
@@ -28,120 +41,174 @@ where
     pub(crate) async fn ws<A, R, AH, EH>(
         &self,
         ob: &Arc<OneBot<AH, EH>>,
-        config: Vec<crate::config::WebSocketServer>,
-        tasks: &mut Vec<JoinHandle<()>>,
-    ) -> WalleResult<()>
+        wss: crate::config::WebSocketServer,
+    ) -> WalleResult<(u64, JoinHandle<()>)>
     where
         A: ProtocolItem,
         R: ProtocolItem,
         AH: ActionHandler<E, A, R> + Send + Sync + 'static,
         EH: EventHandler<E, A, R> + Send + Sync + 'static,
     {
-        for wss in config {
-            let addr = std::net::SocketAddr::new(wss.host, wss.port);
-            let tcp_listener = tokio::net::TcpListener::bind(&addr)
-                .await
-                .map_err(WalleError::from)?;
-            info!(
-                target: super::OBC,
-                "Websocket server listening on ws://{}", addr
-            );
-            let access_token = wss.access_token.clone();
-            let mut signal_rx = ob.get_signal_rx()?;
-            let event_rx = self.event_tx.subscribe();
-            let hb_rx = self.hb_tx.subscribe();
-            let ob = ob.clone();
-            tasks.push(tokio::spawn(async move {
-            loop { tokio::select! {
-                    Ok((stream, addr)) = tcp_listener.accept() => {
-                        if let Some(ws_stream) = upgrade_websocket(&access_token, stream).await {
-                            info!(target: super::OBC, "New websocket connection from {}", addr);
-                            tokio::spawn(ws_loop(
-                                ob.clone(),
-                                event_rx.resubscribe(),
-                                hb_rx.resubscribe(),
-                                ws_stream,
-                            ));
-                        }
+        let addr = std::net::SocketAddr::new(wss.host, wss.port);
+        let acceptor = Acceptor::new(&wss.tls)?;
+        let listener = Listener::bind(addr, &wss.unix).await?;
+        let url = listener.url(if acceptor.is_tls() { "wss" } else { "ws" });
+        info!(target: super::OBC, "Websocket server listening on {}", url);
+        let mut endpoint = self.registry.add_endpoint(ob, Transport::WebSocket, url)?;
+        let access_tokens = wss.tokens();
+        let keepalive = wss.keepalive;
+        let content_type = wss.content_type;
+        let filter = wss.filter;
+        let implt = self.implt.clone();
+        let platform = self.platform.clone();
+        let version = self.version.clone();
+        let event_rx = self.event_tx.subscribe();
+        let hb_rx = self.hb_tx.subscribe();
+        let ob = ob.clone();
+        let id = endpoint.id;
+        let task = ob.clone().spawn(async move {
+            loop {
+                tokio::select! {
+                    Ok((stream, addr)) = listener.accept() => {
+                        let acceptor = acceptor.clone();
+                        let access_tokens = access_tokens.clone();
+                        let ob_ = ob.clone();
+                        let event_rx = event_rx.resubscribe();
+                        let hb_rx = hb_rx.resubscribe();
+                        let keepalive = keepalive.clone();
+                        let filter = filter.clone();
+                        let connect = connect_event(&implt, &platform, &version);
+                        let ctx = endpoint.conn_ctx();
+                        let mut stop = endpoint.stop.resubscribe();
+                        // handshake in its own task, a slow peer must not block accepting
+                        ob.spawn(async move {
+                            let upgrade = tokio::select! {
+                                r = upgrade_websocket(&acceptor, &access_tokens, stream, &addr) => r,
+                                _ = stop.recv() => None,
+                            };
+                            if let Some((ws_stream, scope)) = upgrade {
+                                info!(target: super::OBC, "New websocket connection from {}", addr);
+                                ws_loop(
+                                    ob_,
+                                    event_rx,
+                                    hb_rx,
+                                    ws_stream,
+                                    Transport::WebSocket,
+                                    keepalive,
+                                    content_type,
+                                    scope,
+                                    filter,
+                                    connect,
+                                    ctx,
+                                )
+                                .await;
+                            }
+                        });
                     }
-                    _ = signal_rx.recv() => break,
-                }}
-            }));
-        }
-        Ok(())
+                    _ = endpoint.stop.recv() => break,
+                }
+            }
+        });
+        Ok((id, task))
     }
 
     pub(crate) async fn wsr<A, R, AH, EH>(
         &self,
         ob: &Arc<OneBot<AH, EH>>,
-        config: Vec<crate::config::WebSocketClient>,
-        tasks: &mut Vec<JoinHandle<()>>,
-    ) -> WalleResult<()>
+        wsr: crate::config::WebSocketClient,
+    ) -> WalleResult<(u64, JoinHandle<()>)>
     where
         A: ProtocolItem,
         R: ProtocolItem,
         AH: ActionHandler<E, A, R> + Send + Sync + 'static,
         EH: EventHandler<E, A, R> + Send + Sync + 'static,
     {
-        for wsr in config {
-            let platform = self.platform.clone();
-            let r#impl = self.implt.clone();
-            let event_rx = self.event_tx.subscribe();
-            let hb_rx = self.hb_tx.subscribe();
-            let mut signal_rx = ob.get_signal_rx()?;
-            let ob = ob.clone();
-            tasks.push(tokio::spawn(async move {
-                info!(target: super::OBC, "Start try connect to {}", wsr.url);
-                while signal_rx.try_recv().is_err() {
-                    let req = Request::builder()
-                        .header(
-                            USER_AGENT,
-                            format!("OneBot/{} ({}) Walle/{}", 12, platform, crate::VERSION),
-                        )
-                        .header("X-OneBot-Version", 12.to_string())
-                        .header("X-Platform", platform.clone())
-                        .header("X-Impl", r#impl.clone())
-                        .header(
-                            "X-Self-ID",
-                            ob.action_handler
-                                .self_ids()
-                                .await
-                                .first()
-                                .cloned()
-                                .unwrap_or_default(),
-                        )
-                        .header("X-Client-Role", "Universal".to_string()) // for v11
-                        .header_auth_token(&wsr.access_token);
-                    match try_connect(&wsr, req).await {
-                        Some(ws_stream) => {
-                            ws_loop(
-                                ob.clone(),
-                                event_rx.resubscribe(),
-                                hb_rx.resubscribe(),
-                                ws_stream,
-                            )
-                            .await;
-                            warn!(target: super::OBC, "Disconnected from {}", wsr.url);
-                        }
-                        None => {
-                            tokio::time::sleep(std::time::Duration::from_secs(
-                                wsr.reconnect_interval as u64,
-                            ))
-                            .await;
-                        }
-                    }
+        let platform = self.platform.clone();
+        let r#impl = self.implt.clone();
+        let event_rx = self.event_tx.subscribe();
+        let hb_rx = self.hb_tx.subscribe();
+        let hb_tx = self.hb_tx.clone();
+        let version = self.version.clone();
+        let mut endpoint =
+            self.registry
+                .add_endpoint(ob, Transport::WebSocketRev, wsr.url.clone())?;
+        let ob = ob.clone();
+        let id = endpoint.id;
+        let task = ob.clone().spawn(async move {
+            info!(target: super::OBC, "Start try connect to {}", wsr.url);
+            let backoff = wsr.backoff();
+            let mut timer = BackoffTimer::new(&backoff);
+            loop {
+                let req = Request::builder()
+                    .header(
+                        USER_AGENT,
+                        format!("OneBot/{} ({}) Walle/{}", 12, platform, crate::VERSION),
+                    )
+                    .header("X-OneBot-Version", 12.to_string())
+                    .header("X-Platform", platform.clone())
+                    .header("X-Impl", r#impl.clone())
+                    .header(
+                        "X-Self-ID",
+                        ob.action_handler
+                            .self_ids()
+                            .await
+                            .first()
+                            .cloned()
+                            .unwrap_or_default(),
+                    )
+                    .header("X-Client-Role", "Universal".to_string()) // for v11
+                    .header_auth_token(&wsr.access_token);
+                if let Some(ws_stream) = try_connect(&wsr, req).await {
+                    timer.reset();
+                    ws_loop(
+                        ob.clone(),
+                        event_rx.resubscribe(),
+                        hb_rx.resubscribe(),
+                        ws_stream,
+                        Transport::WebSocketRev,
+                        wsr.keepalive.clone(),
+                        wsr.content_type,
+                        wsr.scope.clone(),
+                        wsr.filter.clone(),
+                        connect_event(&r#impl, &platform, &version),
+                        endpoint.conn_ctx(),
+                    )
+                    .await;
+                    warn!(target: super::OBC, "Disconnected from {}", wsr.url);
                 }
-            }));
-        }
-        Ok(())
+                let on_meta = |event| {
+                    hb_tx.send(event).ok();
+                };
+                if !wait_reconnect(
+                    &wsr,
+                    &mut timer,
+                    &mut endpoint.stop,
+                    (&r#impl, &platform),
+                    on_meta,
+                )
+                .await
+                {
+                    break;
+                }
+            }
+        });
+        Ok((id, task))
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn ws_loop<E, A, R, AH, EH>(
     ob: Arc<OneBot<AH, EH>>,
     mut event_rx: broadcast::Receiver<E>,
     mut hb_rx: broadcast::Receiver<Event>,
-    mut ws_stream: WebSocketStream<TcpStream>,
+    mut ws_stream: WebSocketStream<Stream>,
+    transport: Transport,
+    keepalive: Keepalive,
+    content_type: Option<ContentType>,
+    scope: Scope,
+    filter: Option<EventFilter>,
+    connect: Event,
+    mut ctx: ConnCtx,
 ) where
     E: ProtocolItem + Clone,
     A: ProtocolItem,
@@ -149,19 +216,53 @@ async fn ws_loop<E, A, R, AH, EH>(
     AH: ActionHandler<E, A, R> + Send + Sync + 'static,
     EH: EventHandler<E, A, R> + Send + Sync + 'static,
 {
-    let (json_resp_tx, mut json_resp_rx) = tokio::sync::mpsc::unbounded_channel();
-    let (rmp_resp_tx, mut rmp_resp_rx) = tokio::sync::mpsc::unbounded_channel();
-    let mut signal_rx = ob.get_signal_rx().unwrap(); //todo
+    let (resp_tx, mut resp_rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut content_type = WsContentType::new(content_type);
+
+    // meta.connect must be the first frame of every connection
+    trace!(target: crate::WALLE_CORE, "ws send: {:?}", connect);
+    if ws_stream
+        .send(connect.to_ws_msg(&content_type.get()))
+        .await
+        .is_err()
+    {
+        warn!(target: super::OBC, "Send connect event failed, close connection");
+        return;
+    }
+    let conn_id = next_conn_id();
+    let peer = ws_stream.get_ref().peer_addr().ok();
+    let conn = ctx.open(conn_id, transport, peer, content_type.get(), scope.clone());
+    ob.handle_lifecycle(Lifecycle::Connect {
+        conn_id,
+        transport,
+        peer,
+    })
+    .await;
+    let mut keepalive = KeepaliveTimer::new(&keepalive);
+    let mut drain = false;
     loop {
         tokio::select! {
-            _ = signal_rx.recv() => break,
+            _ = ctx.stop.recv() => {
+                drain = true;
+                break;
+            }
+            tick = keepalive.tick() => match tick {
+                KeepaliveTick::Ping => if ws_stream.send(WsMsg::Ping(vec![])).await.is_err() {
+                    break;
+                },
+                KeepaliveTick::Timeout => {
+                    warn!(target: super::OBC, "Websocket peer not responding, close connection");
+                    break;
+                }
+            },
             event = event_rx.recv() => {
                 match event {
                     Ok(event) => {
-                        // event will always send as json
-                        let event = event.json_encode();
-                        trace!(target: crate::WALLE_CORE, "ws send: {}", event);
-                        if ws_stream.send(WsMsg::Text(event)).await.is_err() {
+                        if !scope_allow_event(&scope, &event) || !filter_allow_event(&filter, &event) {
+                            continue;
+                        }
+                        trace!(target: crate::WALLE_CORE, "ws send: {:?}", event);
+                        if ws_stream.send(event.to_ws_msg(&content_type.get())).await.is_err() {
                             // send failed, break loop and close connection
                             break;
                         }
@@ -175,9 +276,11 @@ async fn ws_loop<E, A, R, AH, EH>(
             hb = hb_rx.recv() => {
                 match hb {
                     Ok(hb) => {
-                        let hb = hb.json_encode();
-                        trace!(target: crate::WALLE_CORE, "ws send: {}", hb);
-                        if ws_stream.send(WsMsg::Text(hb)).await.is_err() {
+                        if !scope_allow_event(&scope, &hb) || !filter_allow_event(&filter, &hb) {
+                            continue;
+                        }
+                        trace!(target: crate::WALLE_CORE, "ws send: {:?}", hb);
+                        if ws_stream.send(hb.to_ws_msg(&content_type.get())).await.is_err() {
                             break;
                         }
                     }
@@ -188,45 +291,87 @@ async fn ws_loop<E, A, R, AH, EH>(
             }
             Some(ws_msg) = ws_stream.next() => {
                 trace!(target: crate::WALLE_CORE, "ws recv: {:?}", ws_msg);
+                keepalive.alive();
                 match ws_msg {
                     // handle action request
-                    Ok(ws_msg) => if ws_recv(
+                    Ok(ws_msg) => {
+                        if content_type.detect(&ws_msg) {
+                            conn.set_content_type(content_type.get());
+                        }
+                        if ws_recv(
                             ws_msg,
                             &ob,
                             &mut ws_stream,
-                            &json_resp_tx,
-                            &rmp_resp_tx
-                        ).await { break },
+                            &resp_tx,
+                            content_type.get(),
+                            &scope,
+                            conn_id,
+                        ).await {
+                            break;
+                        }
+                    }
                     Err(_) => break,
                 }
 
             },
-            Some(resp) = json_resp_rx.recv() => {
-                trace!(target: crate::WALLE_CORE, "ws send json: {:?}", resp);
+            Some(resp) = resp_rx.recv() => {
+                trace!(target: crate::WALLE_CORE, "ws send: {:?}", resp);
                 // send action response
-                if ws_stream.send(WsMsg::Text(resp.json_encode())).await.is_err() {
+                if ws_stream.send(resp.to_ws_msg(&content_type.get())).await.is_err() {
                     break;
                 }
-
-            },
-            Some(resp) = rmp_resp_rx.recv() => {
-                trace!(target: crate::WALLE_CORE, "ws send rmp: {:?}", resp);
-                // send action response
-                if ws_stream.send(WsMsg::Binary(resp.rmp_encode())).await.is_err() {
-                    break;
+            }
+        }
+    }
+    if drain {
+        // no more actions accepted, wait for running ones and flush queued events
+        drop(resp_tx);
+        let flush = async {
+            while let Some(resp) = resp_rx.recv().await {
+                if ws_stream
+                    .send(resp.to_ws_msg(&content_type.get()))
+                    .await
+                    .is_err()
+                {
+                    return;
                 }
             }
+            while let Ok(event) = event_rx.try_recv() {
+                if scope_allow_event(&scope, &event)
+                    && filter_allow_event(&filter, &event)
+                    && ws_stream
+                        .send(event.to_ws_msg(&content_type.get()))
+                        .await
+                        .is_err()
+                {
+                    return;
+                }
+            }
+        };
+        if tokio::time::timeout(ob.drain_timeout(), flush)
+            .await
+            .is_err()
+        {
+            warn!(target: super::OBC, "Websocket connection drain timeout");
         }
     }
     ws_stream.send(WsMsg::Close(None)).await.ok();
+    ob.handle_lifecycle(Lifecycle::Disconnect {
+        conn_id,
+        transport,
+        peer,
+    })
+    .await;
 }
 
 pub(crate) async fn ws_recv<E, A, R, AH, EH>(
     ws_msg: WsMsg,
     ob: &Arc<OneBot<AH, EH>>,
-    ws_stream: &mut WebSocketStream<TcpStream>,
-    json_resp_sender: &tokio::sync::mpsc::UnboundedSender<Echo<R>>,
-    rmp_resp_sender: &tokio::sync::mpsc::UnboundedSender<Echo<R>>,
+    ws_stream: &mut WebSocketStream<Stream>,
+    resp_sender: &tokio::sync::mpsc::UnboundedSender<Echo<R>>,
+    content_type: ContentType,
+    scope: &Scope,
+    conn_id: u64,
 ) -> bool
 where
     E: ProtocolItem,
@@ -245,15 +390,30 @@ where
         }
     };
 
+    let denied = match &ws_msg {
+        WsMsg::Text(text) => scope_check_action(scope, text.as_bytes(), &ContentType::Json),
+        WsMsg::Binary(v) => scope_check_action(scope, v, &ContentType::MsgPack),
+        _ => Ok(()),
+    };
+    if let Err(echo_s) = denied {
+        warn!(target: super::OBC, "action denied by access token scope");
+        let resp: Resp = resp_error::bad_request("action not allowed by access token").into();
+        return ws_stream
+            .send(echo_s.pack(resp).to_ws_msg(&content_type))
+            .await
+            .is_err();
+    }
+
     match ws_msg {
         WsMsg::Text(text) => match serde_json::from_str::<'_, Echo<A>>(&text) {
             Ok(action) => {
                 let (action, echos) = action.unpack();
-                let tx = json_resp_sender.clone();
+                let tx = resp_sender.clone();
                 let ob = ob.clone();
-                tokio::spawn(async move {
+                ob.clone().spawn(async move {
                     tokio::time::timeout(Duration::from_secs(10), async move {
-                        match ob.handle_action(action).await {
+                        let handle = RECV_ECHO.scope(echos.clone(), ob.handle_action(action));
+                        match RECV_CONN.scope(conn_id, handle).await {
                             Ok(r) => {
                                 tx.send(echos.pack(r)).ok();
                             }
@@ -266,8 +426,8 @@ where
             }
             Err(msg) => match serde_json::from_str(&text) {
                 Ok(a) => {
-                    let resp = serde_json::to_string(&err_handle(a, msg.to_string())).unwrap();
-                    if ws_stream.send(WsMsg::Text(resp)).await.is_err() {
+                    let resp = err_handle(a, msg.to_string());
+                    if ws_stream.send(resp.to_ws_msg(&content_type)).await.is_err() {
                         return true;
                     }
                 }
@@ -283,11 +443,12 @@ where
         WsMsg::Binary(v) => match rmp_serde::from_read::<_, Echo<A>>(v.as_slice()) {
             Ok(action) => {
                 let (action, echos) = action.unpack();
-                let tx = rmp_resp_sender.clone();
+                let tx = resp_sender.clone();
                 let ob = ob.clone();
-                tokio::spawn(async move {
+                ob.clone().spawn(async move {
                     tokio::time::timeout(Duration::from_secs(10), async move {
-                        match ob.handle_action(action).await {
+                        let handle = RECV_ECHO.scope(echos.clone(), ob.handle_action(action));
+                        match RECV_CONN.scope(conn_id, handle).await {
                             Ok(r) => {
                                 tx.send(echos.pack(r)).ok();
                             }
@@ -299,8 +460,8 @@ where
             }
             Err(msg) => match rmp_serde::from_read(v.as_slice()) {
                 Ok(a) => {
-                    let resp = rmp_serde::to_vec(&err_handle(a, msg.to_string())).unwrap();
-                    if ws_stream.send(WsMsg::Binary(resp)).await.is_err() {
+                    let resp = err_handle(a, msg.to_string());
+                    if ws_stream.send(resp.to_ws_msg(&content_type)).await.is_err() {
                         return true;
                     }
                 }
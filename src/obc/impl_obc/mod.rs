@@ -1,10 +1,12 @@
 use std::sync::Arc;
 
+use super::endpoint::{ConnInfo, EndpointInfo, Registry};
 use super::OBC;
+use crate::config::{ImplConfig, ImplEndpoint, Validate};
 use crate::event::Event;
 use crate::util::{ProtocolItem, SelfIds};
 use crate::{ActionHandler, EventHandler, OneBot};
-use crate::{GetStatus, WalleResult};
+use crate::{GetStatus, WalleError, WalleResult};
 use async_trait::async_trait;
 use tokio::sync::broadcast;
 use tokio::task::JoinHandle;
@@ -22,8 +24,10 @@ mod impl_ws;
 pub struct ImplOBC<E> {
     pub platform: String,
     pub implt: String,
+    pub version: String, // meta.connect 事件中的实现版本
     pub(crate) event_tx: tokio::sync::broadcast::Sender<E>,
     pub(crate) hb_tx: tokio::sync::broadcast::Sender<crate::event::Event>,
+    pub(crate) registry: Arc<Registry>,
 }
 
 #[async_trait]
@@ -33,36 +37,56 @@ where
     A: ProtocolItem,
     R: ProtocolItem,
 {
-    type Config = crate::config::ImplConfig;
+    type Config = ImplConfig;
     async fn start<AH, EH>(
         &self,
         ob: &Arc<OneBot<AH, EH>>,
-        config: crate::config::ImplConfig,
+        config: ImplConfig,
     ) -> WalleResult<Vec<JoinHandle<()>>>
     where
         AH: ActionHandler<E, A, R> + Send + Sync + 'static,
         EH: EventHandler<E, A, R> + Send + Sync + 'static,
     {
-        let mut tasks = vec![];
+        let mut endpoints = vec![];
         #[cfg(feature = "websocket")]
         {
-            self.ws(ob, config.websocket, &mut tasks).await?;
-            self.wsr(ob, config.websocket_rev, &mut tasks).await?;
+            endpoints.extend(config.websocket.into_iter().map(ImplEndpoint::WebSocket));
+            endpoints.extend(
+                config
+                    .websocket_rev
+                    .into_iter()
+                    .map(ImplEndpoint::WebSocketRev),
+            );
         }
         #[cfg(feature = "http")]
         {
-            self.http(ob, config.http, &mut tasks).await?;
-            self.webhook(ob, config.http_webhook, &mut tasks).await?;
+            endpoints.extend(config.http.into_iter().map(ImplEndpoint::Http));
+            endpoints.extend(
+                config
+                    .http_webhook
+                    .into_iter()
+                    .map(ImplEndpoint::HttpWebhook),
+            );
+        }
+        let mut tasks = vec![];
+        for endpoint in endpoints {
+            tasks.push(self.start_endpoint(ob, endpoint).await?.1);
         }
         if config.heartbeat.enabled {
             tasks.push(start_hb(
                 &ob,
                 self.implt.clone(),
                 self.platform.clone(),
-                config.heartbeat.interval,
+                config.heartbeat.interval(),
                 self.hb_tx.clone(),
             ))
         }
+        tasks.push(start_status_watch(
+            ob,
+            self.implt.clone(),
+            self.platform.clone(),
+            self.hb_tx.clone(),
+        ));
         Ok(tasks)
     }
     async fn call(&self, event: E) -> WalleResult<()> {
@@ -81,8 +105,93 @@ impl<E> ImplOBC<E> {
         Self {
             platform,
             implt: r#impl,
+            version: crate::VERSION.to_string(),
             event_tx,
             hb_tx,
+            registry: Arc::default(),
+        }
+    }
+
+    /// 停止并移除端点及其全部连接，端点不存在时返回 false
+    pub fn remove_endpoint(&self, id: u64) -> bool {
+        self.registry.remove_endpoint(id)
+    }
+
+    /// 运行中的端点，OneBot 关闭后全部移除
+    pub fn endpoints(&self) -> Vec<EndpointInfo> {
+        self.registry.endpoints()
+    }
+
+    /// 活动连接，self_ids 为连接权限范围内的 Bot
+    pub async fn connections<AH, EH>(&self, ob: &OneBot<AH, EH>) -> Vec<ConnInfo>
+    where
+        AH: SelfIds,
+    {
+        let self_ids = ob.action_handler.self_ids().await;
+        self.registry
+            .conns()
+            .into_iter()
+            .map(|(mut conn, scope)| {
+                conn.self_ids = self_ids
+                    .iter()
+                    .filter(|id| scope.allow_self_id(id))
+                    .cloned()
+                    .collect();
+                conn
+            })
+            .collect()
+    }
+}
+
+impl<E> ImplOBC<E>
+where
+    E: ProtocolItem + Clone,
+{
+    /// 在运行中的 OneBot 上添加端点，返回端点 id
+    pub async fn add_endpoint<A, R, AH, EH>(
+        &self,
+        ob: &Arc<OneBot<AH, EH>>,
+        endpoint: ImplEndpoint,
+    ) -> WalleResult<u64>
+    where
+        A: ProtocolItem,
+        R: ProtocolItem,
+        AH: ActionHandler<E, A, R> + Send + Sync + 'static,
+        EH: EventHandler<E, A, R> + Send + Sync + 'static,
+    {
+        endpoint.validate()?;
+        Ok(self.start_endpoint(ob, endpoint).await?.0)
+    }
+
+    #[cfg_attr(
+        not(any(feature = "http", feature = "websocket")),
+        allow(unused_variables)
+    )]
+    async fn start_endpoint<A, R, AH, EH>(
+        &self,
+        ob: &Arc<OneBot<AH, EH>>,
+        endpoint: ImplEndpoint,
+    ) -> WalleResult<(u64, JoinHandle<()>)>
+    where
+        A: ProtocolItem,
+        R: ProtocolItem,
+        AH: ActionHandler<E, A, R> + Send + Sync + 'static,
+        EH: EventHandler<E, A, R> + Send + Sync + 'static,
+    {
+        match endpoint {
+            #[cfg(feature = "websocket")]
+            ImplEndpoint::WebSocket(ws) => self.ws(ob, ws).await,
+            #[cfg(feature = "websocket")]
+            ImplEndpoint::WebSocketRev(wsr) => self.wsr(ob, wsr).await,
+            #[cfg(feature = "http")]
+            ImplEndpoint::Http(http) => self.http(ob, http).await,
+            #[cfg(feature = "http")]
+            ImplEndpoint::HttpWebhook(webhook) => self.webhook(ob, webhook).await,
+            #[allow(unreachable_patterns)]
+            endpoint => Err(WalleError::Other(format!(
+                "{} is not enabled",
+                endpoint.transport()
+            ))),
         }
     }
 }
@@ -97,7 +206,7 @@ fn build_hb<AH, EH>(
 where
     AH: GetStatus,
 {
-    let status = ob.action_handler.get_status();
+    let status = ob.action_handler.get_bot_status(self_id);
     crate::event::Event {
         id: crate::util::new_uuid(),
         implt: implt.to_string(),
@@ -125,28 +234,71 @@ where
     AH: GetStatus + SelfIds + Send + Sync + 'static,
     EH: Send + Sync + 'static,
 {
-    let hb_tx = Arc::new(hb_tx);
     let mut signal = ob.get_signal_rx().unwrap();
     let ob = ob.clone();
-    let mut self_ids = vec![];
-    tokio::spawn(async move {
+    ob.clone().spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(interval as u64));
         loop {
-            if self_ids.is_empty() {
-                self_ids = ob.action_handler.self_ids().await;
+            tokio::select! {
+                _ = signal.recv() => break,
+                _ = ticker.tick() => {
+                    // fetch self_ids every tick to pick up bots added at runtime
+                    for self_id in ob.action_handler.self_ids().await {
+                        hb_tx
+                            .send(build_hb(&ob, &self_id, &implt, &platform, interval))
+                            .ok();
+                    }
+                }
             }
-            if let Ok(_) = signal.try_recv() {
-                break;
+        }
+    })
+}
+
+/// 全部 Bot 的状态
+async fn bots_status<AH, EH>(ob: &OneBot<AH, EH>, platform: &str) -> crate::structs::BotsStatus
+where
+    AH: GetStatus + SelfIds,
+{
+    let mut bots = vec![];
+    for self_id in ob.action_handler.self_ids().await {
+        let online = ob.action_handler.get_bot_status(&self_id).online;
+        bots.push(super::bot_status(platform, &self_id, online));
+    }
+    crate::structs::BotsStatus {
+        good: ob.action_handler.get_status().good,
+        bots,
+    }
+}
+
+/// 监视 ActionHandler 状态，变化时发送 meta.status_update 事件
+fn start_status_watch<AH, EH>(
+    ob: &Arc<OneBot<AH, EH>>,
+    implt: String,
+    platform: String,
+    hb_tx: broadcast::Sender<Event>,
+) -> JoinHandle<()>
+where
+    AH: GetStatus + SelfIds + Send + Sync + 'static,
+    EH: Send + Sync + 'static,
+{
+    let mut signal = ob.get_signal_rx().unwrap();
+    let ob = ob.clone();
+    ob.clone().spawn(async move {
+        let mut status = bots_status(&ob, &platform).await;
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
+        loop {
+            tokio::select! {
+                _ = signal.recv() => break,
+                _ = interval.tick() => {
+                    let current = bots_status(&ob, &platform).await;
+                    if current != status {
+                        status = current.clone();
+                        hb_tx
+                            .send(super::status_update_event(&implt, &platform, current))
+                            .ok();
+                    }
+                }
             }
-            hb_tx
-                .send(build_hb(
-                    &ob,
-                    &self_ids.pop().unwrap_or_default(),
-                    &implt,
-                    &platform,
-                    interval,
-                ))
-                .ok();
-            tokio::time::sleep(std::time::Duration::from_secs(interval as u64)).await;
         }
     })
 }
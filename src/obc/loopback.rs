@@ -0,0 +1,202 @@
+use std::collections::HashSet;
+use std::sync::{atomic::Ordering, Arc};
+
+use tokio::sync::{broadcast::error::RecvError, mpsc};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use super::{
+    connect_event, downcast_event, next_conn_id, AppOBC, BotConn, BotMapExt, ImplOBC,
+    LifecycleNotifier, OBC,
+};
+use crate::{
+    error::WalleResult,
+    lifecycle::Lifecycle,
+    util::{Echo, ProtocolItem, SelfId, Transport, RECV_CONN, RECV_ECHO},
+    ActionHandler, EventHandler, OneBot,
+};
+
+/// 实现端发往应用端的数据
+enum LoopbackItem<E, R> {
+    Event(E),
+    Resp(Echo<R>),
+}
+
+/// 在同一进程内直连实现端与应用端，双方 OneBot 需已启动
+///
+/// Event 与 Action 以原类型传递，不经过序列化；任一端关闭时连接断开
+pub fn loopback<E, A, R, AH, EH>(
+    impl_ob: &Arc<OneBot<AH, ImplOBC<E>>>,
+    app_ob: &Arc<OneBot<AppOBC<A, R>, EH>>,
+) -> WalleResult<Vec<JoinHandle<()>>>
+where
+    E: ProtocolItem + SelfId + Clone,
+    A: ProtocolItem + SelfId + Clone,
+    R: ProtocolItem,
+    AH: ActionHandler<E, A, R> + Send + Sync + 'static,
+    EH: EventHandler<E, A, R> + Send + Sync + 'static,
+{
+    let (action_tx, action_rx) = mpsc::unbounded_channel();
+    let (item_tx, item_rx) = mpsc::unbounded_channel();
+    let impl_task = impl_loop(impl_ob, action_rx, item_tx)?;
+    let peer = (
+        impl_ob.event_handler.implt.clone(),
+        impl_ob.event_handler.platform.clone(),
+    );
+    let app_task = app_loop(app_ob, action_tx, item_rx, peer)?;
+    info!(target: OBC, "Loopback connected");
+    Ok(vec![impl_task, app_task])
+}
+
+fn impl_loop<E, A, R, AH>(
+    ob: &Arc<OneBot<AH, ImplOBC<E>>>,
+    mut action_rx: mpsc::UnboundedReceiver<Echo<A>>,
+    item_tx: mpsc::UnboundedSender<LoopbackItem<E, R>>,
+) -> WalleResult<JoinHandle<()>>
+where
+    E: ProtocolItem + Clone,
+    A: ProtocolItem,
+    R: ProtocolItem,
+    AH: ActionHandler<E, A, R> + Send + Sync + 'static,
+{
+    let mut signal_rx = ob.get_signal_rx()?;
+    let implt = &ob.event_handler;
+    let mut event_rx = implt.event_tx.subscribe();
+    let mut hb_rx = implt.hb_tx.subscribe();
+    let connect = connect_event(&implt.implt, &implt.platform, &implt.version);
+    let ob = ob.clone();
+    Ok(ob.clone().spawn(async move {
+        // meta.connect must be the first event of every connection
+        if let Some(connect) = downcast_event(connect) {
+            item_tx.send(LoopbackItem::Event(connect)).ok();
+        }
+        let conn_id = next_conn_id();
+        ob.handle_lifecycle(Lifecycle::Connect {
+            conn_id,
+            transport: Transport::Loopback,
+            peer: None,
+        })
+        .await;
+        loop {
+            tokio::select! {
+                _ = signal_rx.recv() => break,
+                event = event_rx.recv() => match event {
+                    Ok(event) => if item_tx.send(LoopbackItem::Event(event)).is_err() {
+                        break;
+                    },
+                    Err(RecvError::Lagged(n)) => warn!(target: OBC, "loopback lagged {} events", n),
+                    Err(RecvError::Closed) => break,
+                },
+                hb = hb_rx.recv() => match hb {
+                    Ok(hb) => if let Some(hb) = downcast_event(hb) {
+                        if item_tx.send(LoopbackItem::Event(hb)).is_err() {
+                            break;
+                        }
+                    },
+                    Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => break,
+                },
+                action = action_rx.recv() => match action {
+                    Some(action) => {
+                        let (action, echo) = action.unpack();
+                        let ob_ = ob.clone();
+                        let item_tx = item_tx.clone();
+                        ob.spawn(async move {
+                            let handle = RECV_ECHO.scope(echo.clone(), ob_.handle_action(action));
+                            match RECV_CONN.scope(conn_id, handle).await {
+                                Ok(r) => {
+                                    item_tx.send(LoopbackItem::Resp(echo.pack(r))).ok();
+                                }
+                                Err(e) => warn!(target: OBC, "handle action error: {}", e),
+                            }
+                        });
+                    }
+                    None => break,
+                },
+            }
+        }
+        ob.handle_lifecycle(Lifecycle::Disconnect {
+            conn_id,
+            transport: Transport::Loopback,
+            peer: None,
+        })
+        .await;
+    }))
+}
+
+fn app_loop<E, A, R, EH>(
+    ob: &Arc<OneBot<AppOBC<A, R>, EH>>,
+    action_tx: mpsc::UnboundedSender<Echo<A>>,
+    mut item_rx: mpsc::UnboundedReceiver<LoopbackItem<E, R>>,
+    (implt, platform): (String, String),
+) -> WalleResult<JoinHandle<()>>
+where
+    E: ProtocolItem + SelfId + Clone,
+    A: ProtocolItem + SelfId + Clone,
+    R: ProtocolItem,
+    EH: EventHandler<E, A, R> + Send + Sync + 'static,
+{
+    let mut signal_rx = ob.get_signal_rx()?;
+    let echo_map = ob.action_handler.echos.clone();
+    let bot_map = ob.action_handler.bots.clone();
+    let meta = ob.action_handler.lifecycle_meta.load(Ordering::Relaxed);
+    let mut notifier = LifecycleNotifier::with_peer(meta, implt, platform);
+    let ob = ob.clone();
+    Ok(ob.clone().spawn(async move {
+        let transport = Transport::Loopback;
+        let conn = BotConn::new(next_conn_id(), transport, action_tx);
+        let mut bot_set = HashSet::new();
+        let connect = Lifecycle::Connect {
+            conn_id: conn.id,
+            transport,
+            peer: None,
+        };
+        notifier.notify(&ob, connect).await;
+        loop {
+            tokio::select! {
+                _ = signal_rx.recv() => break,
+                item = item_rx.recv() => match item {
+                    Some(LoopbackItem::Event(event)) => {
+                        let self_id = event.self_id();
+                        // meta 事件不属于任何 Bot
+                        if !self_id.is_empty() {
+                            if bot_map.ensure_bot(&self_id, &conn) {
+                                let online = Lifecycle::BotOnline {
+                                    self_id: self_id.clone(),
+                                    conn_id: conn.id,
+                                    transport,
+                                };
+                                notifier.notify(&ob, online).await;
+                            }
+                            bot_set.insert(self_id);
+                        }
+                        let ob_ = ob.clone();
+                        ob.spawn(async move { ob_.handle_event(event).await });
+                    }
+                    Some(LoopbackItem::Resp(resp)) => {
+                        let (r, echo) = resp.unpack();
+                        echo_map.resolve(&echo, r);
+                    }
+                    None => break,
+                },
+            }
+        }
+        for self_id in bot_set {
+            if bot_map.remove_bot(&self_id, conn.id) {
+                let offline = Lifecycle::BotOffline {
+                    self_id,
+                    conn_id: conn.id,
+                    transport,
+                };
+                notifier.notify(&ob, offline).await;
+            }
+        }
+        echo_map.cancel_conn(conn.id);
+        let disconnect = Lifecycle::Disconnect {
+            conn_id: conn.id,
+            transport,
+            peer: None,
+        };
+        notifier.notify(&ob, disconnect).await;
+    }))
+}
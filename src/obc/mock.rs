@@ -0,0 +1,203 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use super::ImplOBC;
+use crate::{
+    action::Action,
+    event::{BaseEvent, Event},
+    resp::{resp_error, Resp},
+    structs::Status,
+    util::{new_uuid, timestamp_nano_f64, SelfIds},
+    ActionHandler, EventHandler, GetStatus, OneBot, WalleResult,
+};
+
+/// 基于 ImplOBC 的 Mock 实现端
+pub type MockOneBot = OneBot<MockImpl, ImplOBC<Event>>;
+
+/// 预设的 action 响应
+struct Expectation {
+    action: String,
+    resp: Resp,
+    repeat: bool,
+}
+
+/// 可编排的 Mock 实现端 ActionHandler，用于测试应用端
+///
+/// 按 action 名称匹配预设响应，未匹配的 action 返回 unsupported_action；
+/// 收到的所有 action 均会被记录
+pub struct MockImpl {
+    self_ids: Vec<String>,
+    expectations: Mutex<VecDeque<Expectation>>,
+    actions: Mutex<Vec<Action>>,
+    action_tx: mpsc::UnboundedSender<Action>,
+    action_rx: tokio::sync::Mutex<mpsc::UnboundedReceiver<Action>>,
+}
+
+impl MockImpl {
+    pub fn new(self_id: &str) -> Self {
+        let (action_tx, action_rx) = mpsc::unbounded_channel();
+        Self {
+            self_ids: vec![self_id.to_string()],
+            expectations: Mutex::default(),
+            actions: Mutex::default(),
+            action_tx,
+            action_rx: tokio::sync::Mutex::new(action_rx),
+        }
+    }
+
+    /// 期望收到一次该 action，并以 resp 响应
+    ///
+    /// 同名 action 的多次期望按添加顺序依次匹配
+    pub fn expect<T: Into<Resp>>(&self, action: &str, resp: T) -> &Self {
+        self.push_expectation(action, resp.into(), false)
+    }
+
+    /// 该 action 每次均以 resp 响应，优先级低于 expect
+    pub fn stub<T: Into<Resp>>(&self, action: &str, resp: T) -> &Self {
+        self.push_expectation(action, resp.into(), true)
+    }
+
+    fn push_expectation(&self, action: &str, resp: Resp, repeat: bool) -> &Self {
+        self.expectations.lock().unwrap().push_back(Expectation {
+            action: action.to_string(),
+            resp,
+            repeat,
+        });
+        self
+    }
+
+    /// 已收到的全部 action
+    pub fn actions(&self) -> Vec<Action> {
+        self.actions.lock().unwrap().clone()
+    }
+
+    /// 等待下一个收到的 action，超时返回 None
+    pub async fn next_action(&self, timeout: Duration) -> Option<Action> {
+        let mut rx = self.action_rx.lock().await;
+        tokio::time::timeout(timeout, rx.recv())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// 尚未被匹配的 expect
+    pub fn unmet_expectations(&self) -> Vec<String> {
+        self.expectations
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|e| !e.repeat)
+            .map(|e| e.action.clone())
+            .collect()
+    }
+
+    /// 断言所有 expect 均已被匹配
+    pub fn assert_expectations(&self) {
+        let unmet = self.unmet_expectations();
+        assert!(unmet.is_empty(), "unmet mock expectations: {:?}", unmet);
+    }
+
+    fn respond(&self, action: &str) -> Resp {
+        let mut expectations = self.expectations.lock().unwrap();
+        let found = expectations
+            .iter()
+            .position(|e| !e.repeat && e.action == action)
+            .or_else(|| expectations.iter().position(|e| e.action == action));
+        match found {
+            Some(i) if expectations[i].repeat => expectations[i].resp.clone(),
+            Some(i) => expectations.remove(i).unwrap().resp,
+            None => resp_error::unsupported_action(action).into(),
+        }
+    }
+}
+
+#[async_trait]
+impl SelfIds for MockImpl {
+    async fn self_ids(&self) -> Vec<String> {
+        self.self_ids.clone()
+    }
+}
+
+impl GetStatus for MockImpl {
+    fn get_status(&self) -> Status {
+        Status {
+            good: true,
+            online: true,
+        }
+    }
+}
+
+#[async_trait]
+impl ActionHandler<Event, Action, Resp> for MockImpl {
+    type Config = ();
+    async fn start<AH, EH>(
+        &self,
+        _: &Arc<OneBot<AH, EH>>,
+        _: (),
+    ) -> WalleResult<Vec<JoinHandle<()>>>
+    where
+        AH: ActionHandler<Event, Action, Resp> + Send + Sync + 'static,
+        EH: EventHandler<Event, Action, Resp> + Send + Sync + 'static,
+    {
+        Ok(vec![])
+    }
+    async fn call(&self, action: Action) -> WalleResult<Resp> {
+        let resp = self.respond(&action.action);
+        self.actions.lock().unwrap().push(action.clone());
+        self.action_tx.send(action).ok();
+        Ok(resp)
+    }
+}
+
+impl MockOneBot {
+    /// 构造 Mock 实现端，启动时传入 `((), ImplConfig)`
+    ///
+    /// 应用端可通过 ImplConfig 中的连接方式或 `loopback` 接入
+    pub fn mock(self_id: &str, platform: &str) -> Self {
+        OneBot::new(
+            MockImpl::new(self_id),
+            ImplOBC::new("walle-mock".to_string(), platform.to_string()),
+        )
+    }
+
+    /// 推送事件，未填写的 id、time、self_id、impl 与 platform 字段会被自动补全
+    pub async fn push_event<T: Into<Event>>(self: &Arc<Self>, event: T) -> WalleResult<()> {
+        let mut event: Event = event.into();
+        if event.id.is_empty() {
+            event.id = new_uuid();
+        }
+        if event.time == 0.0 {
+            event.time = timestamp_nano_f64();
+        }
+        if event.self_id.is_empty() {
+            event.self_id = self.action_handler.self_ids[0].clone();
+        }
+        if event.implt.is_empty() {
+            event.implt = self.event_handler.implt.clone();
+        }
+        if event.platform.is_empty() {
+            event.platform = self.event_handler.platform.clone();
+        }
+        self.handle_event::<_, Action, Resp>(event).await
+    }
+}
+
+/// 构造待推送的类型化事件，其余字段由 `MockOneBot::push_event` 补全
+pub fn mock_event<T, D, S>(ty: T, detail_type: D, sub_type: S) -> BaseEvent<T, D, S> {
+    BaseEvent {
+        id: String::default(),
+        self_id: String::default(),
+        time: 0.0,
+        implt: (),
+        platform: (),
+        ty,
+        detail_type,
+        sub_type,
+        extra: Default::default(),
+    }
+}
@@ -2,12 +2,235 @@ pub const OBC: &str = "Walle-OBC";
 
 #[cfg(feature = "app-obc")]
 mod app_obc;
+#[cfg(feature = "console")]
+mod console;
+mod endpoint;
+#[cfg(feature = "impl-obc")]
+mod fake;
 #[cfg(feature = "impl-obc")]
 mod impl_obc;
+#[cfg(all(feature = "impl-obc", feature = "app-obc"))]
+mod loopback;
+#[cfg(feature = "impl-obc")]
+mod mock;
+#[cfg(any(feature = "http", feature = "websocket"))]
+mod net;
+#[cfg(all(feature = "impl-obc", feature = "app-obc"))]
+mod relay;
 #[cfg(feature = "websocket")]
 mod ws_util;
 
 #[cfg(feature = "app-obc")]
 pub use app_obc::*;
+#[cfg(feature = "console")]
+pub use console::{ConsoleOneBot, ConsolePlatform};
+pub use endpoint::{ConnInfo, EndpointInfo};
+#[cfg(feature = "impl-obc")]
+pub use fake::{FakeFile, FakeGroup, FakeGuild, FakeMessage, FakeOneBot, FakePlatform, FakeState};
 #[cfg(feature = "impl-obc")]
 pub use impl_obc::*;
+#[cfg(all(feature = "impl-obc", feature = "app-obc"))]
+pub use loopback::loopback;
+#[cfg(feature = "impl-obc")]
+pub use mock::{mock_event, MockImpl, MockOneBot};
+#[cfg(all(feature = "impl-obc", feature = "app-obc"))]
+pub use relay::{Relay, RelayActions, RelayDownstream, RelayEvents, RelayUpstream};
+
+/// 构造 meta 事件，用于上报 OBC 自身状态
+#[cfg(any(feature = "impl-obc", feature = "http", feature = "websocket"))]
+pub(crate) fn meta_event(
+    implt: &str,
+    platform: &str,
+    detail_type: &str,
+    extra: crate::util::ValueMap,
+) -> crate::event::Event {
+    crate::event::Event {
+        id: crate::util::new_uuid(),
+        implt: implt.to_string(),
+        platform: platform.to_string(),
+        self_id: String::default(),
+        time: crate::util::timestamp_nano_f64(),
+        ty: "meta".to_string(),
+        detail_type: detail_type.to_string(),
+        sub_type: String::default(),
+        extra,
+    }
+}
+
+/// 将生命周期变化转换为 `meta.connect` 或 `meta.status_update` 事件
+///
+/// 连接断开没有对应的标准事件，返回 None
+#[cfg(all(
+    feature = "app-obc",
+    any(feature = "impl-obc", feature = "http", feature = "websocket")
+))]
+pub(crate) fn lifecycle_event(
+    implt: &str,
+    platform: &str,
+    lifecycle: &crate::lifecycle::Lifecycle,
+) -> Option<crate::event::Event> {
+    use crate::lifecycle::Lifecycle;
+    let (self_id, online) = match lifecycle {
+        Lifecycle::Connect {
+            conn_id,
+            transport,
+            peer,
+        } => {
+            let mut event = connect_event(implt, platform, "");
+            event
+                .extra
+                .insert("conn_id".to_string(), (*conn_id as i64).into());
+            event
+                .extra
+                .insert("transport".to_string(), transport.to_string().into());
+            event
+                .extra
+                .insert("peer".to_string(), peer.map(|p| p.to_string()).into());
+            return Some(event);
+        }
+        Lifecycle::Disconnect { .. } => return None,
+        Lifecycle::BotOnline { self_id, .. } => (self_id, true),
+        Lifecycle::BotOffline { self_id, .. } => (self_id, false),
+    };
+    let status = crate::structs::BotsStatus {
+        good: true,
+        bots: vec![bot_status(platform, self_id, online)],
+    };
+    let mut event = status_update_event(implt, platform, status);
+    event.self_id = self_id.clone();
+    Some(event)
+}
+
+/// 构造 `meta.connect` 事件
+#[cfg(any(
+    feature = "websocket",
+    all(feature = "app-obc", any(feature = "impl-obc", feature = "http"))
+))]
+pub(crate) fn connect_event(implt: &str, platform: &str, version: &str) -> crate::event::Event {
+    let connect = crate::event::Connect {
+        version: crate::structs::Version {
+            implt: implt.to_string(),
+            platform: platform.to_string(),
+            version: version.to_string(),
+            onebot_version: "12".to_string(),
+        },
+    };
+    meta_event(implt, platform, "connect", connect.into())
+}
+
+/// 构造 `meta.status_update` 事件
+#[cfg(any(
+    feature = "impl-obc",
+    all(feature = "app-obc", any(feature = "http", feature = "websocket"))
+))]
+pub(crate) fn status_update_event(
+    implt: &str,
+    platform: &str,
+    status: crate::structs::BotsStatus,
+) -> crate::event::Event {
+    let status_update = crate::event::StatusUpdate { status };
+    meta_event(implt, platform, "status_update", status_update.into())
+}
+
+#[cfg(any(
+    feature = "impl-obc",
+    all(feature = "app-obc", any(feature = "http", feature = "websocket"))
+))]
+pub(crate) fn bot_status(platform: &str, self_id: &str, online: bool) -> crate::structs::BotStatus {
+    crate::structs::BotStatus {
+        selft: crate::structs::Selft {
+            platform: platform.to_string(),
+            user_id: self_id.to_string(),
+        },
+        online,
+    }
+}
+
+/// 将标准 Event 转换为泛型 Event，无法表示时返回 None
+#[cfg(all(
+    feature = "app-obc",
+    any(feature = "impl-obc", feature = "http", feature = "websocket")
+))]
+pub(crate) fn downcast_event<E>(event: crate::event::Event) -> Option<E>
+where
+    E: crate::util::ProtocolItem,
+{
+    use crate::util::ProtocolItem;
+    E::rmp_decode(&event.rmp_encode())
+        .map_err(|e| tracing::trace!(target: OBC, "meta event not supported: {}", e))
+        .ok()
+}
+
+/// 按 access_token 权限范围过滤外发事件
+#[cfg(all(feature = "impl-obc", any(feature = "http", feature = "websocket")))]
+pub(crate) fn scope_allow_event<E: serde::Serialize>(
+    scope: &crate::config::Scope,
+    event: &E,
+) -> bool {
+    #[derive(serde::Deserialize)]
+    struct EventPeek {
+        #[serde(rename = "type")]
+        ty: String,
+        detail_type: String,
+        #[serde(default)]
+        self_id: String,
+    }
+    if scope.events.is_empty() && scope.self_ids.is_empty() {
+        return true;
+    }
+    match serde_json::to_value(event).and_then(serde_json::from_value::<EventPeek>) {
+        Ok(e) => scope.allow_event(&e.ty, &e.detail_type, &e.self_id),
+        Err(_) => false,
+    }
+}
+
+/// 按连接的过滤表达式过滤事件，未设置时全部通过
+#[cfg(any(feature = "http", feature = "websocket"))]
+pub(crate) fn filter_allow_event<E: serde::Serialize>(
+    filter: &Option<crate::filter::EventFilter>,
+    event: &E,
+) -> bool {
+    match filter {
+        Some(filter) => filter.matches(event),
+        None => true,
+    }
+}
+
+/// 按 access_token 权限范围检查 action 请求，拒绝时返回请求的 echo
+#[cfg(all(feature = "impl-obc", any(feature = "http", feature = "websocket")))]
+pub(crate) fn scope_check_action(
+    scope: &crate::config::Scope,
+    data: &[u8],
+    content_type: &crate::util::ContentType,
+) -> Result<(), crate::util::EchoS> {
+    use crate::util::ProtocolItem;
+    #[derive(Debug, serde::Serialize, serde::Deserialize, Default)]
+    struct ParamsPeek {
+        #[serde(default)]
+        self_id: String,
+    }
+    #[derive(Debug, serde::Serialize, serde::Deserialize)]
+    struct ActionPeek {
+        action: String,
+        #[serde(default)]
+        params: ParamsPeek,
+    }
+    if scope.actions.is_empty() && scope.self_ids.is_empty() {
+        return Ok(());
+    }
+    match crate::util::Echo::<ActionPeek>::decode(data, content_type) {
+        Ok(a) if scope.allow_action(&a.inner.action, &a.inner.params.self_id) => Ok(()),
+        Ok(a) => Err(a.get_echo()),
+        Err(_) => Err(crate::util::EchoS(None)),
+    }
+}
+
+/// 为每个连接分配唯一 id
+#[cfg(any(
+    feature = "websocket",
+    all(feature = "app-obc", any(feature = "impl-obc", feature = "http"))
+))]
+pub(crate) fn next_conn_id() -> u64 {
+    static CONN_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    CONN_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+}
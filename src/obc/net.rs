@@ -0,0 +1,586 @@
+use std::{
+    io,
+    net::SocketAddr,
+    path::PathBuf,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::config::{TlsClient, TlsServer, UnixSocket};
+use crate::error::WalleResult;
+
+/// 明文、TLS 或 Unix socket 连接
+pub(crate) enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixStream),
+    #[cfg(feature = "tls")]
+    ServerTls(Box<tokio_rustls::server::TlsStream<TcpStream>>),
+    #[cfg(feature = "tls")]
+    ClientTls(Box<tokio_rustls::client::TlsStream<TcpStream>>),
+}
+
+macro_rules! delegate {
+    ($stream: expr, $s: ident => $e: expr) => {
+        match $stream {
+            Stream::Tcp($s) => $e,
+            #[cfg(unix)]
+            Stream::Unix($s) => $e,
+            #[cfg(feature = "tls")]
+            Stream::ServerTls($s) => $e,
+            #[cfg(feature = "tls")]
+            Stream::ClientTls($s) => $e,
+        }
+    };
+}
+
+impl Stream {
+    #[allow(dead_code)]
+    pub(crate) fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Self::Tcp(s) => s.peer_addr(),
+            #[cfg(unix)]
+            Self::Unix(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "unix socket has no ip address",
+            )),
+            #[cfg(feature = "tls")]
+            Self::ServerTls(s) => s.get_ref().0.peer_addr(),
+            #[cfg(feature = "tls")]
+            Self::ClientTls(s) => s.get_ref().0.peer_addr(),
+        }
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        delegate!(self.get_mut(), s => Pin::new(s).poll_read(cx, buf))
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        delegate!(self.get_mut(), s => Pin::new(s).poll_write(cx, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        delegate!(self.get_mut(), s => Pin::new(s).poll_flush(cx))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        delegate!(self.get_mut(), s => Pin::new(s).poll_shutdown(cx))
+    }
+}
+
+#[cfg(feature = "http")]
+impl hyper::client::connect::Connection for Stream {
+    fn connected(&self) -> hyper::client::connect::Connected {
+        hyper::client::connect::Connected::new()
+    }
+}
+
+/// 服务端连接，配置 TLS 时先完成握手
+#[derive(Clone)]
+pub(crate) struct Acceptor {
+    #[cfg(feature = "tls")]
+    tls: Option<tokio_rustls::TlsAcceptor>,
+}
+
+impl Acceptor {
+    pub(crate) fn new(config: &Option<TlsServer>) -> WalleResult<Self> {
+        #[cfg(feature = "tls")]
+        {
+            let tls = match config {
+                Some(config) => Some(tls::server_config(config)?.into()),
+                None => None,
+            };
+            Ok(Self { tls })
+        }
+        #[cfg(not(feature = "tls"))]
+        match config {
+            Some(_) => Err(tls_disabled().into()),
+            None => Ok(Self {}),
+        }
+    }
+
+    pub(crate) fn is_tls(&self) -> bool {
+        #[cfg(feature = "tls")]
+        return self.tls.is_some();
+        #[cfg(not(feature = "tls"))]
+        false
+    }
+
+    /// 仅对 TCP 连接进行 TLS 握手，Unix socket 与 TLS 不能同时配置
+    pub(crate) async fn accept(&self, stream: Stream) -> io::Result<Stream> {
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            if let Stream::Tcp(stream) = stream {
+                return Ok(Stream::ServerTls(Box::new(tls.accept(stream).await?)));
+            }
+        }
+        Ok(stream)
+    }
+}
+
+/// 监听 TCP 端口或 Unix socket
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, PathBuf),
+}
+
+impl Listener {
+    /// 设置 unix 时监听 Unix socket，否则监听 addr
+    pub(crate) async fn bind(addr: SocketAddr, unix: &Option<UnixSocket>) -> WalleResult<Self> {
+        match unix {
+            None => Ok(Self::Tcp(TcpListener::bind(addr).await?)),
+            #[cfg(unix)]
+            Some(unix) => {
+                remove_stale_socket(&unix.path)?;
+                let listener = match unix.mode {
+                    Some(mode) => bind_with_mode(&unix.path, mode)?,
+                    None => tokio::net::UnixListener::bind(&unix.path)?,
+                };
+                Ok(Self::Unix(listener, unix.path.clone()))
+            }
+            #[cfg(not(unix))]
+            Some(_) => Err(unix_unsupported().into()),
+        }
+    }
+
+    /// 返回连接与对端描述
+    pub(crate) async fn accept(&self) -> io::Result<(Stream, String)> {
+        match self {
+            Self::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                Ok((Stream::Tcp(stream), addr.to_string()))
+            }
+            #[cfg(unix)]
+            Self::Unix(listener, path) => {
+                let (stream, _) = listener.accept().await?;
+                Ok((Stream::Unix(stream), format!("unix:{}", path.display())))
+            }
+        }
+    }
+
+    /// 用于日志的监听地址
+    pub(crate) fn url(&self, scheme: &str) -> String {
+        match self {
+            Self::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => format!("{}://{}", scheme, addr),
+                Err(_) => format!("{}://", scheme),
+            },
+            #[cfg(unix)]
+            Self::Unix(_, path) => format!("{}+unix://{}", scheme, path.display()),
+        }
+    }
+}
+
+/// 移除上次运行遗留的 socket 文件
+///
+/// 仍有进程在该 socket 上监听时返回 AddrInUse，路径被其他类型文件占用时返回错误
+#[cfg(unix)]
+fn remove_stale_socket(path: &std::path::Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => {
+            match std::os::unix::net::UnixStream::connect(path) {
+                Ok(_) => Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("{} is in use", path.display()),
+                )),
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                    std::fs::remove_file(path)
+                }
+                Err(e) => Err(e),
+            }
+        }
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        )),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// 在私有临时目录中创建 socket 并设置权限后再移动到 path，
+/// 避免 socket 在设置权限前以默认权限暴露
+#[cfg(unix)]
+fn bind_with_mode(path: &std::path::Path, mode: u32) -> io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    let parent = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => std::path::Path::new("."),
+    };
+    let dir = parent.join(format!(
+        ".walle-{}-{}",
+        std::process::id(),
+        crate::util::timestamp_nano()
+    ));
+    std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let tmp = dir.join("sock");
+    let bind = || {
+        let listener = tokio::net::UnixListener::bind(&tmp)?;
+        std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(mode))?;
+        std::fs::rename(&tmp, path)?;
+        Ok(listener)
+    };
+    let r = bind();
+    std::fs::remove_dir_all(&dir).ok();
+    r
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Self::Unix(_, path) = self {
+            std::fs::remove_file(path).ok();
+        }
+    }
+}
+
+/// 客户端连接，按 scheme 决定是否使用 TLS，设置 unix 时改为连接 Unix socket
+#[derive(Clone)]
+pub(crate) struct Connector {
+    #[cfg(feature = "tls")]
+    tls: tokio_rustls::TlsConnector,
+    unix: Option<PathBuf>,
+}
+
+impl Connector {
+    pub(crate) fn new(config: &TlsClient, unix: &Option<PathBuf>) -> WalleResult<Self> {
+        #[cfg(not(unix))]
+        if unix.is_some() {
+            return Err(unix_unsupported().into());
+        }
+        #[cfg(feature = "tls")]
+        return Ok(Self {
+            tls: tls::client_config(config)?.into(),
+            unix: unix.clone(),
+        });
+        #[cfg(not(feature = "tls"))]
+        {
+            let _ = config;
+            Ok(Self { unix: unix.clone() })
+        }
+    }
+
+    pub(crate) async fn connect(&self, host: &str, port: u16, secure: bool) -> io::Result<Stream> {
+        #[cfg(unix)]
+        if let Some(path) = &self.unix {
+            return Ok(Stream::Unix(tokio::net::UnixStream::connect(path).await?));
+        }
+        let stream = TcpStream::connect((host, port)).await?;
+        if !secure {
+            return Ok(Stream::Tcp(stream));
+        }
+        #[cfg(feature = "tls")]
+        {
+            let name = tokio_rustls::rustls::ServerName::try_from(host)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            let stream = self.tls.connect(name, stream).await?;
+            Ok(Stream::ClientTls(Box::new(stream)))
+        }
+        #[cfg(not(feature = "tls"))]
+        Err(tls_disabled())
+    }
+}
+
+#[cfg(feature = "http")]
+impl hyper::service::Service<hyper::Uri> for Connector {
+    type Response = Stream;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn std::future::Future<Output = io::Result<Stream>> + Send>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: hyper::Uri) -> Self::Future {
+        let connector = self.clone();
+        Box::pin(async move {
+            let secure = uri.scheme_str() == Some("https");
+            let host = uri
+                .host()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "missing host"))?;
+            let port = uri.port_u16().unwrap_or(if secure { 443 } else { 80 });
+            connector.connect(host, port, secure).await
+        })
+    }
+}
+
+/// 按 TLS 与 Unix socket 设置构造 HTTP 客户端
+#[cfg(feature = "http")]
+pub(crate) fn hyper_client(
+    config: &crate::config::HttpClient,
+) -> WalleResult<hyper::Client<Connector, hyper::Body>> {
+    Ok(hyper::Client::builder().build(Connector::new(&config.tls, &config.unix)?))
+}
+
+#[cfg(not(unix))]
+fn unix_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "unix socket is not supported on this platform",
+    )
+}
+
+#[cfg(not(feature = "tls"))]
+fn tls_disabled() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "tls feature is not enabled")
+}
+
+#[cfg(feature = "tls")]
+mod tls {
+    use std::{fs::File, io, io::BufReader, path::Path, sync::Arc};
+
+    use rustls_pemfile::Item;
+    use tokio_rustls::rustls::{
+        server::AllowAnyAuthenticatedClient, Certificate, ClientConfig, OwnedTrustAnchor,
+        PrivateKey, RootCertStore, ServerConfig,
+    };
+
+    use crate::config::{TlsClient, TlsServer};
+
+    fn invalid<E: ToString>(e: E) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+    }
+
+    fn read_pem(path: &Path) -> io::Result<Vec<Item>> {
+        rustls_pemfile::read_all(&mut BufReader::new(File::open(path)?))
+    }
+
+    fn load_certs(path: &Path) -> io::Result<Vec<Certificate>> {
+        let certs: Vec<_> = read_pem(path)?
+            .into_iter()
+            .filter_map(|item| match item {
+                Item::X509Certificate(cert) => Some(Certificate(cert)),
+                _ => None,
+            })
+            .collect();
+        if certs.is_empty() {
+            return Err(invalid(format!("no certificate in {}", path.display())));
+        }
+        Ok(certs)
+    }
+
+    fn load_key(path: &Path) -> io::Result<PrivateKey> {
+        read_pem(path)?
+            .into_iter()
+            .find_map(|item| match item {
+                Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => Some(PrivateKey(key)),
+                _ => None,
+            })
+            .ok_or_else(|| invalid(format!("no private key in {}", path.display())))
+    }
+
+    fn load_roots(path: &Path) -> io::Result<RootCertStore> {
+        let mut roots = RootCertStore::empty();
+        for cert in load_certs(path)? {
+            roots.add(&cert).map_err(invalid)?;
+        }
+        Ok(roots)
+    }
+
+    pub(super) fn server_config(config: &TlsServer) -> io::Result<Arc<ServerConfig>> {
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let builder = match &config.client_ca {
+            Some(ca) => {
+                builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(load_roots(ca)?))
+            }
+            None => builder.with_no_client_auth(),
+        };
+        builder
+            .with_single_cert(load_certs(&config.cert)?, load_key(&config.key)?)
+            .map(Arc::new)
+            .map_err(invalid)
+    }
+
+    pub(super) fn client_config(config: &TlsClient) -> io::Result<Arc<ClientConfig>> {
+        let roots = match &config.ca {
+            Some(ca) => load_roots(ca)?,
+            None => {
+                let mut roots = RootCertStore::empty();
+                roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+                    OwnedTrustAnchor::from_subject_spki_name_constraints(
+                        ta.subject,
+                        ta.spki,
+                        ta.name_constraints,
+                    )
+                }));
+                roots
+            }
+        };
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots);
+        let config = match (&config.cert, &config.key) {
+            (Some(cert), Some(key)) => builder
+                .with_single_cert(load_certs(cert)?, load_key(key)?)
+                .map_err(invalid)?,
+            _ => builder.with_no_client_auth(),
+        };
+        Ok(Arc::new(config))
+    }
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn tls_test() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let dir = std::env::temp_dir().join(format!("walle-tls-{}", crate::util::timestamp_nano()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let write_cert = |name: &str| {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let (cert_path, key_path) = (
+            dir.join(format!("{name}.pem")),
+            dir.join(format!("{name}.key")),
+        );
+        std::fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+        std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+        (cert_path, key_path)
+    };
+    let (server_cert, server_key) = write_cert("server");
+    let (client_cert, client_key) = write_cert("client");
+    let acceptor = Acceptor::new(&Some(TlsServer {
+        cert: server_cert.clone(),
+        key: server_key,
+        client_ca: Some(client_cert.clone()),
+    }))
+    .unwrap();
+    let trusted = Connector::new(
+        &TlsClient {
+            ca: Some(server_cert.clone()),
+            cert: Some(client_cert),
+            key: Some(client_key),
+        },
+        &None,
+    )
+    .unwrap();
+    let anonymous = Connector::new(
+        &TlsClient {
+            ca: Some(server_cert),
+            ..Default::default()
+        },
+        &None,
+    )
+    .unwrap();
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    rt.block_on(async {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((tcp, _)) = listener.accept().await {
+                if let Ok(mut stream) = acceptor.accept(Stream::Tcp(tcp)).await {
+                    stream.write_all(b"walle").await.ok();
+                    stream.shutdown().await.ok();
+                }
+            }
+        });
+
+        let mut stream = trusted.connect("localhost", port, true).await.unwrap();
+        let mut buf = String::new();
+        stream.read_to_string(&mut buf).await.unwrap();
+        assert_eq!(buf, "walle");
+
+        // server requires a client certificate
+        let mut buf = String::new();
+        let result = match anonymous.connect("localhost", port, true).await {
+            Ok(mut stream) => stream.read_to_string(&mut buf).await.map(|_| ()),
+            Err(e) => Err(e),
+        };
+        assert!(result.is_err());
+    });
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket_test() {
+    use std::os::unix::fs::PermissionsExt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let path = std::env::temp_dir().join(format!("walle-{}.sock", crate::util::timestamp_nano()));
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    rt.block_on(async {
+        let unix = UnixSocket {
+            path: path.clone(),
+            mode: Some(0o600),
+        };
+        let listener = Listener::bind(([127, 0, 0, 1], 0).into(), &Some(unix))
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+        // a socket still listened on is not taken over
+        let unix = UnixSocket {
+            path: path.clone(),
+            mode: None,
+        };
+        match Listener::bind(([127, 0, 0, 1], 0).into(), &Some(unix)).await {
+            Err(crate::WalleError::IO(e)) => assert_eq!(e.kind(), io::ErrorKind::AddrInUse),
+            _ => panic!("bind to a socket in use"),
+        }
+        // the probe connection
+        listener.accept().await.unwrap();
+        // a socket left by a stopped instance is replaced
+        let stale = path.with_extension("stale");
+        drop(std::os::unix::net::UnixListener::bind(&stale).unwrap());
+        let unix = UnixSocket {
+            path: stale.clone(),
+            mode: None,
+        };
+        drop(
+            Listener::bind(([127, 0, 0, 1], 0).into(), &Some(unix))
+                .await
+                .unwrap(),
+        );
+        assert!(!stale.exists());
+        let taken = path.with_extension("txt");
+        std::fs::write(&taken, "").unwrap();
+        let unix = UnixSocket {
+            path: taken.clone(),
+            mode: None,
+        };
+        assert!(Listener::bind(([127, 0, 0, 1], 0).into(), &Some(unix))
+            .await
+            .is_err());
+        std::fs::remove_file(taken).unwrap();
+        let connector = Connector::new(&TlsClient::default(), &Some(path.clone())).unwrap();
+        let (client, server) = tokio::join!(connector.connect("localhost", 80, false), async {
+            let (stream, peer) = listener.accept().await.unwrap();
+            assert!(peer.starts_with("unix:"));
+            Acceptor::new(&None).unwrap().accept(stream).await.unwrap()
+        });
+        let (mut client, mut server) = (client.unwrap(), server);
+        client.write_all(b"walle").await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut buf = String::new();
+        server.read_to_string(&mut buf).await.unwrap();
+        assert_eq!(buf, "walle");
+        drop(listener);
+    });
+    assert!(!path.exists());
+}
@@ -0,0 +1,176 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+
+use super::{lifecycle_event, AppOBC, ImplOBC};
+use crate::{
+    action::Action,
+    config::RelayConfig,
+    error::{WalleError, WalleResult},
+    event::Event,
+    lifecycle::Lifecycle,
+    resp::{resp_error, Resp},
+    structs::Status,
+    util::SelfIds,
+    ActionHandler, EventHandler, GetStatus, OneBot,
+};
+
+/// 连接上游实现端的应用端 OneBot
+pub type RelayUpstream = OneBot<AppOBC<Action, Resp>, RelayEvents>;
+/// 向下游应用端提供服务的实现端 OneBot
+pub type RelayDownstream = OneBot<RelayActions, ImplOBC<Event>>;
+
+/// 协议中继
+///
+/// 上游为 AppOBC，下游为 ImplOBC：上游事件转发至全部下游连接，
+/// 下游 action 按 self_id 路由至上游对应 Bot。
+/// 下游连接可通过 access_token 或连接的 `scope` 限制可接收的事件与可调用的 action
+pub struct Relay {
+    pub upstream: Arc<RelayUpstream>,
+    pub downstream: Arc<RelayDownstream>,
+}
+
+impl Relay {
+    pub fn new(platform: &str) -> Self {
+        let implt = ImplOBC::new("walle-relay".to_string(), platform.to_string());
+        let upstream = Arc::new(OneBot::new(
+            AppOBC::new(),
+            RelayEvents {
+                implt: implt.implt.clone(),
+                platform: implt.platform.clone(),
+                event_tx: implt.event_tx.clone(),
+                hb_tx: implt.hb_tx.clone(),
+            },
+        ));
+        let downstream = Arc::new(OneBot::new(
+            RelayActions {
+                upstream: upstream.clone(),
+            },
+            implt,
+        ));
+        Self {
+            upstream,
+            downstream,
+        }
+    }
+
+    /// 先启动下游再启动上游，避免丢失上游连接初期的事件
+    pub async fn start(&self, config: RelayConfig) -> WalleResult<Vec<JoinHandle<()>>> {
+        let mut tasks = self
+            .downstream
+            .start::<Event, Action, Resp>((), config.downstream, true)
+            .await?;
+        tasks.extend(
+            self.upstream
+                .start::<Event, Action, Resp>(config.upstream, (), true)
+                .await?,
+        );
+        Ok(tasks)
+    }
+
+    pub async fn shutdown(&self) -> WalleResult<()> {
+        self.downstream.shutdown::<Event, Action, Resp>().await?;
+        self.upstream.shutdown::<Event, Action, Resp>().await
+    }
+}
+
+/// 将上游事件转发至下游连接
+///
+/// 上游的 meta 事件不转发，下游的 meta 事件由中继自身生成
+pub struct RelayEvents {
+    implt: String,
+    platform: String,
+    event_tx: broadcast::Sender<Event>,
+    hb_tx: broadcast::Sender<Event>,
+}
+
+#[async_trait]
+impl EventHandler<Event, Action, Resp> for RelayEvents {
+    type Config = ();
+    async fn start<AH, EH>(
+        &self,
+        _: &Arc<OneBot<AH, EH>>,
+        _: (),
+    ) -> WalleResult<Vec<JoinHandle<()>>>
+    where
+        AH: ActionHandler<Event, Action, Resp> + Send + Sync + 'static,
+        EH: EventHandler<Event, Action, Resp> + Send + Sync + 'static,
+    {
+        Ok(vec![])
+    }
+    async fn call(&self, event: Event) -> WalleResult<()> {
+        if event.ty != "meta" {
+            self.event_tx.send(event).ok();
+        }
+        Ok(())
+    }
+    async fn on_lifecycle(&self, lifecycle: &Lifecycle) {
+        // 上游 Bot 上下线以 meta.status_update 通知下游
+        if let Lifecycle::BotOnline { .. } | Lifecycle::BotOffline { .. } = lifecycle {
+            if let Some(event) = lifecycle_event(&self.implt, &self.platform, lifecycle) {
+                self.hb_tx.send(event).ok();
+            }
+        }
+    }
+}
+
+/// 将下游 action 按 self_id 转发至上游
+pub struct RelayActions {
+    upstream: Arc<RelayUpstream>,
+}
+
+#[async_trait]
+impl SelfIds for RelayActions {
+    async fn self_ids(&self) -> Vec<String> {
+        SelfIds::self_ids(&self.upstream.action_handler).await
+    }
+}
+
+impl GetStatus for RelayActions {
+    fn get_status(&self) -> Status {
+        Status {
+            good: true,
+            online: !self.upstream.action_handler.bots.is_empty(),
+        }
+    }
+    fn get_bot_status(&self, self_id: &str) -> Status {
+        self.upstream.action_handler.get_bot_status(self_id)
+    }
+}
+
+#[async_trait]
+impl ActionHandler<Event, Action, Resp> for RelayActions {
+    type Config = ();
+    async fn start<AH, EH>(
+        &self,
+        _: &Arc<OneBot<AH, EH>>,
+        _: (),
+    ) -> WalleResult<Vec<JoinHandle<()>>>
+    where
+        AH: ActionHandler<Event, Action, Resp> + Send + Sync + 'static,
+        EH: EventHandler<Event, Action, Resp> + Send + Sync + 'static,
+    {
+        Ok(vec![])
+    }
+    async fn call(&self, action: Action) -> WalleResult<Resp> {
+        Ok(
+            match self
+                .upstream
+                .handle_action::<Event, Action, Resp>(action)
+                .await
+            {
+                Ok(resp) => resp,
+                Err(WalleError::BotNotExist) => resp_error::bad_request("bot not exist").into(),
+                Err(WalleError::RespError(e)) => e.into(),
+                Err(
+                    e @ (WalleError::ActionSendError
+                    | WalleError::ResponseTimeout
+                    | WalleError::Disconnected),
+                ) => resp_error::network_error(e).into(),
+                Err(e) => resp_error::internal_handler(e).into(),
+            },
+        )
+    }
+}
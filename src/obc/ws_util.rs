@@ -1,33 +1,241 @@
-use super::OBC;
+use super::{
+    endpoint::Stop,
+    meta_event,
+    net::{Acceptor, Connector, Stream},
+    OBC,
+};
+use crate::{
+    event::Event,
+    util::{url_with_token, verify_access_token, ContentType},
+    value_map,
+};
 use colored::*;
-use tokio::net::TcpStream;
+use std::time::Duration;
+use tokio::time::Instant;
 use tokio_tungstenite::tungstenite::handshake::client::{generate_key, Request, Response};
 use tokio_tungstenite::tungstenite::http::{
     request::Builder as HttpReqBuilder, response::Builder as HttpRespBuilder, Response as HttpResp,
     Uri,
 };
+use tokio_tungstenite::tungstenite::Message as WsMsg;
 use tokio_tungstenite::{accept_hdr_async, client_async, WebSocketStream};
 use tracing::{info, warn};
 
-use crate::config::WebSocketClient;
+use crate::config::{AccessToken, Backoff, Keepalive, Scope, WebSocketClient};
+
+const MAX_DELAY: Duration = Duration::from_secs(Backoff::MAX_DELAY as u64);
+
+/// 按退避设置计算重连等待时间
+pub(crate) struct BackoffTimer<'a> {
+    config: &'a Backoff,
+    attempts: u32,
+}
+
+impl<'a> BackoffTimer<'a> {
+    pub(crate) fn new(config: &'a Backoff) -> Self {
+        Self {
+            config,
+            attempts: 0,
+        }
+    }
+
+    /// 连接成功后重置
+    pub(crate) fn reset(&mut self) {
+        self.attempts = 0;
+    }
+
+    pub(crate) fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// 下一次重连前的等待时间，超出重连次数上限时返回 None
+    pub(crate) fn next_delay(&mut self) -> Option<Duration> {
+        if let Some(max) = self.config.max_attempts {
+            if self.attempts >= max {
+                return None;
+            }
+        }
+        let exp = self.config.multiplier.max(1.0).powi(self.attempts as i32);
+        let delay = (self.config.initial * exp).min(self.config.max).max(0.0);
+        // random factor in [-jitter, jitter]
+        let jitter = self.config.jitter.clamp(0.0, 1.0) * (random_f64() * 2.0 - 1.0);
+        self.attempts += 1;
+        // unchecked config may be NaN or too large for a Duration
+        let delay = Duration::try_from_secs_f64(delay * (1.0 + jitter)).unwrap_or(MAX_DELAY);
+        Some(delay.min(MAX_DELAY))
+    }
+}
+
+fn random_f64() -> f64 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(crate::util::timestamp_nano());
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// WebSocket 连接心跳计时
+pub(crate) struct KeepaliveTimer {
+    interval: Duration,
+    timeout: Duration,
+    next_ping: Instant,
+    deadline: Option<Instant>,
+}
+
+/// 心跳计时结果
+pub(crate) enum KeepaliveTick {
+    /// 需要发送 Ping
+    Ping,
+    /// 对端未在超时时间内响应
+    Timeout,
+}
+
+impl KeepaliveTimer {
+    pub(crate) fn new(config: &Keepalive) -> Self {
+        let interval = Duration::from_secs(config.ping_interval);
+        Self {
+            interval,
+            timeout: Duration::from_secs(config.pong_timeout),
+            next_ping: Instant::now() + interval,
+            deadline: None,
+        }
+    }
+
+    /// 收到对端任意消息
+    pub(crate) fn alive(&mut self) {
+        self.deadline = None;
+    }
+
+    /// 等待下一次心跳事件，未启用时永不返回
+    pub(crate) async fn tick(&mut self) -> KeepaliveTick {
+        if self.interval.is_zero() {
+            return std::future::pending().await;
+        }
+        match self.deadline {
+            Some(deadline) if deadline <= self.next_ping => {
+                tokio::time::sleep_until(deadline).await;
+                KeepaliveTick::Timeout
+            }
+            _ => {
+                tokio::time::sleep_until(self.next_ping).await;
+                self.next_ping += self.interval;
+                if self.deadline.is_none() && !self.timeout.is_zero() {
+                    self.deadline = Some(Instant::now() + self.timeout);
+                }
+                KeepaliveTick::Ping
+            }
+        }
+    }
+}
+
+/// WebSocket 连接编码
+///
+/// 未指定编码时，以对端首个数据帧的类型为准，此前默认使用 json
+pub(crate) struct WsContentType {
+    content_type: ContentType,
+    fixed: bool,
+}
+
+impl WsContentType {
+    pub(crate) fn new(config: Option<ContentType>) -> Self {
+        Self {
+            content_type: config.unwrap_or(ContentType::Json),
+            fixed: config.is_some(),
+        }
+    }
+
+    pub(crate) fn get(&self) -> ContentType {
+        self.content_type
+    }
+
+    /// 本次确定了编码时返回 true
+    pub(crate) fn detect(&mut self, msg: &WsMsg) -> bool {
+        if self.fixed {
+            return false;
+        }
+        match msg {
+            WsMsg::Text(_) => self.content_type = ContentType::Json,
+            WsMsg::Binary(_) => self.content_type = ContentType::MsgPack,
+            _ => return false,
+        }
+        self.fixed = true;
+        true
+    }
+}
+
+/// 等待下一次重连，放弃重连或收到关闭信号时返回 false
+///
+/// 启用 meta_event 时，重连信息会以 `walle.reconnect` meta 事件交由 `on_meta` 处理
+pub(crate) async fn wait_reconnect(
+    config: &WebSocketClient,
+    timer: &mut BackoffTimer<'_>,
+    stop: &mut Stop,
+    (implt, platform): (&str, &str),
+    on_meta: impl FnOnce(Event),
+) -> bool {
+    let delay = match timer.next_delay() {
+        Some(delay) => delay,
+        None => {
+            warn!(
+                target: OBC,
+                "Give up reconnecting to {} after {} attempts",
+                config.url,
+                timer.attempts()
+            );
+            return false;
+        }
+    };
+    info!(
+        target: OBC,
+        "Reconnect to {} in {:.1} seconds (attempt {})",
+        config.url,
+        delay.as_secs_f64(),
+        timer.attempts()
+    );
+    if config.reconnect.meta_event {
+        on_meta(meta_event(
+            implt,
+            platform,
+            "walle.reconnect",
+            value_map! {
+                "url": config.url.clone(),
+                "attempt": timer.attempts(),
+                "delay": delay.as_secs_f64()
+            },
+        ));
+    }
+    tokio::select! {
+        _ = stop.recv() => false,
+        _ = tokio::time::sleep(delay) => true,
+    }
+}
 
 pub(crate) async fn try_connect(
     config: &WebSocketClient,
     req: HttpReqBuilder,
-) -> Option<WebSocketStream<TcpStream>> {
+) -> Option<WebSocketStream<Stream>> {
     fn err<E: std::fmt::Display>(
         config: &WebSocketClient,
         e: E,
-    ) -> Option<WebSocketStream<TcpStream>> {
+    ) -> Option<WebSocketStream<Stream>> {
         warn!(target: OBC, "connect to {} failed: {}", config.url, e);
-        info!(
-            target: OBC,
-            "Retry in {} seconds", config.reconnect_interval
-        );
         None
     }
-    let uri: Uri = config.url.parse().unwrap();
-    let addr = format!("{}:{}", uri.host().unwrap(), uri.port().unwrap());
+    let url = if config.token_in_query {
+        url_with_token(&config.url, &config.access_token)
+    } else {
+        config.url.clone()
+    };
+    let uri: Uri = match url.parse() {
+        Ok(uri) => uri,
+        Err(e) => return err(config, e),
+    };
+    let secure = uri.scheme_str() == Some("wss");
+    let domain = match uri.host() {
+        Some(domain) => domain.to_string(),
+        None => return err(config, "host is empty"),
+    };
+    let port = uri.port_u16().unwrap_or(if secure { 443 } else { 80 });
     let authority = match uri.authority() {
         Some(authority) => authority.as_str(),
         None => return err(config, "authority is empty"),
@@ -37,7 +245,11 @@ pub(crate) async fn try_connect(
         .map(|idx| authority.split_at(idx + 1).1)
         .unwrap_or_else(|| authority);
 
-    let stream = match TcpStream::connect(&addr).await {
+    let connector = match Connector::new(&config.tls, &config.unix) {
+        Ok(connector) => connector,
+        Err(e) => return err(config, e),
+    };
+    let stream = match connector.connect(&domain, port, secure).await {
         Ok(stream) => stream,
         Err(e) => return err(config, e),
     };
@@ -64,36 +276,30 @@ pub(crate) async fn try_connect(
     }
 }
 
+/// 升级为 WebSocket 连接，同时返回连接所用 access_token 的权限范围
 pub(crate) async fn upgrade_websocket(
-    access_token: &Option<String>,
-    stream: TcpStream,
-) -> Option<WebSocketStream<TcpStream>> {
-    let addr = match stream.peer_addr() {
-        Ok(addr) => addr,
+    acceptor: &Acceptor,
+    access_tokens: &[AccessToken],
+    stream: Stream,
+    addr: &str,
+) -> Option<(WebSocketStream<Stream>, Scope)> {
+    let stream = match acceptor.accept(stream).await {
+        Ok(stream) => stream,
         Err(e) => {
-            warn!(target: OBC, "Upgrade websocket failed: {}", e);
+            warn!(target: OBC, "TLS handshake with {} failed: {}", addr, e);
             return None;
         }
     };
 
+    let mut scope = Scope::default();
     let callback = |req: &Request, resp: Response| -> Result<Response, HttpResp<Option<String>>> {
-        let headers = req.headers();
-        if let Some(token) = access_token {
-            match headers.get("Authorization").and_then(|a| a.to_str().ok()) {
-                Some(auth) => {
-                    if auth.strip_prefix("Bearer ") != Some(token) {
-                        return Err(HttpRespBuilder::new()
-                            .status(403)
-                            .body(Some("Authorization Header is invalid".to_string()))
-                            .unwrap());
-                    }
-                }
-                None => {
-                    return Err(HttpRespBuilder::new()
-                        .status(403)
-                        .body(Some("Missing Authorization Header".to_string()))
-                        .unwrap())
-                }
+        match verify_access_token(access_tokens, req.headers(), req.uri()) {
+            Ok(s) => scope = s,
+            Err(msg) => {
+                return Err(HttpRespBuilder::new()
+                    .status(403)
+                    .body(Some(msg.to_string()))
+                    .unwrap())
             }
         }
         info!(
@@ -107,7 +313,7 @@ pub(crate) async fn upgrade_websocket(
     match accept_hdr_async(stream, callback).await {
         Ok(s) => {
             info!(target: OBC, "New websocket client connected from {}", addr);
-            Some(s)
+            Some((s, scope))
         }
         Err(e) => {
             info!(target: OBC, "Upgrade websocket from {} failed: {}", addr, e);
@@ -115,3 +321,117 @@ pub(crate) async fn upgrade_websocket(
         }
     }
 }
+
+#[test]
+fn backoff_test() {
+    let config = Backoff {
+        initial: 1.0,
+        max: 5.0,
+        multiplier: 2.0,
+        jitter: 0.0,
+        max_attempts: Some(4),
+        meta_event: false,
+    };
+    let mut timer = BackoffTimer::new(&config);
+    let delays: Vec<u64> = std::iter::from_fn(|| timer.next_delay())
+        .map(|d| d.as_secs())
+        .collect();
+    assert_eq!(delays, vec![1, 2, 4, 5]);
+    timer.reset();
+    assert_eq!(timer.next_delay(), Some(Duration::from_secs(1)));
+
+    for max in [f64::INFINITY, f64::NAN] {
+        let config = Backoff {
+            initial: f64::MAX,
+            max,
+            ..config.clone()
+        };
+        let mut timer = BackoffTimer::new(&config);
+        assert_eq!(timer.next_delay(), Some(MAX_DELAY));
+    }
+}
+
+#[test]
+fn keepalive_test() {
+    let timer = |interval: u64, timeout: u64| {
+        let interval = Duration::from_millis(interval);
+        KeepaliveTimer {
+            interval,
+            timeout: Duration::from_millis(timeout),
+            next_ping: Instant::now() + interval,
+            deadline: None,
+        }
+    };
+    let is_ping = |tick: KeepaliveTick| matches!(tick, KeepaliveTick::Ping);
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    rt.block_on(async {
+        // ping every interval while the peer answers
+        let start = Instant::now();
+        let mut t = timer(40, 20);
+        for n in 1..=2 {
+            assert!(is_ping(t.tick().await));
+            assert!(start.elapsed() >= Duration::from_millis(40 * n));
+            t.alive();
+        }
+        // no pong within timeout after a ping
+        assert!(is_ping(t.tick().await));
+        assert!(matches!(t.tick().await, KeepaliveTick::Timeout));
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(140), "{:?}", elapsed);
+
+        // timeout longer than interval keeps pinging until the deadline
+        let mut t = timer(20, 50);
+        for _ in 0..3 {
+            assert!(is_ping(t.tick().await));
+        }
+        assert!(matches!(t.tick().await, KeepaliveTick::Timeout));
+
+        // disabled
+        let mut t = KeepaliveTimer::new(&Keepalive {
+            ping_interval: 0,
+            pong_timeout: 10,
+        });
+        assert!(tokio::time::timeout(Duration::from_millis(50), t.tick())
+            .await
+            .is_err());
+    });
+}
+
+#[test]
+fn token_query_test() {
+    use tokio::io::AsyncReadExt;
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    rt.block_on(async {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/onebot", listener.local_addr().unwrap());
+        for token_in_query in [false, true] {
+            let config = WebSocketClient {
+                url: url.clone(),
+                access_token: Some("secret".to_string()),
+                token_in_query,
+                ..Default::default()
+            };
+            let (_, request) = tokio::join!(try_connect(&config, Request::builder()), async {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0; 1024];
+                let n = stream.read(&mut buf).await.unwrap();
+                String::from_utf8_lossy(&buf[..n]).into_owned()
+            });
+            let line = request.lines().next().unwrap();
+            assert_eq!(
+                line.contains("access_token=secret"),
+                token_in_query,
+                "{}",
+                line
+            );
+        }
+    });
+}
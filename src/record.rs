@@ -0,0 +1,594 @@
+//! 流量录制与确定性回放
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{Read, Write};
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+use crate::{
+    error::{WalleError, WalleResult},
+    lifecycle::Lifecycle,
+    resp::{resp_error, RespError},
+    structs::Status,
+    util::{timestamp_nano_f64, ContentType, SelfId, SelfIds, Value},
+    ActionHandler, EventHandler, GetStatus, OneBot, WALLE_CORE,
+};
+
+/// 一条录制记录
+///
+/// Action 与 Resp 通过录制时分配的 seq 配对，conn_id 在实现端为收到 Action 的连接，
+/// 在应用端为 Bot 所在的连接（实现端的 Event 推送至全部连接，不记录 conn_id）；
+/// echo 为 Action 在线路上实际使用的 echo，实现端为收到时携带的 echo，
+/// 应用端在发出时才确定，仅记录于 Resp，未经网络传输时为空
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Record<E, A, R> {
+    Event {
+        time: f64,
+        conn_id: Option<u64>,
+        event: E,
+    },
+    Action {
+        time: f64,
+        conn_id: Option<u64>,
+        seq: u64,
+        #[serde(default)]
+        echo: Option<Value>,
+        action: A,
+    },
+    Resp {
+        time: f64,
+        conn_id: Option<u64>,
+        seq: u64,
+        #[serde(default)]
+        echo: Option<Value>,
+        resp: R,
+    },
+}
+
+/// 写入一条记录，json 格式每行一条，msgpack 格式首尾相接
+pub fn write_record<W, E, A, R>(
+    writer: &mut W,
+    record: &Record<E, A, R>,
+    content_type: ContentType,
+) -> WalleResult<()>
+where
+    W: Write + ?Sized,
+    E: Serialize,
+    A: Serialize,
+    R: Serialize,
+{
+    match content_type {
+        ContentType::Json => {
+            serde_json::to_writer(&mut *writer, record)
+                .map_err(|e| WalleError::Other(e.to_string()))?;
+            writer.write_all(b"\n")?;
+        }
+        ContentType::MsgPack => rmp_serde::encode::write_named(writer, record)
+            .map_err(|e| WalleError::Other(e.to_string()))?,
+    }
+    Ok(())
+}
+
+/// 读取全部记录
+pub fn read_records<Rd, E, A, R>(
+    mut reader: Rd,
+    content_type: ContentType,
+) -> WalleResult<Vec<Record<E, A, R>>>
+where
+    Rd: Read,
+    E: for<'de> Deserialize<'de>,
+    A: for<'de> Deserialize<'de>,
+    R: for<'de> Deserialize<'de>,
+{
+    let mut buf = vec![];
+    reader.read_to_end(&mut buf)?;
+    let mut records = vec![];
+    match content_type {
+        ContentType::Json => {
+            for line in buf.split(|b| *b == b'\n') {
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+                records.push(
+                    serde_json::from_slice(line).map_err(|e| WalleError::Other(e.to_string()))?,
+                );
+            }
+        }
+        ContentType::MsgPack => {
+            let mut cursor = std::io::Cursor::new(&buf);
+            while (cursor.position() as usize) < buf.len() {
+                records.push(
+                    rmp_serde::decode::from_read(&mut cursor)
+                        .map_err(|e| WalleError::Other(e.to_string()))?,
+                );
+            }
+        }
+    }
+    Ok(records)
+}
+
+/// 写入任务持有的输出与记录队列，停止后交还以便再次启动
+type RecordWriter = (
+    Box<dyn Write + Send>,
+    mpsc::UnboundedReceiver<Option<Vec<u8>>>,
+);
+
+/// 录制层，包装 ActionHandler 并记录经过 OneBot 的全部 Event、Action 与 Resp
+///
+/// 记录在调用方编码后交由写入任务落盘，写入任务随 OneBot 启动，关闭时写完剩余记录
+///
+/// 可通过 Deref 访问被包装的 ActionHandler
+pub struct Recorder<H> {
+    pub inner: H,
+    content_type: ContentType,
+    seq: AtomicU64,
+    conns: Mutex<HashMap<String, u64>>,
+    tx: mpsc::UnboundedSender<Option<Vec<u8>>>,
+    writer: Mutex<Option<RecordWriter>>,
+    task: Mutex<Option<JoinHandle<RecordWriter>>>,
+}
+
+impl<H> Recorder<H> {
+    pub fn new<W>(inner: H, writer: W, content_type: ContentType) -> Self
+    where
+        W: Write + Send + 'static,
+    {
+        let (tx, rx) = mpsc::unbounded_channel();
+        Self {
+            inner,
+            content_type,
+            seq: AtomicU64::new(1),
+            conns: Mutex::default(),
+            tx,
+            writer: Mutex::new(Some((Box::new(writer), rx))),
+            task: Mutex::default(),
+        }
+    }
+
+    /// 录制到文件，文件已存在时追加
+    pub fn create<P: AsRef<Path>>(
+        inner: H,
+        path: P,
+        content_type: ContentType,
+    ) -> WalleResult<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self::new(inner, file, content_type))
+    }
+
+    fn conn_id(&self, self_id: &str) -> Option<u64> {
+        self.conns.lock().unwrap().get(self_id).copied()
+    }
+
+    fn write<E, A, R>(&self, record: &Record<E, A, R>)
+    where
+        E: Serialize,
+        A: Serialize,
+        R: Serialize,
+    {
+        let mut buf = vec![];
+        match write_record(&mut buf, record, self.content_type) {
+            Ok(_) => {
+                self.tx.send(Some(buf)).ok();
+            }
+            Err(e) => warn!(target: WALLE_CORE, "encode record failed: {}", e),
+        }
+    }
+
+    /// 启动写入任务，已启动时不做任何事
+    fn start_writer(&self) {
+        let (mut writer, mut rx) = match self.writer.lock().unwrap().take() {
+            Some(writer) => writer,
+            None => return,
+        };
+        let task = tokio::task::spawn_blocking(move || {
+            let log = |r: std::io::Result<()>| {
+                if let Err(e) = r {
+                    warn!(target: WALLE_CORE, "write record failed: {}", e);
+                }
+            };
+            loop {
+                let data = match rx.try_recv() {
+                    Ok(data) => data,
+                    // 暂无新记录时再落盘
+                    Err(mpsc::error::TryRecvError::Empty) => {
+                        log(writer.flush());
+                        rx.blocking_recv().flatten()
+                    }
+                    Err(mpsc::error::TryRecvError::Disconnected) => None,
+                };
+                match data {
+                    Some(data) => log(writer.write_all(&data)),
+                    None => break,
+                }
+            }
+            log(writer.flush());
+            (writer, rx)
+        });
+        *self.task.lock().unwrap() = Some(task);
+    }
+
+    /// 写完已产生的记录并停止写入任务，可再次启动
+    async fn stop_writer(&self) {
+        let task = self.task.lock().unwrap().take();
+        if let Some(task) = task {
+            self.tx.send(None).ok();
+            match task.await {
+                Ok(writer) => *self.writer.lock().unwrap() = Some(writer),
+                Err(e) => warn!(target: WALLE_CORE, "record writer panicked: {}", e),
+            }
+        }
+    }
+}
+
+impl<H> Deref for Recorder<H> {
+    type Target = H;
+    fn deref(&self) -> &H {
+        &self.inner
+    }
+}
+
+#[async_trait]
+impl<H: SelfIds + Send + Sync> SelfIds for Recorder<H> {
+    async fn self_ids(&self) -> Vec<String> {
+        self.inner.self_ids().await
+    }
+}
+
+impl<H: GetStatus> GetStatus for Recorder<H> {
+    fn get_status(&self) -> Status {
+        self.inner.get_status()
+    }
+    fn get_bot_status(&self, self_id: &str) -> Status {
+        self.inner.get_bot_status(self_id)
+    }
+}
+
+#[async_trait]
+impl<H, E, A, R> ActionHandler<E, A, R> for Recorder<H>
+where
+    H: ActionHandler<E, A, R> + Send + Sync + 'static,
+    H::Config: Send + 'static,
+    E: SelfId + Serialize + Send + 'static,
+    A: SelfId + Serialize + Send + 'static,
+    R: Serialize + Send + 'static,
+{
+    type Config = H::Config;
+    async fn start<AH, EH>(
+        &self,
+        ob: &Arc<OneBot<AH, EH>>,
+        config: Self::Config,
+    ) -> WalleResult<Vec<JoinHandle<()>>>
+    where
+        AH: ActionHandler<E, A, R> + Send + Sync + 'static,
+        EH: EventHandler<E, A, R> + Send + Sync + 'static,
+    {
+        self.start_writer();
+        self.inner.start(ob, config).await
+    }
+    async fn call(&self, action: A) -> WalleResult<R> {
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "impl-obc")]
+        let conn_id = crate::util::RECV_CONN
+            .try_with(|c| *c)
+            .ok()
+            .or_else(|| self.conn_id(&action.self_id()));
+        #[cfg(not(feature = "impl-obc"))]
+        let conn_id = self.conn_id(&action.self_id());
+        #[cfg(feature = "impl-obc")]
+        let echo = crate::util::RECV_ECHO
+            .try_with(|e| e.0.clone())
+            .ok()
+            .flatten();
+        #[cfg(not(feature = "impl-obc"))]
+        let echo = None;
+        self.write::<(), _, ()>(&Record::Action {
+            time: timestamp_nano_f64(),
+            conn_id,
+            seq,
+            echo: echo.clone(),
+            action: &action,
+        });
+        #[cfg(feature = "app-obc")]
+        let (resp, echo) = {
+            let sent = std::cell::RefCell::new(None);
+            let (resp, sent) = crate::util::SENT_ECHO
+                .scope(sent, async {
+                    let resp = self.inner.call(action).await;
+                    (resp, crate::util::SENT_ECHO.with(|e| e.take()))
+                })
+                .await;
+            (resp?, sent.map(|e| e.0).unwrap_or(echo))
+        };
+        #[cfg(not(feature = "app-obc"))]
+        let resp = self.inner.call(action).await?;
+        self.write::<(), (), _>(&Record::Resp {
+            time: timestamp_nano_f64(),
+            conn_id,
+            seq,
+            echo,
+            resp: &resp,
+        });
+        Ok(resp)
+    }
+    async fn before_call_event(&self, event: E) -> WalleResult<E>
+    where
+        E: Send + 'static,
+    {
+        self.write::<_, (), ()>(&Record::Event {
+            time: timestamp_nano_f64(),
+            conn_id: self.conn_id(&event.self_id()),
+            event: &event,
+        });
+        self.inner.before_call_event(event).await
+    }
+    async fn after_call_event(&self) -> WalleResult<()> {
+        self.inner.after_call_event().await
+    }
+    async fn on_lifecycle(&self, lifecycle: &Lifecycle) {
+        match lifecycle {
+            Lifecycle::BotOnline {
+                self_id, conn_id, ..
+            } => {
+                self.conns.lock().unwrap().insert(self_id.clone(), *conn_id);
+            }
+            Lifecycle::BotOffline { self_id, .. } => {
+                self.conns.lock().unwrap().remove(self_id);
+            }
+            _ => {}
+        }
+        self.inner.on_lifecycle(lifecycle).await
+    }
+    async fn shutdown(&self) {
+        self.inner.shutdown().await;
+        self.stop_writer().await
+    }
+}
+
+/// 回放与录制不一致之处
+#[derive(Debug, Clone, PartialEq)]
+pub enum Divergence<A> {
+    /// 录制中不存在的 Action
+    Unexpected(A),
+    /// 录制中存在但回放时未发出的 Action
+    Missing(A),
+    /// 发出顺序与录制不同的 Action
+    Reordered(A),
+}
+
+/// 录制中的一次 Action 调用
+struct RecordedCall<A, R> {
+    index: usize,
+    action: A,
+    key: serde_json::Value,
+    resp: Option<R>,
+}
+
+/// 回放器，作为 ActionHandler 将录制的 Event 依次交由 EventHandler 处理，
+/// 并以录制的 Resp 响应 Action
+///
+/// 每个 Event 推送前，会先等待录制中位于其前的 Action 被发出（至多等待 action_timeout）；
+/// OneBot 关闭后再次启动时从停止处继续回放
+pub struct Replayer<E, A, R> {
+    pub action_timeout: Duration,
+    events: Arc<tokio::sync::Mutex<VecDeque<(E, usize)>>>,
+    calls: Arc<Mutex<VecDeque<RecordedCall<A, R>>>>,
+    expected: AtomicUsize,
+    total: usize,
+    self_ids: Vec<String>,
+    divergences: Arc<Mutex<Vec<Divergence<A>>>>,
+    matched: watch::Sender<usize>,
+    done: Arc<watch::Sender<bool>>,
+}
+
+impl<E, A, R> Replayer<E, A, R>
+where
+    E: SelfId,
+    A: Serialize,
+{
+    pub fn new(records: Vec<Record<E, A, R>>) -> Self {
+        let mut events = VecDeque::new();
+        let mut calls = VecDeque::new();
+        let mut seqs = HashMap::new();
+        let mut self_ids = HashSet::new();
+        for record in records {
+            match record {
+                Record::Event { event, .. } => {
+                    self_ids.insert(event.self_id());
+                    events.push_back((event, calls.len()));
+                }
+                Record::Action { seq, action, .. } => {
+                    seqs.insert(seq, calls.len());
+                    calls.push_back(RecordedCall {
+                        index: calls.len(),
+                        key: serde_json::to_value(&action).unwrap_or_default(),
+                        action,
+                        resp: None,
+                    });
+                }
+                Record::Resp { seq, resp, .. } => {
+                    if let Some(call) = seqs.get(&seq).and_then(|i| calls.get_mut(*i)) {
+                        call.resp = Some(resp);
+                    }
+                }
+            }
+        }
+        let total = calls.len();
+        Self {
+            action_timeout: Duration::from_secs(1),
+            events: Arc::new(tokio::sync::Mutex::new(events)),
+            calls: Arc::new(Mutex::new(calls)),
+            expected: AtomicUsize::new(0),
+            total,
+            self_ids: self_ids.into_iter().collect(),
+            divergences: Arc::default(),
+            matched: watch::channel(0).0,
+            done: Arc::new(watch::channel(false).0),
+        }
+    }
+
+    /// 从录制文件构造
+    pub fn open<P: AsRef<Path>>(path: P, content_type: ContentType) -> WalleResult<Self>
+    where
+        E: for<'de> Deserialize<'de>,
+        A: for<'de> Deserialize<'de>,
+        R: for<'de> Deserialize<'de>,
+    {
+        Ok(Self::new(read_records(
+            std::fs::File::open(path)?,
+            content_type,
+        )?))
+    }
+
+    /// 等待回放结束，返回全部不一致之处
+    pub async fn wait(&self) -> Vec<Divergence<A>>
+    where
+        A: Clone,
+    {
+        let mut done = self.done.subscribe();
+        while !*done.borrow() {
+            if done.changed().await.is_err() {
+                break;
+            }
+        }
+        self.divergences.lock().unwrap().clone()
+    }
+}
+
+/// 等待已匹配的 Action 数量达到 n
+async fn wait_matched(matched: &mut watch::Receiver<usize>, n: usize, timeout: Duration) {
+    let wait = async {
+        while *matched.borrow() < n {
+            if matched.changed().await.is_err() {
+                break;
+            }
+        }
+    };
+    tokio::time::timeout(timeout, wait).await.ok();
+}
+
+#[async_trait]
+impl<E, A, R> SelfIds for Replayer<E, A, R>
+where
+    E: Send,
+    A: Send,
+    R: Send,
+{
+    async fn self_ids(&self) -> Vec<String> {
+        self.self_ids.clone()
+    }
+}
+
+impl<E, A, R> GetStatus for Replayer<E, A, R> {
+    fn get_status(&self) -> Status {
+        Status {
+            good: true,
+            online: true,
+        }
+    }
+}
+
+#[async_trait]
+impl<E, A, R> ActionHandler<E, A, R> for Replayer<E, A, R>
+where
+    E: Send + 'static,
+    A: Serialize + Send + 'static,
+    R: From<RespError> + Send + 'static,
+{
+    type Config = ();
+    async fn start<AH, EH>(
+        &self,
+        ob: &Arc<OneBot<AH, EH>>,
+        _: (),
+    ) -> WalleResult<Vec<JoinHandle<()>>>
+    where
+        AH: ActionHandler<E, A, R> + Send + Sync + 'static,
+        EH: EventHandler<E, A, R> + Send + Sync + 'static,
+    {
+        let events = self.events.clone();
+        let mut signal_rx = ob.get_signal_rx()?;
+        let mut matched = self.matched.subscribe();
+        let timeout = self.action_timeout;
+        let total = self.total;
+        let calls = self.calls.clone();
+        let divergences = self.divergences.clone();
+        let done = self.done.clone();
+        let ob_ = ob.clone();
+        Ok(vec![ob.spawn(async move {
+            let mut events = events.lock().await;
+            while let Some((event, before)) = events.pop_front() {
+                tokio::select! {
+                    _ = signal_rx.recv() => {
+                        events.push_front((event, before));
+                        return;
+                    }
+                    _ = wait_matched(&mut matched, before, timeout) => {}
+                }
+                if let Err(e) = ob_.handle_event(event).await {
+                    warn!(target: WALLE_CORE, "replay event failed: {}", e);
+                }
+            }
+            wait_matched(&mut matched, total, timeout).await;
+            let missing = calls
+                .lock()
+                .unwrap()
+                .drain(..)
+                .map(|c| c.action)
+                .collect::<Vec<_>>();
+            divergences
+                .lock()
+                .unwrap()
+                .extend(missing.into_iter().map(Divergence::Missing));
+            done.send_modify(|done| *done = true);
+        })])
+    }
+    async fn call(&self, action: A) -> WalleResult<R> {
+        let key = serde_json::to_value(&action).unwrap_or_default();
+        let call = {
+            let mut calls = self.calls.lock().unwrap();
+            match calls.iter().position(|c| c.key == key) {
+                Some(i) => {
+                    let call = calls.remove(i).unwrap();
+                    // 早于已匹配 Action 的调用视为乱序，跳过的调用最终记为 Missing
+                    if call.index < self.expected.load(Ordering::Relaxed) {
+                        self.divergences
+                            .lock()
+                            .unwrap()
+                            .push(Divergence::Reordered(action));
+                    } else {
+                        self.expected.store(call.index + 1, Ordering::Relaxed);
+                    }
+                    Some(call)
+                }
+                None => {
+                    self.divergences
+                        .lock()
+                        .unwrap()
+                        .push(Divergence::Unexpected(action));
+                    None
+                }
+            }
+        };
+        match call {
+            Some(call) => {
+                self.matched.send_modify(|n| *n += 1);
+                call.resp
+                    .ok_or_else(|| WalleError::Other("no response recorded".to_string()))
+            }
+            None => Ok(resp_error::bad_request("action not in recording").into()),
+        }
+    }
+}
@@ -7,6 +7,47 @@ pub struct Status {
     pub online: bool,
 }
 
+/// `meta.status_update` 中的状态
+#[derive(Debug, Clone, PartialEq, Eq, PushToValueMap, OneBot)]
+#[value]
+pub struct BotsStatus {
+    pub good: bool,
+    pub bots: Vec<BotStatus>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BotStatus {
+    pub selft: Selft,
+    pub online: bool,
+}
+
+// 字段名 `self` 无法由 derive 生成
+impl From<BotStatus> for crate::util::Value {
+    fn from(bot: BotStatus) -> Self {
+        crate::value!({ "self": bot.selft, "online": bot.online })
+    }
+}
+
+impl TryFrom<crate::util::Value> for BotStatus {
+    type Error = crate::error::WalleError;
+    fn try_from(v: crate::util::Value) -> Result<Self, Self::Error> {
+        use crate::util::ValueMapExt;
+        let mut map = v.downcast_map()?;
+        Ok(Self {
+            selft: map.remove_downcast("self")?,
+            online: map.remove_downcast("online")?,
+        })
+    }
+}
+
+/// 机器人自身标识
+#[derive(Debug, Clone, PartialEq, Eq, PushToValueMap, OneBot)]
+#[value]
+pub struct Selft {
+    pub platform: String,
+    pub user_id: String,
+}
+
 #[derive(Debug, Clone, PartialEq, PushToValueMap, OneBot)]
 #[value]
 pub struct SendMessageResp {
@@ -3,6 +3,7 @@ use crate::{
     alt::ColoredAlt,
     error::WalleError,
     event::*,
+    resp::Resp,
     segment::*,
     structs::Status,
     util::{Value, ValueMap},
@@ -10,6 +11,96 @@ use crate::{
 };
 use walle_macro::{_OneBot as OneBot, _PushToValueMap as PushToValueMap};
 
+#[cfg(any(feature = "impl-obc", feature = "app-obc"))]
+fn rt() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+/// 将收到的事件转发到 channel
+#[cfg(any(feature = "impl-obc", feature = "app-obc"))]
+struct Recorder(tokio::sync::mpsc::UnboundedSender<Event>);
+
+#[cfg(any(feature = "impl-obc", feature = "app-obc"))]
+#[async_trait::async_trait]
+impl crate::EventHandler<Event, Action, Resp> for Recorder {
+    type Config = ();
+    async fn start<AH, EH>(
+        &self,
+        _: &std::sync::Arc<crate::OneBot<AH, EH>>,
+        _: (),
+    ) -> crate::WalleResult<Vec<tokio::task::JoinHandle<()>>>
+    where
+        AH: crate::ActionHandler<Event, Action, Resp> + Send + Sync + 'static,
+        EH: crate::EventHandler<Event, Action, Resp> + Send + Sync + 'static,
+    {
+        Ok(vec![])
+    }
+    async fn call(&self, event: Event) -> crate::WalleResult<()> {
+        self.0.send(event).ok();
+        Ok(())
+    }
+}
+
+/// 以 action 名作为响应的 bot
+#[cfg(any(feature = "impl-obc", feature = "app-obc"))]
+struct Echoer;
+
+#[cfg(any(feature = "impl-obc", feature = "app-obc"))]
+#[async_trait::async_trait]
+impl crate::util::SelfIds for Echoer {
+    async fn self_ids(&self) -> Vec<String> {
+        vec!["bot".to_string()]
+    }
+}
+
+#[cfg(any(feature = "impl-obc", feature = "app-obc"))]
+impl crate::GetStatus for Echoer {
+    fn get_status(&self) -> Status {
+        Status {
+            good: true,
+            online: true,
+        }
+    }
+}
+
+#[cfg(any(feature = "impl-obc", feature = "app-obc"))]
+#[async_trait::async_trait]
+impl crate::ActionHandler<Event, Action, Resp> for Echoer {
+    type Config = ();
+    async fn start<AH, EH>(
+        &self,
+        _: &std::sync::Arc<crate::OneBot<AH, EH>>,
+        _: (),
+    ) -> crate::WalleResult<Vec<tokio::task::JoinHandle<()>>>
+    where
+        AH: crate::ActionHandler<Event, Action, Resp> + Send + Sync + 'static,
+        EH: crate::EventHandler<Event, Action, Resp> + Send + Sync + 'static,
+    {
+        Ok(vec![])
+    }
+    async fn call(&self, action: Action) -> crate::WalleResult<Resp> {
+        Ok(value_map! { "action": action.action }.into())
+    }
+}
+
+/// 共享的输出缓冲
+#[cfg(any(feature = "impl-obc", feature = "app-obc"))]
+#[derive(Clone, Default)]
+struct Buf(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+#[cfg(any(feature = "impl-obc", feature = "app-obc"))]
+impl std::io::Write for Buf {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
 #[test]
 fn event() {
     fn test<T>(event: (&str, Event, T))
@@ -357,3 +448,1618 @@ fn option_action() {
         )
     )
 }
+
+#[test]
+fn echo() {
+    use crate::util::{Echo, EchoMap, EchoS};
+
+    let action: Echo<Action> = serde_json::from_str(
+        r#"{"action":"get_self_info","params":{},"echo":{"seq":1,"tag":"walle"}}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        action.get_echo(),
+        EchoS(Some(value!({"seq": 1, "tag": "walle"})))
+    );
+    let rmp = rmp_serde::to_vec(&action).unwrap();
+    assert_eq!(rmp_serde::from_slice::<Echo<Action>>(&rmp).unwrap(), action);
+
+    let map = EchoMap::<Resp>::default();
+    let timeout = std::time::Duration::from_secs(10);
+    let mut rx0 = map.insert(EchoS::new("0"), Some(0), timeout);
+    let mut rx1 = map.insert(EchoS::new("1"), Some(1), timeout);
+    assert_eq!(map.cancel_conn(0), 1);
+    assert!(matches!(rx0.try_recv(), Ok(Err(WalleError::Disconnected))));
+    assert!(rx1.try_recv().is_err());
+    assert_eq!(map.len(), 1);
+    let mut rx2 = map.insert(EchoS::new("2"), None, std::time::Duration::ZERO);
+    assert_eq!(map.clear_expired(), 1);
+    assert!(matches!(
+        rx2.try_recv(),
+        Ok(Err(WalleError::ResponseTimeout))
+    ));
+}
+
+#[cfg(all(
+    feature = "app-obc",
+    any(feature = "impl-obc", feature = "http", feature = "websocket")
+))]
+#[test]
+fn lifecycle_meta() {
+    use crate::{lifecycle::Lifecycle, obc::lifecycle_event, util::Transport};
+
+    let online = Lifecycle::BotOnline {
+        self_id: "123".to_string(),
+        conn_id: 0,
+        transport: Transport::WebSocket,
+    };
+    let event = lifecycle_event("walle", "qq", &online).unwrap();
+    assert_eq!(event.self_id, "123");
+    assert_eq!(
+        event.extra.get("status"),
+        Some(&value!({
+            "good": true,
+            "bots": [{"self": {"platform": "qq", "user_id": "123"}, "online": true}]
+        }))
+    );
+    let disconnect = Lifecycle::Disconnect {
+        conn_id: 0,
+        transport: Transport::WebSocket,
+        peer: None,
+    };
+    assert!(lifecycle_event("walle", "qq", &disconnect).is_none());
+}
+
+#[cfg(feature = "impl-obc")]
+#[test]
+fn heartbeat() {
+    use crate::{
+        config::{Heartbeat, ImplConfig},
+        obc::ImplOBC,
+        OneBot, WalleResult,
+    };
+    use std::sync::Arc;
+
+    /// bot a 在线，bot b 离线
+    struct Bots;
+
+    #[async_trait::async_trait]
+    impl crate::util::SelfIds for Bots {
+        async fn self_ids(&self) -> Vec<String> {
+            vec!["a".to_string(), "b".to_string()]
+        }
+    }
+
+    impl crate::GetStatus for Bots {
+        fn get_status(&self) -> Status {
+            Status {
+                good: true,
+                online: true,
+            }
+        }
+        fn get_bot_status(&self, self_id: &str) -> Status {
+            Status {
+                good: true,
+                online: self_id == "a",
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl crate::ActionHandler<Event, Action, Resp> for Bots {
+        type Config = ();
+        async fn start<AH, EH>(
+            &self,
+            _: &Arc<OneBot<AH, EH>>,
+            _: (),
+        ) -> WalleResult<Vec<tokio::task::JoinHandle<()>>>
+        where
+            AH: crate::ActionHandler<Event, Action, Resp> + Send + Sync + 'static,
+            EH: crate::EventHandler<Event, Action, Resp> + Send + Sync + 'static,
+        {
+            Ok(vec![])
+        }
+        async fn call(&self, _: Action) -> WalleResult<Resp> {
+            Ok(value_map! {}.into())
+        }
+    }
+
+    rt().block_on(async {
+        let ob = Arc::new(OneBot::new(
+            Bots,
+            ImplOBC::<Event>::new("walle".to_string(), "test".to_string()),
+        ));
+        let mut hb_rx = ob.event_handler.hb_tx.subscribe();
+        let config = ImplConfig {
+            heartbeat: Heartbeat {
+                enabled: true,
+                interval: 0,
+            },
+            ..Default::default()
+        };
+        ob.start((), config, true).await.unwrap();
+        // the first tick fires immediately, one heartbeat per bot
+        let mut hbs = [hb_rx.recv().await.unwrap(), hb_rx.recv().await.unwrap()];
+        hbs.sort_by(|a, b| a.self_id.cmp(&b.self_id));
+        for (hb, (self_id, online)) in hbs.iter().zip([("a", true), ("b", false)]) {
+            assert_eq!(hb.detail_type, "heartbeat");
+            assert_eq!(hb.self_id, self_id);
+            assert_eq!(hb.extra.get("interval"), Some(&Value::Int(4)));
+            assert_eq!(
+                hb.extra.get("status"),
+                Some(&value!({ "good": true, "online": online }))
+            );
+        }
+        ob.shutdown().await.unwrap();
+    });
+}
+
+#[cfg(all(feature = "impl-obc", feature = "websocket"))]
+#[test]
+fn meta_connect() {
+    use crate::{
+        config::{Heartbeat, ImplConfig, ImplEndpoint, WebSocketServer},
+        obc::ImplOBC,
+        util::SelfIds,
+        ActionHandler, EventHandler, GetStatus, OneBot, WalleResult,
+    };
+    use async_trait::async_trait;
+    use futures_util::StreamExt;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+    use tokio_tungstenite::tungstenite::{self, Message};
+
+    struct Toggle(AtomicBool);
+
+    #[async_trait]
+    impl SelfIds for Toggle {
+        async fn self_ids(&self) -> Vec<String> {
+            vec!["bot".to_string()]
+        }
+    }
+
+    impl GetStatus for Toggle {
+        fn get_status(&self) -> Status {
+            Status {
+                good: true,
+                online: self.0.load(Ordering::SeqCst),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ActionHandler<Event, Action, Resp> for Toggle {
+        type Config = ();
+        async fn start<AH, EH>(
+            &self,
+            _: &Arc<OneBot<AH, EH>>,
+            _: (),
+        ) -> WalleResult<Vec<tokio::task::JoinHandle<()>>>
+        where
+            AH: ActionHandler<Event, Action, Resp> + Send + Sync + 'static,
+            EH: EventHandler<Event, Action, Resp> + Send + Sync + 'static,
+        {
+            Ok(vec![])
+        }
+        async fn call(&self, _: Action) -> WalleResult<Resp> {
+            Ok(value_map! {}.into())
+        }
+    }
+
+    async fn next_event<S>(ws: &mut S) -> Event
+    where
+        S: futures_util::Stream<Item = Result<Message, tungstenite::Error>> + Unpin,
+    {
+        loop {
+            if let Message::Text(text) = ws.next().await.unwrap().unwrap() {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    rt().block_on(async {
+        let ob = Arc::new(OneBot::new(
+            Toggle(AtomicBool::new(true)),
+            ImplOBC::<Event>::new("walle".to_string(), "test".to_string()),
+        ));
+        let impl_config = ImplConfig {
+            http: vec![],
+            http_webhook: vec![],
+            websocket: vec![],
+            websocket_rev: vec![],
+            heartbeat: Heartbeat {
+                enabled: false,
+                interval: 0,
+            },
+        };
+        ob.start((), impl_config, true).await.unwrap();
+        let server = WebSocketServer {
+            port: 0,
+            ..Default::default()
+        };
+        ob.event_handler
+            .add_endpoint(&ob, ImplEndpoint::WebSocket(server))
+            .await
+            .unwrap();
+        let url = ob.event_handler.endpoints()[0].addr.clone();
+        let (mut ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+
+        // meta.connect 总是连接的第一个事件
+        let event: ConnectEvent = next_event(&mut ws).await.try_into().unwrap();
+        assert_eq!(event.detail_type.version.onebot_version, "12");
+        let message = Event {
+            id: "1".to_string(),
+            implt: "walle".to_string(),
+            platform: "test".to_string(),
+            self_id: "bot".to_string(),
+            time: 0.0,
+            ty: "message".to_string(),
+            detail_type: "private".to_string(),
+            sub_type: String::default(),
+            extra: value_map! { "message_id": "1" },
+        };
+        ob.handle_event(message).await.unwrap();
+        assert_eq!(next_event(&mut ws).await.id, "1");
+
+        ob.action_handler.0.store(false, Ordering::SeqCst);
+        let event = next_event(&mut ws).await;
+        assert_eq!(
+            event.extra.get("status"),
+            Some(&value!({
+                "good": true,
+                "bots": [{"self": {"platform": "test", "user_id": "bot"}, "online": false}]
+            }))
+        );
+        let event: StatusUpdateEvent = event.try_into().unwrap();
+        assert!(!event.detail_type.status.bots[0].online);
+
+        ob.shutdown().await.unwrap();
+    });
+}
+
+#[test]
+fn content_type() {
+    use crate::util::{ContentType, ProtocolItem};
+
+    assert_eq!(
+        serde_json::from_str::<ContentType>(r#""msgpack""#).unwrap(),
+        ContentType::MsgPack
+    );
+    let action = Action {
+        action: "get_self_info".to_string(),
+        params: ValueMap::default(),
+    };
+    for content_type in [ContentType::Json, ContentType::MsgPack] {
+        let data = match content_type {
+            ContentType::Json => action.json_encode().into_bytes(),
+            ContentType::MsgPack => action.rmp_encode(),
+        };
+        assert_eq!(Action::decode(&data, &content_type).unwrap(), action);
+    }
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn access_token() {
+    use crate::{config::AccessToken, util::verify_access_token};
+    use hyper::http::{header::AUTHORIZATION, HeaderMap, Uri};
+
+    let tokens = vec![
+        AccessToken::Token("old".to_string()),
+        serde_json::from_str(r#"{"token":"new token","actions":["get_*"]}"#).unwrap(),
+    ];
+    let mut headers = HeaderMap::new();
+    let root: Uri = "/".parse().unwrap();
+    assert_eq!(
+        verify_access_token(&tokens, &headers, &root),
+        Err("Missing access token")
+    );
+    assert!(verify_access_token(&[], &headers, &root).is_ok());
+    let query: Uri = "/?access_token=new%20token".parse().unwrap();
+    let scope = verify_access_token(&tokens, &headers, &query).unwrap();
+    assert!(scope.allow_action("get_self_info", ""));
+    assert!(!scope.allow_action("send_message", ""));
+    headers.insert(AUTHORIZATION, "Bearer old".parse().unwrap());
+    let scope = verify_access_token(&tokens, &headers, &root).unwrap();
+    assert!(scope.is_unrestricted());
+    headers.insert(AUTHORIZATION, "Bearer wrong".parse().unwrap());
+    assert_eq!(
+        verify_access_token(&tokens, &headers, &root),
+        Err("Access token is invalid")
+    );
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn signature() {
+    use crate::util::{
+        hmac_sha256, sign_body, timestamp_nano, verify_signature, SIGNATURE_HEADER,
+        TIMESTAMP_HEADER,
+    };
+    use hyper::http::HeaderMap;
+
+    // RFC 4231 test case 2
+    assert_eq!(
+        hex::encode(hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+    let body = br#"{"type":"meta"}"#;
+    let now = (timestamp_nano() / 1_000_000_000) as u64;
+    let mut headers = HeaderMap::new();
+    assert_eq!(
+        verify_signature("secret", 300, &headers, body),
+        Err("Missing signature")
+    );
+    headers.insert(
+        SIGNATURE_HEADER,
+        sign_body("secret", now, body).parse().unwrap(),
+    );
+    headers.insert(TIMESTAMP_HEADER, now.to_string().parse().unwrap());
+    assert!(verify_signature("secret", 300, &headers, body).is_ok());
+    assert_eq!(
+        verify_signature("other", 300, &headers, body),
+        Err("Signature is invalid")
+    );
+    let old = now - 600;
+    headers.insert(
+        SIGNATURE_HEADER,
+        sign_body("secret", old, body).parse().unwrap(),
+    );
+    headers.insert(TIMESTAMP_HEADER, old.to_string().parse().unwrap());
+    assert_eq!(
+        verify_signature("secret", 300, &headers, body),
+        Err("Signature expired")
+    );
+}
+
+#[cfg(all(feature = "impl-obc", feature = "app-obc"))]
+#[test]
+fn loopback() {
+    use crate::{
+        config::{AppConfig, Heartbeat, ImplConfig},
+        obc::{loopback, AppOBC, BotMapExt, ImplOBC},
+        util::Transport,
+        OneBot,
+    };
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+
+    rt().block_on(async {
+        let impl_ob = Arc::new(OneBot::new(
+            Echoer,
+            ImplOBC::<Event>::new("walle".to_string(), "test".to_string()),
+        ));
+        let impl_config = ImplConfig {
+            http: vec![],
+            http_webhook: vec![],
+            websocket: vec![],
+            websocket_rev: vec![],
+            heartbeat: Heartbeat {
+                enabled: true,
+                interval: 1,
+            },
+        };
+        impl_ob.start((), impl_config, true).await.unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let app_ob = Arc::new(OneBot::new(AppOBC::new(), Recorder(tx)));
+        let app_config = AppConfig {
+            websocket_rev: vec![],
+            ..Default::default()
+        };
+        app_ob.start(app_config, (), true).await.unwrap();
+        loopback(&impl_ob, &app_ob).unwrap();
+
+        let connect = rx.recv().await.unwrap();
+        assert_eq!(connect.detail_type, "connect");
+        let hb = rx.recv().await.unwrap();
+        assert_eq!(
+            (hb.detail_type.as_str(), hb.self_id.as_str()),
+            ("heartbeat", "bot")
+        );
+        assert_eq!(
+            app_ob.action_handler.bots.get_bot("bot").unwrap()[0].transport,
+            Transport::Loopback
+        );
+        assert!(app_ob.action_handler.bots.get_bot("").is_none());
+
+        let action = Action {
+            action: "get_self_info".to_string(),
+            params: value_map! { "self_id": "bot" },
+        };
+        let resp = app_ob.handle_action(action).await.unwrap();
+        assert_eq!(resp.data, value!({ "action": "get_self_info" }));
+        assert_eq!(app_ob.action_handler.pending_echos(), 0);
+
+        impl_ob.shutdown().await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        assert!(app_ob.action_handler.bots.get_bot("bot").is_none());
+        app_ob.shutdown().await.unwrap();
+    });
+}
+
+/// 应用端生命周期 meta 事件带有对端的实现与平台名称
+#[cfg(all(feature = "impl-obc", feature = "app-obc"))]
+#[test]
+fn loopback_lifecycle_meta() {
+    use crate::{
+        config::{AppConfig, Heartbeat, ImplConfig},
+        obc::{loopback, AppOBC, ImplOBC},
+        OneBot,
+    };
+    use std::{sync::Arc, time::Duration};
+    use tokio::sync::mpsc;
+
+    rt().block_on(async {
+        let impl_ob = Arc::new(OneBot::new(
+            Echoer,
+            ImplOBC::<Event>::new("walle".to_string(), "test".to_string()),
+        ));
+        let impl_config = ImplConfig {
+            http: vec![],
+            http_webhook: vec![],
+            websocket: vec![],
+            websocket_rev: vec![],
+            heartbeat: Heartbeat {
+                enabled: true,
+                interval: 1,
+            },
+        };
+        impl_ob.start((), impl_config, true).await.unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let app_ob = Arc::new(OneBot::new(AppOBC::new(), Recorder(tx)));
+        let app_config = AppConfig {
+            websocket_rev: vec![],
+            lifecycle_meta: true,
+            ..Default::default()
+        };
+        app_ob.start(app_config, (), true).await.unwrap();
+        loopback(&impl_ob, &app_ob).unwrap();
+
+        let (mut connect, mut online) = (None, None);
+        while connect.is_none() || online.is_none() {
+            let event = tokio::time::timeout(Duration::from_secs(3), rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            match event.detail_type.as_str() {
+                "connect" if event.extra.contains_key("conn_id") => connect = Some(event),
+                "status_update" => online = Some(event),
+                _ => {}
+            }
+        }
+        for event in [connect.unwrap(), online.unwrap()] {
+            assert_eq!(
+                (event.implt.as_str(), event.platform.as_str()),
+                ("walle", "test")
+            );
+        }
+
+        impl_ob.shutdown().await.unwrap();
+        app_ob.shutdown().await.unwrap();
+    });
+}
+
+#[cfg(all(feature = "impl-obc", feature = "app-obc"))]
+#[test]
+fn mock() {
+    use crate::{
+        config::{AppConfig, Heartbeat, ImplConfig},
+        obc::{loopback, mock_event, AppOBC, MockOneBot},
+        OneBot,
+    };
+    use std::{sync::Arc, time::Duration};
+    use tokio::sync::mpsc;
+
+    rt().block_on(async {
+        let mock = Arc::new(MockOneBot::mock("bot", "test"));
+        mock.action_handler
+            .expect("send_message", value_map! { "message_id": "1" })
+            .stub("get_self_info", value_map! { "user_id": "bot" });
+        let impl_config = ImplConfig {
+            http: vec![],
+            http_webhook: vec![],
+            websocket: vec![],
+            websocket_rev: vec![],
+            heartbeat: Heartbeat {
+                enabled: false,
+                interval: 0,
+            },
+        };
+        mock.start((), impl_config, true).await.unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let app_ob = Arc::new(OneBot::new(AppOBC::new(), Recorder(tx)));
+        let app_config = AppConfig {
+            websocket_rev: vec![],
+            ..Default::default()
+        };
+        app_ob.start(app_config, (), true).await.unwrap();
+        loopback(&mock, &app_ob).unwrap();
+        assert_eq!(rx.recv().await.unwrap().detail_type, "connect");
+
+        mock.push_event(mock_event(
+            Message {
+                message_id: "0".to_string(),
+                message: "hello".to_string().into_message(),
+                alt_message: "hello".to_string(),
+                user_id: "user".to_string(),
+            },
+            Private {},
+            (),
+        ))
+        .await
+        .unwrap();
+        let event = rx.recv().await.unwrap();
+        assert_eq!(
+            (event.self_id.as_str(), event.platform.as_str()),
+            ("bot", "test")
+        );
+        let event: PrivateMessageEvent = event.try_into().unwrap();
+        assert_eq!(event.ty.user_id, "user");
+
+        let send = |action: &str| Action {
+            action: action.to_string(),
+            params: value_map! { "self_id": "bot" },
+        };
+        let resp = app_ob.handle_action(send("send_message")).await.unwrap();
+        assert_eq!(resp.data, value!({ "message_id": "1" }));
+        let resp = app_ob.handle_action(send("send_message")).await.unwrap();
+        assert_eq!(resp.retcode, 10002);
+        for _ in 0..2 {
+            let resp = app_ob.handle_action(send("get_self_info")).await.unwrap();
+            assert_eq!(resp.data, value!({ "user_id": "bot" }));
+        }
+        mock.action_handler.assert_expectations();
+        let first = mock
+            .action_handler
+            .next_action(Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(first.action, "send_message");
+        assert_eq!(mock.action_handler.actions().len(), 4);
+
+        mock.shutdown().await.unwrap();
+        app_ob.shutdown().await.unwrap();
+    });
+}
+
+#[cfg(all(feature = "impl-obc", feature = "app-obc"))]
+#[test]
+fn fake_platform() {
+    use crate::{
+        config::{AppConfig, Heartbeat, ImplConfig},
+        obc::{loopback, AppOBC, FakeOneBot},
+        OneBot,
+    };
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+
+    rt().block_on(async {
+        let fake = Arc::new(FakeOneBot::fake("bot", "test"));
+        let platform = &fake.action_handler;
+        platform.add_group("g1", "group");
+        let impl_config = ImplConfig {
+            http: vec![],
+            http_webhook: vec![],
+            websocket: vec![],
+            websocket_rev: vec![],
+            heartbeat: Heartbeat {
+                enabled: false,
+                interval: 0,
+            },
+        };
+        fake.start((), impl_config, true).await.unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let app_ob = Arc::new(OneBot::new(AppOBC::new(), Recorder(tx)));
+        let app_config = AppConfig {
+            websocket_rev: vec![],
+            ..Default::default()
+        };
+        app_ob.start(app_config, (), true).await.unwrap();
+        loopback(&fake, &app_ob).unwrap();
+        assert_eq!(rx.recv().await.unwrap().detail_type, "connect");
+
+        platform.join_group("g1", "alice", "alice");
+        let event: GroupMemberIncreaseEvent = rx.recv().await.unwrap().try_into().unwrap();
+        assert_eq!(event.detail_type.user_id, "alice");
+        platform.receive_group("g1", "alice", "hi".to_string());
+        let event: GroupMessageEvent = rx.recv().await.unwrap().try_into().unwrap();
+        assert_eq!(event.ty.alt_message, "hi");
+
+        let call = |action: &str, params: ValueMap| {
+            let mut params = params;
+            params.insert("self_id".to_string(), "bot".into());
+            let app_ob = app_ob.clone();
+            let action = Action {
+                action: action.to_string(),
+                params,
+            };
+            async move { app_ob.handle_action(action).await.unwrap() }
+        };
+        let resp = call(
+            "send_message",
+            value_map! {
+                "detail_type": "group",
+                "group_id": "g1",
+                "message": "hello".to_string().into_message()
+            },
+        )
+        .await;
+        let message_id = resp
+            .as_result_downcast::<crate::structs::SendMessageResp>()
+            .unwrap()
+            .message_id;
+        assert_eq!(
+            platform.state().messages[&message_id]
+                .message
+                .extract_plain_text(),
+            "hello"
+        );
+        let resp = call("get_group_member_list", value_map! { "group_id": "g1" }).await;
+        assert_eq!(resp.as_result().unwrap().downcast_list().unwrap().len(), 2);
+
+        call(
+            "delete_message",
+            value_map! { "message_id": message_id.clone() },
+        )
+        .await;
+        let event: GroupMessageDeleteEvent = rx.recv().await.unwrap().try_into().unwrap();
+        assert_eq!(event.detail_type.message_id, message_id);
+
+        call(
+            "set_group_name",
+            value_map! { "group_id": "g1", "group_name": "new" },
+        )
+        .await;
+        assert_eq!(platform.state().groups["g1"].group_name, "new");
+        call("leave_group", value_map! { "group_id": "g1" }).await;
+        let event: GroupMemberDecreaseEvent = rx.recv().await.unwrap().try_into().unwrap();
+        assert_eq!(event.detail_type.user_id, "bot");
+        let resp = call("get_group_info", value_map! { "group_id": "g1" }).await;
+        assert_eq!(resp.retcode, 10003);
+
+        let resp = call(
+            "upload_file",
+            value_map! {
+                "type": "data",
+                "name": "a.txt",
+                "data": crate::util::OneBotBytes(b"data".to_vec())
+            },
+        )
+        .await;
+        let file_id = resp
+            .as_result_downcast::<crate::structs::FileId>()
+            .unwrap()
+            .file_id;
+        let resp = call(
+            "get_file",
+            value_map! { "file_id": file_id, "type": "data" },
+        )
+        .await;
+        assert_eq!(
+            resp.data.as_map().unwrap()["data"].as_bytes(),
+            Some(&b"data"[..])
+        );
+        let upload_path = |path: &str| {
+            value_map! { "type": "path", "name": "b.txt", "path": path }
+        };
+        let resp = call("upload_file", upload_path("b.txt")).await;
+        assert_eq!(resp.retcode, 10004);
+        let resp = call(
+            "get_file",
+            value_map! { "file_id": file_id.clone(), "type": "path" },
+        )
+        .await;
+        assert_eq!(resp.retcode, 10004);
+
+        let dir = std::env::temp_dir().join(format!("walle-fake-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("b.txt"), b"path").unwrap();
+        platform.set_file_dir(&dir);
+        let outside = std::env::temp_dir().join(format!("walle-fake-{}.txt", std::process::id()));
+        std::fs::write(&outside, b"secret").unwrap();
+        for path in [
+            outside.to_str().unwrap(),
+            "../walle-fake.txt",
+            "sub/../../b.txt",
+        ] {
+            assert_ne!(call("upload_file", upload_path(path)).await.retcode, 0);
+        }
+        std::fs::remove_file(&outside).unwrap();
+        let resp = call("upload_file", upload_path("sub/../b.txt")).await;
+        let path_id = resp
+            .as_result_downcast::<crate::structs::FileId>()
+            .unwrap()
+            .file_id;
+        let resp = call(
+            "get_file",
+            value_map! { "file_id": path_id, "type": "path" },
+        )
+        .await;
+        let file = resp.as_result_downcast::<crate::structs::File>().unwrap();
+        assert_eq!(std::fs::read(file.path.unwrap()).unwrap(), b"path");
+        let resp = call("get_file", value_map! { "file_id": file_id, "type": "url" }).await;
+        let file = resp.as_result_downcast::<crate::structs::File>().unwrap();
+        let url = file.url.unwrap();
+        assert_eq!(
+            std::fs::read(url.strip_prefix("file://").unwrap()).unwrap(),
+            b"data"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(call("unknown", value_map! {}).await.retcode, 10002);
+
+        fake.shutdown().await.unwrap();
+        app_ob.shutdown().await.unwrap();
+    });
+}
+
+#[cfg(any(feature = "impl-obc", feature = "app-obc"))]
+#[test]
+fn start_failure() {
+    use crate::{OneBot, WalleResult};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    struct Failing;
+
+    #[async_trait::async_trait]
+    impl crate::EventHandler<Event, Action, Resp> for Failing {
+        type Config = ();
+        async fn start<AH, EH>(
+            &self,
+            _: &Arc<OneBot<AH, EH>>,
+            _: (),
+        ) -> WalleResult<Vec<tokio::task::JoinHandle<()>>>
+        where
+            AH: crate::ActionHandler<Event, Action, Resp> + Send + Sync + 'static,
+            EH: crate::EventHandler<Event, Action, Resp> + Send + Sync + 'static,
+        {
+            Err(WalleError::Other("boom".to_string()))
+        }
+        async fn call(&self, _: Event) -> WalleResult<()> {
+            Ok(())
+        }
+    }
+
+    rt().block_on(async {
+        let ob = Arc::new(OneBot::new(Echoer, Failing));
+        for _ in 0..2 {
+            assert!(matches!(
+                ob.start((), (), true).await,
+                Err(WalleError::Other(_))
+            ));
+            assert!(!ob.started());
+            tokio::time::timeout(Duration::from_secs(1), ob.wait())
+                .await
+                .unwrap();
+        }
+    });
+}
+
+#[cfg(any(feature = "impl-obc", feature = "app-obc"))]
+#[test]
+fn record_replay() {
+    #[cfg(feature = "impl-obc")]
+    use crate::util::{EchoS, RECV_ECHO};
+    use crate::{
+        record::{self, read_records, Divergence, Record, Replayer},
+        util::ContentType,
+        OneBot,
+    };
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    let event = |id: &str| Event {
+        id: id.to_string(),
+        implt: "walle".to_string(),
+        platform: "test".to_string(),
+        self_id: "bot".to_string(),
+        time: 0.0,
+        ty: "message".to_string(),
+        detail_type: "private".to_string(),
+        sub_type: String::default(),
+        extra: value_map! { "message_id": id },
+    };
+    let action = |name: &str| Action {
+        action: name.to_string(),
+        params: value_map! { "self_id": "bot" },
+    };
+
+    rt().block_on(async {
+        for content_type in [ContentType::Json, ContentType::MsgPack] {
+            let buf = Buf::default();
+            let (tx, _rx) = mpsc::unbounded_channel();
+            let ob = Arc::new(OneBot::new(
+                record::Recorder::new(Echoer, buf.clone(), content_type),
+                Recorder(tx),
+            ));
+            ob.start((), (), true).await.unwrap();
+            ob.handle_event(event("1")).await.unwrap();
+            let get_self_info = ob.handle_action(action("get_self_info"));
+            // as if received by a transport with echo "e1"
+            #[cfg(feature = "impl-obc")]
+            let get_self_info =
+                RECV_ECHO.scope(EchoS(Some(Value::Str("e1".to_string()))), get_self_info);
+            get_self_info.await.unwrap();
+            ob.handle_action(action("get_version")).await.unwrap();
+            ob.handle_event(event("2")).await.unwrap();
+            ob.shutdown().await.unwrap();
+
+            let data = buf.0.lock().unwrap().clone();
+            let records: Vec<Record<Event, Action, Resp>> =
+                read_records(&data[..], content_type).unwrap();
+            assert_eq!(records.len(), 6);
+            // only the impl side records received echos
+            let e1 = cfg!(feature = "impl-obc").then(|| Value::Str("e1".to_string()));
+            assert!(matches!(&records[1], Record::Action { seq: 1, echo, .. } if *echo == e1));
+            assert!(matches!(&records[2], Record::Resp { seq: 1, echo, .. } if *echo == e1));
+            assert!(matches!(
+                &records[4],
+                Record::Resp {
+                    seq: 2,
+                    echo: None,
+                    ..
+                }
+            ));
+
+            // replay with the same behaviour
+            let (tx, mut rx) = mpsc::unbounded_channel();
+            let ob = Arc::new(OneBot::new(Replayer::new(records.clone()), Recorder(tx)));
+            ob.start((), (), true).await.unwrap();
+            assert_eq!(rx.recv().await.unwrap().id, "1");
+            let resp = ob.handle_action(action("get_self_info")).await.unwrap();
+            assert_eq!(resp.data, value!({ "action": "get_self_info" }));
+            ob.handle_action(action("get_version")).await.unwrap();
+            assert_eq!(rx.recv().await.unwrap().id, "2");
+            assert!(ob.action_handler.wait().await.is_empty());
+            ob.shutdown().await.unwrap();
+
+            // replay with a divergent action
+            let (tx, mut rx) = mpsc::unbounded_channel();
+            let mut replayer = Replayer::new(records);
+            replayer.action_timeout = Duration::from_millis(50);
+            let ob = Arc::new(OneBot::new(replayer, Recorder(tx)));
+            ob.start((), (), true).await.unwrap();
+            assert_eq!(rx.recv().await.unwrap().id, "1");
+            let resp = ob.handle_action(action("get_status")).await.unwrap();
+            assert_eq!(resp.retcode, 10001);
+            // skipping get_self_info does not make get_version out of order
+            ob.handle_action(action("get_version")).await.unwrap();
+            assert_eq!(
+                ob.action_handler.wait().await,
+                vec![
+                    Divergence::Unexpected(action("get_status")),
+                    Divergence::Missing(action("get_self_info"))
+                ]
+            );
+            ob.shutdown().await.unwrap();
+        }
+    });
+}
+
+/// 实现端经由 loopback 收到的 Action 记录所在连接与 echo
+#[cfg(all(feature = "impl-obc", feature = "app-obc"))]
+#[test]
+fn record_loopback() {
+    use crate::{
+        config::{AppConfig, Heartbeat, ImplConfig},
+        obc::{loopback, AppOBC, ImplOBC},
+        record::{self, read_records, Record},
+        util::ContentType,
+        OneBot,
+    };
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+
+    rt().block_on(async {
+        let buf = Buf::default();
+        let impl_ob = Arc::new(OneBot::new(
+            record::Recorder::new(Echoer, buf.clone(), ContentType::Json),
+            ImplOBC::<Event>::new("walle".to_string(), "test".to_string()),
+        ));
+        let impl_config = ImplConfig {
+            http: vec![],
+            http_webhook: vec![],
+            websocket: vec![],
+            websocket_rev: vec![],
+            heartbeat: Heartbeat {
+                enabled: true,
+                interval: 1,
+            },
+        };
+        impl_ob.start((), impl_config, true).await.unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let app_ob = Arc::new(OneBot::new(AppOBC::new(), Recorder(tx)));
+        let app_config = AppConfig {
+            websocket_rev: vec![],
+            ..Default::default()
+        };
+        app_ob.start(app_config, (), true).await.unwrap();
+        loopback(&impl_ob, &app_ob).unwrap();
+        while rx.recv().await.unwrap().detail_type != "heartbeat" {}
+        let action = Action {
+            action: "get_self_info".to_string(),
+            params: value_map! { "self_id": "bot" },
+        };
+        app_ob.handle_action(action).await.unwrap();
+        impl_ob.shutdown().await.unwrap();
+        app_ob.shutdown().await.unwrap();
+
+        let data = buf.0.lock().unwrap().clone();
+        let records: Vec<Record<Event, Action, Resp>> =
+            read_records(&data[..], ContentType::Json).unwrap();
+        let (conn_id, echo) = records
+            .iter()
+            .find_map(|r| match r {
+                Record::Action { conn_id, echo, .. } => Some((*conn_id, echo.clone())),
+                _ => None,
+            })
+            .unwrap();
+        assert!(conn_id.is_some());
+        assert!(echo.is_some());
+        assert!(records.iter().any(
+            |r| matches!(r, Record::Resp { conn_id: c, echo: e, .. } if *c == conn_id && *e == echo)
+        ));
+    });
+}
+
+#[cfg(all(feature = "impl-obc", feature = "app-obc"))]
+#[test]
+fn relay() {
+    use crate::{
+        config::{AppConfig, Heartbeat, ImplConfig, RelayConfig},
+        obc::{loopback, mock_event, AppOBC, MockOneBot, Relay},
+        OneBot,
+    };
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+
+    rt().block_on(async {
+        let impl_config = ImplConfig {
+            http: vec![],
+            http_webhook: vec![],
+            websocket: vec![],
+            websocket_rev: vec![],
+            heartbeat: Heartbeat {
+                enabled: false,
+                interval: 0,
+            },
+        };
+        let mock = Arc::new(MockOneBot::mock("bot", "test"));
+        mock.action_handler
+            .stub("send_message", value_map! { "message_id": "1" });
+        mock.start((), impl_config.clone(), true).await.unwrap();
+
+        let relay = Relay::new("relay");
+        relay
+            .start(RelayConfig {
+                upstream: AppConfig::empty(),
+                downstream: impl_config,
+            })
+            .await
+            .unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let app_ob = Arc::new(OneBot::new(AppOBC::new(), Recorder(tx)));
+        app_ob.start(AppConfig::empty(), (), true).await.unwrap();
+        loopback(&relay.downstream, &app_ob).unwrap();
+        let connect = rx.recv().await.unwrap();
+        assert_eq!(
+            (connect.detail_type.as_str(), connect.implt.as_str()),
+            ("connect", "walle-relay")
+        );
+        loopback(&mock, &relay.upstream).unwrap();
+
+        mock.push_event(mock_event(
+            Message {
+                message_id: "0".to_string(),
+                message: "hello".to_string().into_message(),
+                alt_message: "hello".to_string(),
+                user_id: "user".to_string(),
+            },
+            Private {},
+            (),
+        ))
+        .await
+        .unwrap();
+        // upstream meta events are replaced by the relay's own status_update
+        let event = loop {
+            let event = rx.recv().await.unwrap();
+            if event.ty != "meta" {
+                break event;
+            }
+            assert_eq!(event.detail_type, "status_update");
+        };
+        assert_eq!(
+            (event.self_id.as_str(), event.platform.as_str()),
+            ("bot", "test")
+        );
+
+        let send = |self_id: &str| Action {
+            action: "send_message".to_string(),
+            params: value_map! { "self_id": self_id },
+        };
+        let resp = app_ob.handle_action(send("bot")).await.unwrap();
+        assert_eq!(resp.data, value!({ "message_id": "1" }));
+        assert_eq!(mock.action_handler.actions().len(), 1);
+        // app side only knows bots that have sent events, ask the relay directly
+        let resp = relay
+            .downstream
+            .handle_action(send("nobody"))
+            .await
+            .unwrap();
+        assert_eq!(resp.retcode, 10001);
+
+        mock.shutdown().await.unwrap();
+        relay.shutdown().await.unwrap();
+        app_ob.shutdown().await.unwrap();
+    });
+}
+
+#[cfg(all(feature = "impl-obc", feature = "app-obc", feature = "http"))]
+#[test]
+fn http_failover() {
+    use crate::{
+        config::{AppConfig, AppEndpoint, Heartbeat, HttpClient, ImplConfig},
+        obc::{loopback, mock_event, AppOBC, MockOneBot},
+        OneBot,
+    };
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+
+    rt().block_on(async {
+        let mock = Arc::new(MockOneBot::mock("bot", "test"));
+        mock.action_handler
+            .stub("get_self_info", value_map! { "user_id": "bot" });
+        let impl_config = ImplConfig {
+            http: vec![],
+            http_webhook: vec![],
+            websocket: vec![],
+            websocket_rev: vec![],
+            heartbeat: Heartbeat {
+                enabled: false,
+                interval: 0,
+            },
+        };
+        mock.start((), impl_config, true).await.unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let app_ob = Arc::new(OneBot::new(AppOBC::new(), Recorder(tx)));
+        app_ob.start(AppConfig::empty(), (), true).await.unwrap();
+        // nothing listens on port 1, the request is never delivered
+        let client = HttpClient {
+            url: "http://127.0.0.1:1".to_string(),
+            ..Default::default()
+        };
+        app_ob
+            .action_handler
+            .add_endpoint(
+                &app_ob,
+                AppEndpoint::Http {
+                    self_id: "bot".to_string(),
+                    client,
+                },
+            )
+            .await
+            .unwrap();
+        loopback(&mock, &app_ob).unwrap();
+        mock.push_event(mock_event(
+            Message {
+                message_id: "0".to_string(),
+                message: "hello".to_string().into_message(),
+                alt_message: "hello".to_string(),
+                user_id: "user".to_string(),
+            },
+            Private {},
+            (),
+        ))
+        .await
+        .unwrap();
+        while rx.recv().await.unwrap().ty != "message" {}
+
+        let resp = app_ob
+            .handle_action(Action {
+                action: "get_self_info".to_string(),
+                params: value_map! { "self_id": "bot" },
+            })
+            .await
+            .unwrap();
+        assert_eq!(resp.data, value!({ "user_id": "bot" }));
+
+        mock.shutdown().await.unwrap();
+        app_ob.shutdown().await.unwrap();
+    });
+}
+
+#[cfg(all(feature = "impl-obc", feature = "app-obc", feature = "http"))]
+#[test]
+fn webhook_quick_reply() {
+    use crate::{
+        config::{
+            AppConfig, AppEndpoint, Heartbeat, HttpClient, HttpServer, ImplConfig, ImplEndpoint,
+            Scope,
+        },
+        obc::{mock_event, AppOBC, MockOneBot},
+        OneBot,
+    };
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+
+    rt().block_on(async {
+        let mock = Arc::new(MockOneBot::mock("bot", "test"));
+        mock.action_handler
+            .stub("get_self_info", value_map! { "user_id": "bot" });
+        let impl_config = ImplConfig {
+            http: vec![],
+            http_webhook: vec![],
+            websocket: vec![],
+            websocket_rev: vec![],
+            heartbeat: Heartbeat {
+                enabled: false,
+                interval: 0,
+            },
+        };
+        mock.start((), impl_config, true).await.unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let app_ob = Arc::new(OneBot::new(AppOBC::new(), Recorder(tx)));
+        app_ob.start(AppConfig::empty(), (), true).await.unwrap();
+
+        // actions are sent after the event handler returns
+        let webhook = HttpServer {
+            port: 0,
+            quick_reply_window: 8,
+            quick_reply_results: true,
+            ..Default::default()
+        };
+        app_ob
+            .action_handler
+            .add_endpoint(&app_ob, AppEndpoint::HttpWebhook(webhook))
+            .await
+            .unwrap();
+        let client = HttpClient {
+            url: app_ob.action_handler.endpoints()[0].addr.clone(),
+            report_results: true,
+            scope: Scope {
+                actions: vec!["get_self_info".to_string()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        mock.event_handler
+            .add_endpoint(&mock, ImplEndpoint::HttpWebhook(client))
+            .await
+            .unwrap();
+        mock.push_event(mock_event(
+            Message {
+                message_id: "0".to_string(),
+                message: "hello".to_string().into_message(),
+                alt_message: "hello".to_string(),
+                user_id: "user".to_string(),
+            },
+            Private {},
+            (),
+        ))
+        .await
+        .unwrap();
+        while rx.recv().await.unwrap().ty != "message" {}
+
+        let send = |action: &str| Action {
+            action: action.to_string(),
+            params: value_map! { "self_id": "bot" },
+        };
+        let (denied, allowed) = tokio::join!(
+            app_ob.handle_action(send("send_message")),
+            app_ob.handle_action(send("get_self_info"))
+        );
+        assert_eq!(denied.unwrap().retcode, 10001);
+        assert_eq!(allowed.unwrap().data, value!({ "user_id": "bot" }));
+        assert_eq!(mock.action_handler.actions().len(), 1);
+
+        mock.shutdown().await.unwrap();
+        app_ob.shutdown().await.unwrap();
+    });
+}
+
+#[cfg(all(feature = "impl-obc", feature = "app-obc", feature = "websocket"))]
+#[test]
+fn endpoints() {
+    use crate::{
+        config::{
+            AppConfig, AppEndpoint, Heartbeat, ImplConfig, ImplEndpoint, WebSocketClient,
+            WebSocketServer,
+        },
+        obc::{mock_event, AppOBC, MockOneBot},
+        util::Transport,
+        OneBot,
+    };
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+
+    rt().block_on(async {
+        let mock = Arc::new(MockOneBot::mock("bot", "test"));
+        let impl_config = ImplConfig {
+            http: vec![],
+            http_webhook: vec![],
+            websocket: vec![],
+            websocket_rev: vec![],
+            heartbeat: Heartbeat {
+                enabled: false,
+                interval: 0,
+            },
+        };
+        mock.start((), impl_config, true).await.unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let app_ob = Arc::new(OneBot::new(AppOBC::new(), Recorder(tx)));
+        app_ob.start(AppConfig::empty(), (), true).await.unwrap();
+
+        let server = WebSocketServer {
+            port: 0,
+            ..Default::default()
+        };
+        let id = mock
+            .event_handler
+            .add_endpoint(&mock, ImplEndpoint::WebSocket(server))
+            .await
+            .unwrap();
+        let endpoints = mock.event_handler.endpoints();
+        assert_eq!(endpoints.len(), 1);
+        assert_eq!(endpoints[0].transport, Transport::WebSocket);
+        let client = WebSocketClient {
+            url: endpoints[0].addr.clone(),
+            ..Default::default()
+        };
+        app_ob
+            .action_handler
+            .add_endpoint(&app_ob, AppEndpoint::WebSocket(client))
+            .await
+            .unwrap();
+        let connect = rx.recv().await.unwrap();
+        assert_eq!(connect.detail_type, "connect");
+        mock.push_event(mock_event(
+            Message {
+                message_id: "0".to_string(),
+                message: "hello".to_string().into_message(),
+                alt_message: "hello".to_string(),
+                user_id: "user".to_string(),
+            },
+            Private {},
+            (),
+        ))
+        .await
+        .unwrap();
+        while rx.recv().await.unwrap().ty != "message" {}
+
+        let conns = mock.event_handler.connections(&mock).await;
+        assert_eq!(conns.len(), 1);
+        assert_eq!(
+            (conns[0].endpoint_id, conns[0].transport, &conns[0].self_ids),
+            (id, Transport::WebSocket, &vec!["bot".to_string()])
+        );
+        assert!(conns[0].peer.is_some());
+        let conns = app_ob.action_handler.connections();
+        assert_eq!(conns.len(), 1);
+        assert_eq!(conns[0].self_ids, vec!["bot".to_string()]);
+
+        #[cfg(feature = "http")]
+        {
+            use crate::config::HttpClient;
+
+            let client = HttpClient {
+                url: "http://127.0.0.1:1".to_string(),
+                ..Default::default()
+            };
+            let http_id = app_ob
+                .action_handler
+                .add_endpoint(
+                    &app_ob,
+                    AppEndpoint::Http {
+                        self_id: "other".to_string(),
+                        client,
+                    },
+                )
+                .await
+                .unwrap();
+            let conns = app_ob.action_handler.connections();
+            assert_eq!(conns.len(), 2);
+            let http = conns
+                .iter()
+                .find(|c| c.transport == Transport::Http)
+                .unwrap();
+            assert_eq!(
+                (http.endpoint_id, &http.self_ids, http.peer),
+                (http_id, &vec!["other".to_string()], None)
+            );
+            assert!(app_ob.action_handler.remove_endpoint(http_id));
+            while app_ob.action_handler.connections().len() != 1 {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        }
+
+        assert!(mock.event_handler.remove_endpoint(id));
+        assert!(!mock.event_handler.remove_endpoint(id));
+        while !mock.event_handler.connections(&mock).await.is_empty() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert!(mock.event_handler.endpoints().is_empty());
+        assert!(app_ob
+            .action_handler
+            .add_endpoint(
+                &app_ob,
+                AppEndpoint::WebSocket(WebSocketClient {
+                    url: "127.0.0.1".to_string(),
+                    ..Default::default()
+                })
+            )
+            .await
+            .is_err());
+
+        mock.shutdown().await.unwrap();
+        app_ob.shutdown().await.unwrap();
+        assert!(app_ob.action_handler.endpoints().is_empty());
+    });
+}
+
+#[cfg(all(feature = "impl-obc", feature = "websocket"))]
+#[test]
+fn slow_handshake() {
+    use crate::{
+        config::{Heartbeat, ImplConfig, ImplEndpoint, WebSocketServer},
+        obc::MockOneBot,
+    };
+    use std::{sync::Arc, time::Duration};
+
+    rt().block_on(async {
+        let mock = Arc::new(MockOneBot::mock("bot", "test"));
+        let impl_config = ImplConfig {
+            http: vec![],
+            http_webhook: vec![],
+            websocket: vec![],
+            websocket_rev: vec![],
+            heartbeat: Heartbeat {
+                enabled: false,
+                interval: 0,
+            },
+        };
+        mock.start((), impl_config, true).await.unwrap();
+        let server = WebSocketServer {
+            port: 0,
+            ..Default::default()
+        };
+        mock.event_handler
+            .add_endpoint(&mock, ImplEndpoint::WebSocket(server))
+            .await
+            .unwrap();
+        let url = mock.event_handler.endpoints()[0].addr.clone();
+        // a peer that never sends its handshake
+        let _idle = tokio::net::TcpStream::connect(url.trim_start_matches("ws://"))
+            .await
+            .unwrap();
+        let connect = tokio_tungstenite::connect_async(url);
+        let (ws, _) = tokio::time::timeout(Duration::from_secs(1), connect)
+            .await
+            .unwrap()
+            .unwrap();
+        drop(ws);
+        mock.shutdown().await.unwrap();
+    });
+}
+
+#[cfg(all(feature = "impl-obc", feature = "app-obc", feature = "websocket"))]
+#[test]
+fn graceful_shutdown() {
+    use crate::{
+        config::{AppConfig, Heartbeat, ImplConfig, WebSocketClient, WebSocketServer},
+        obc::{mock_event, AppOBC, ImplOBC},
+        util::SelfIds,
+        ActionHandler, EventHandler, GetStatus, OneBot, WalleResult,
+    };
+    use async_trait::async_trait;
+    use std::{sync::Arc, time::Duration};
+    use tokio::sync::mpsc;
+
+    struct Slow(mpsc::UnboundedSender<()>);
+
+    #[async_trait]
+    impl SelfIds for Slow {
+        async fn self_ids(&self) -> Vec<String> {
+            vec!["bot".to_string()]
+        }
+    }
+
+    impl GetStatus for Slow {
+        fn get_status(&self) -> Status {
+            Status {
+                good: true,
+                online: true,
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ActionHandler<Event, Action, Resp> for Slow {
+        type Config = ();
+        async fn start<AH, EH>(
+            &self,
+            _: &Arc<OneBot<AH, EH>>,
+            _: (),
+        ) -> WalleResult<Vec<tokio::task::JoinHandle<()>>>
+        where
+            AH: ActionHandler<Event, Action, Resp> + Send + Sync + 'static,
+            EH: EventHandler<Event, Action, Resp> + Send + Sync + 'static,
+        {
+            Ok(vec![])
+        }
+        async fn call(&self, action: Action) -> WalleResult<Resp> {
+            self.0.send(()).ok();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Ok(value_map! { "action": action.action }.into())
+        }
+    }
+
+    rt().block_on(async {
+        let (called_tx, mut called_rx) = mpsc::unbounded_channel();
+        let impl_ob = Arc::new(
+            OneBot::new(
+                Slow(called_tx),
+                ImplOBC::<Event>::new("walle".to_string(), "test".to_string()),
+            )
+            .with_drain_timeout(Duration::from_secs(2)),
+        );
+        let impl_config = ImplConfig {
+            http: vec![],
+            http_webhook: vec![],
+            websocket: vec![WebSocketServer {
+                port: 0,
+                ..Default::default()
+            }],
+            websocket_rev: vec![],
+            heartbeat: Heartbeat {
+                enabled: false,
+                interval: 0,
+            },
+        };
+        let tasks = impl_ob.start((), impl_config, true).await.unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let app_ob = Arc::new(OneBot::new(AppOBC::new(), Recorder(tx)));
+        let app_config = AppConfig {
+            websocket: vec![WebSocketClient {
+                url: impl_ob.event_handler.endpoints()[0].addr.clone(),
+                ..Default::default()
+            }],
+            ..AppConfig::empty()
+        };
+        app_ob.start(app_config, (), true).await.unwrap();
+        assert_eq!(rx.recv().await.unwrap().detail_type, "connect");
+        let mut event: Event = mock_event(
+            Message {
+                message_id: "0".to_string(),
+                message: "hello".to_string().into_message(),
+                alt_message: "hello".to_string(),
+                user_id: "user".to_string(),
+            },
+            Private {},
+            (),
+        )
+        .into();
+        event.self_id = "bot".to_string();
+        impl_ob.handle_event(event).await.unwrap();
+        while rx.recv().await.unwrap().ty != "message" {}
+
+        let ob = app_ob.clone();
+        let call = tokio::spawn(async move {
+            ob.handle_action(Action {
+                action: "get_self_info".to_string(),
+                params: value_map! { "self_id": "bot" },
+            })
+            .await
+        });
+        called_rx.recv().await.unwrap();
+        let ob = impl_ob.clone();
+        let wait = tokio::spawn(async move { ob.wait().await });
+        // the action in progress is answered before the connection closes
+        impl_ob.shutdown().await.unwrap();
+        let resp = call.await.unwrap().unwrap();
+        assert_eq!(resp.data, value!({ "action": "get_self_info" }));
+        wait.await.unwrap();
+        for task in tasks {
+            assert!(task.is_finished());
+        }
+        assert!(impl_ob.event_handler.connections(&impl_ob).await.is_empty());
+        impl_ob.wait().await;
+
+        app_ob.shutdown().await.unwrap();
+        assert!(app_ob.shutdown().await.is_err());
+    });
+}
+
+#[cfg(all(feature = "console", feature = "app-obc"))]
+#[test]
+fn console() {
+    use crate::{
+        config::{AppConfig, Heartbeat, ImplConfig},
+        obc::{loopback, AppOBC, ConsolePlatform, ImplOBC},
+        segment::{Mention, MentionAll},
+        OneBot,
+    };
+    use std::sync::Arc;
+    use tokio::io::{AsyncWriteExt, BufReader};
+    use tokio::sync::mpsc;
+
+    async fn next_message(rx: &mut mpsc::UnboundedReceiver<Event>) -> Event {
+        loop {
+            let event = rx.recv().await.unwrap();
+            if event.ty == "message" {
+                return event;
+            }
+        }
+    }
+
+    rt().block_on(async {
+        let (mut stdin, input) = tokio::io::duplex(1024);
+        let output = Buf::default();
+        let console = Arc::new(OneBot::new(
+            ConsolePlatform::with_io("bot", "console", BufReader::new(input), output.clone()),
+            ImplOBC::new("walle-console".to_string(), "console".to_string()),
+        ));
+        let impl_config = ImplConfig {
+            http: vec![],
+            http_webhook: vec![],
+            websocket: vec![],
+            websocket_rev: vec![],
+            heartbeat: Heartbeat {
+                enabled: false,
+                interval: 0,
+            },
+        };
+        console.start((), impl_config, true).await.unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let app_ob = Arc::new(OneBot::new(AppOBC::new(), Recorder(tx)));
+        app_ob.start(AppConfig::empty(), (), true).await.unwrap();
+        loopback(&console, &app_ob).unwrap();
+
+        stdin
+            .write_all(b"hello @alice\n/group g1\n/user bob\n@all hi\n")
+            .await
+            .unwrap();
+        let event: PrivateMessageEvent = next_message(&mut rx).await.try_into().unwrap();
+        assert_eq!(event.ty.user_id, "user");
+        assert_eq!(
+            event.ty.message,
+            vec![
+                "hello ".into(),
+                Mention {
+                    user_id: "alice".to_string()
+                }
+                .into()
+            ]
+        );
+        let event: GroupMessageEvent = next_message(&mut rx).await.try_into().unwrap();
+        assert_eq!(
+            (
+                event.ty.user_id.as_str(),
+                event.detail_type.group_id.as_str()
+            ),
+            ("bob", "g1")
+        );
+        assert_eq!(event.ty.message, vec![MentionAll {}.into(), " hi".into()]);
+
+        let resp = app_ob
+            .handle_action(Action {
+                action: "send_message".to_string(),
+                params: value_map! {
+                    "self_id": "bot",
+                    "detail_type": "group",
+                    "group_id": "g1",
+                    "message": [{ "type": "text", "data": { "text": "pong" } }]
+                },
+            })
+            .await
+            .unwrap();
+        assert_eq!(resp.retcode, 0);
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert!(output.ends_with("[group g1] bot: pong\n"), "{}", output);
+
+        console.shutdown().await.unwrap();
+        app_ob.shutdown().await.unwrap();
+    });
+}
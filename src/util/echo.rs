@@ -1,71 +1,45 @@
-use super::{timestamp_nano, ValueMap};
-use serde::{de::Visitor, Deserialize, Serialize};
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+
+use super::{timestamp_nano, Value};
+use crate::error::{WalleError, WalleResult};
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct Echo<I> {
     #[serde(flatten)]
     pub inner: I,
-    pub echo: Option<EchoInner>,
+    pub echo: Option<Value>,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash, Default)]
-pub struct EchoS(pub Option<EchoInner>);
+/// Echo 标识，可以是任意 Value
+///
+/// 用作 Map 键时，仅 Str Int Bool Bytes List 参与哈希，其余类型依靠相等比较区分
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct EchoS(pub Option<Value>);
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
-pub enum EchoInner {
-    S(String),
-    Map(String),
-}
+impl Eq for EchoS {}
 
-impl Serialize for EchoInner {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        match self {
-            EchoInner::S(s) => s.serialize(serializer),
-            EchoInner::Map(s) => {
-                let map: ValueMap = serde_json::from_str(s).unwrap();
-                map.serialize(serializer)
+impl Hash for EchoS {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        fn hash_value<H: Hasher>(value: &Value, state: &mut H) {
+            std::mem::discriminant(value).hash(state);
+            match value {
+                Value::Str(s) => s.hash(state),
+                Value::Int(i) => i.hash(state),
+                Value::Bool(b) => b.hash(state),
+                Value::Bytes(b) => b.0.hash(state),
+                Value::List(l) => l.iter().for_each(|v| hash_value(v, state)),
+                // f64 and map are not hashable, equal values still share discriminant
+                Value::F64(_) | Value::Map(_) | Value::Null => {}
             }
         }
-    }
-}
-
-struct EchoInnerVisitor;
-
-impl<'de> Visitor<'de> for EchoInnerVisitor {
-    type Value = EchoInner;
-
-    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-        formatter.write_str("string or map")
-    }
-
-    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
-    where
-        E: serde::de::Error,
-    {
-        Ok(EchoInner::S(v.to_owned()))
-    }
-
-    fn visit_map<M>(self, mut map: M) -> Result<Self::Value, M::Error>
-    where
-        M: serde::de::MapAccess<'de>,
-    {
-        let mut s = ValueMap::new();
-        while let Some(key) = map.next_key::<String>()? {
-            s.insert(key, map.next_value()?);
+        if let Some(value) = &self.0 {
+            hash_value(value, state);
         }
-        Ok(EchoInner::Map(serde_json::to_string(&s).unwrap()))
-    }
-}
-
-impl<'de> Deserialize<'de> for EchoInner {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        deserializer.deserialize_any(EchoInnerVisitor)
     }
 }
 
@@ -88,6 +62,128 @@ impl EchoS {
     }
 
     pub fn new(tag: &str) -> Self {
-        Self(Some(EchoInner::S(format!("{}-{}", tag, timestamp_nano()))))
+        Self(Some(Value::Str(format!("{}-{}", tag, timestamp_nano()))))
+    }
+}
+
+// task_local! 不保留条目上的属性，cfg 需加在宏调用上
+
+// 实现端正在处理的 Action 收到时携带的 echo 与所在连接，Http 请求没有连接
+#[cfg(feature = "impl-obc")]
+tokio::task_local! {
+    pub(crate) static RECV_ECHO: EchoS;
+    pub(crate) static RECV_CONN: u64;
+}
+
+// 应用端 Action 最近一次发出时使用的 echo
+#[cfg(feature = "app-obc")]
+tokio::task_local! {
+    pub(crate) static SENT_ECHO: std::cell::RefCell<Option<EchoS>>;
+}
+
+struct Pending<R> {
+    tx: oneshot::Sender<WalleResult<R>>,
+    conn: Option<u64>,
+    deadline: Instant,
+}
+
+/// 等待响应的 Echo 暂存 Map
+///
+/// 超时或所属连接断开时，Echo 会被移除并立即以错误唤醒等待方
+pub struct EchoMap<R> {
+    inner: DashMap<EchoS, Pending<R>>,
+}
+
+impl<R> Default for EchoMap<R> {
+    fn default() -> Self {
+        Self {
+            inner: DashMap::new(),
+        }
+    }
+}
+
+impl<R> EchoMap<R> {
+    /// 登记一个等待响应的 Echo，`conn` 为已知的发送连接
+    pub fn insert(
+        &self,
+        echo: EchoS,
+        conn: Option<u64>,
+        timeout: Duration,
+    ) -> oneshot::Receiver<WalleResult<R>> {
+        let (tx, rx) = oneshot::channel();
+        self.inner.insert(
+            echo,
+            Pending {
+                tx,
+                conn,
+                deadline: Instant::now() + timeout,
+            },
+        );
+        rx
+    }
+
+    /// 收到响应，唤醒等待方
+    pub fn resolve(&self, echo: &EchoS, resp: R) -> bool {
+        match self.inner.remove(echo) {
+            Some((_, pending)) => {
+                pending.tx.send(Ok(resp)).ok();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 移除 Echo 并以错误唤醒等待方
+    pub fn cancel(&self, echo: &EchoS, error: WalleError) -> bool {
+        match self.inner.remove(echo) {
+            Some((_, pending)) => {
+                pending.tx.send(Err(error)).ok();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 连接断开，取消该连接上所有等待中的 Echo
+    pub fn cancel_conn(&self, conn: u64) -> usize {
+        let echos: Vec<EchoS> = self
+            .inner
+            .iter()
+            .filter(|p| p.conn == Some(conn))
+            .map(|p| p.key().clone())
+            .collect();
+        echos
+            .iter()
+            .filter(|echo| self.cancel(echo, WalleError::Disconnected))
+            .count()
+    }
+
+    /// 指定连接上等待中的 Echo 数量
+    pub fn conn_pending(&self, conn: u64) -> usize {
+        self.inner.iter().filter(|p| p.conn == Some(conn)).count()
+    }
+
+    /// 清理所有已超时的 Echo
+    pub fn clear_expired(&self) -> usize {
+        let now = Instant::now();
+        let echos: Vec<EchoS> = self
+            .inner
+            .iter()
+            .filter(|p| p.deadline <= now)
+            .map(|p| p.key().clone())
+            .collect();
+        echos
+            .iter()
+            .filter(|echo| self.cancel(echo, WalleError::ResponseTimeout))
+            .count()
+    }
+
+    /// 等待中的 Echo 数量
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
     }
 }
@@ -4,10 +4,16 @@ use serde::{Deserialize, Serialize};
 
 mod bytes;
 mod echo;
+#[cfg(feature = "http")]
+mod sign;
+mod tasks;
 pub mod value;
 
 pub use bytes::*;
 pub use echo::*;
+#[cfg(feature = "http")]
+pub use sign::*;
+pub(crate) use tasks::Tasks;
 pub use value::*;
 
 pub fn timestamp_nano() -> u128 {
@@ -21,11 +27,31 @@ pub fn timestamp_nano_f64() -> f64 {
     timestamp_nano() as f64 / 1_000_000_000.0
 }
 
-#[cfg(feature = "impl-obc")]
+#[cfg(any(feature = "impl-obc", feature = "app-obc"))]
 pub fn new_uuid() -> String {
     uuid::Uuid::from_u128(timestamp_nano()).to_string()
 }
 
+/// 等待 SIGINT，unix 平台上同时等待 SIGTERM
+#[cfg(feature = "signal")]
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = term.recv() => {}
+            },
+            Err(_) => {
+                tokio::signal::ctrl_c().await.ok();
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.ok();
+}
+
 pub trait SelfId: Sized {
     fn self_id(&self) -> String;
 }
@@ -57,6 +83,15 @@ pub trait ProtocolItem:
     {
         rmp_serde::from_slice(v).map_err(|e| e.to_string())
     }
+    fn decode(v: &[u8], content_type: &ContentType) -> Result<Self, String>
+    where
+        Self: Sized,
+    {
+        match content_type {
+            ContentType::Json => serde_json::from_slice(v).map_err(|e| e.to_string()),
+            ContentType::MsgPack => Self::rmp_decode(v),
+        }
+    }
     #[cfg(feature = "http")]
     fn to_body(self, content_type: &ContentType) -> hyper::Body {
         match content_type {
@@ -83,7 +118,8 @@ impl<T> ProtocolItem for T where
 /// Onebot 协议支持的数据编码格式
 ///
 /// Json or MessagePack
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ContentType {
     Json,
     MsgPack,
@@ -109,14 +145,45 @@ impl std::fmt::Display for ContentType {
     }
 }
 
+/// OneBot 连接所使用的通讯方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Transport {
+    Http,
+    HttpWebhook,
+    WebSocket,
+    WebSocketRev,
+    /// 进程内直连
+    Loopback,
+}
+
+impl Transport {
+    pub fn is_websocket(&self) -> bool {
+        matches!(self, Self::WebSocket | Self::WebSocketRev)
+    }
+}
+
+impl std::fmt::Display for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Http => write!(f, "http"),
+            Self::HttpWebhook => write!(f, "http_webhook"),
+            Self::WebSocket => write!(f, "websocket"),
+            Self::WebSocketRev => write!(f, "websocket_rev"),
+            Self::Loopback => write!(f, "loopback"),
+        }
+    }
+}
+
 pub(crate) trait AuthReqHeaderExt {
     fn header_auth_token(self, token: &Option<String>) -> Self;
 }
 
 #[cfg(feature = "http")]
-use hyper::http::{header::AUTHORIZATION, request::Builder};
+use hyper::http::{header::AUTHORIZATION, request::Builder, HeaderMap, Uri};
 #[cfg(all(feature = "websocket", not(feature = "http")))]
-use tokio_tungstenite::tungstenite::http::{header::AUTHORIZATION, request::Builder};
+use tokio_tungstenite::tungstenite::http::{
+    header::AUTHORIZATION, request::Builder, HeaderMap, Uri,
+};
 
 #[cfg(any(feature = "websocket", feature = "http"))]
 impl AuthReqHeaderExt for Builder {
@@ -128,3 +195,49 @@ impl AuthReqHeaderExt for Builder {
         }
     }
 }
+
+/// 校验请求携带的 access_token，返回匹配 token 的权限范围，tokens 为空时不做校验
+///
+/// `Authorization: Bearer` 头与 `access_token` 查询参数任一匹配即通过
+#[cfg(any(feature = "websocket", feature = "http"))]
+pub(crate) fn verify_access_token(
+    tokens: &[crate::config::AccessToken],
+    headers: &HeaderMap,
+    uri: &Uri,
+) -> Result<crate::config::Scope, &'static str> {
+    if tokens.is_empty() {
+        return Ok(Default::default());
+    }
+    let header_token = headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(ToString::to_string);
+    let query_token = uri.query().and_then(|query| {
+        form_urlencoded::parse(query.as_bytes())
+            .find(|(k, _)| k == "access_token")
+            .map(|(_, v)| v.into_owned())
+    });
+    if header_token.is_none() && query_token.is_none() {
+        return Err("Missing access token");
+    }
+    [header_token, query_token]
+        .iter()
+        .flatten()
+        .find_map(|token| tokens.iter().find(|t| t.token() == token))
+        .map(|t| t.scope())
+        .ok_or("Access token is invalid")
+}
+
+/// 为 url 附加 `access_token` 查询参数
+#[cfg(feature = "websocket")]
+pub(crate) fn url_with_token(url: &str, token: &Option<String>) -> String {
+    match token {
+        Some(token) => {
+            let token: String = form_urlencoded::byte_serialize(token.as_bytes()).collect();
+            let sep = if url.contains('?') { '&' } else { '?' };
+            format!("{}{}access_token={}", url, sep, token)
+        }
+        None => url.to_string(),
+    }
+}
@@ -0,0 +1,69 @@
+use hyper::http::HeaderMap;
+use sha2::{Digest, Sha256};
+
+/// 签名请求头，值为 `sha256=<hex>`
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+/// 签名时间戳请求头，值为 unix 秒
+pub const TIMESTAMP_HEADER: &str = "X-Timestamp";
+
+const BLOCK_SIZE: usize = 64;
+
+/// HMAC-SHA256
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner = Sha256::new();
+    inner.update(block.map(|b| b ^ 0x36));
+    inner.update(data);
+    let mut outer = Sha256::new();
+    outer.update(block.map(|b| b ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+/// 计算推送签名，签名内容为 `<timestamp>.<body>`
+pub fn sign_body(secret: &str, timestamp: u64, body: &[u8]) -> String {
+    let mut data = format!("{}.", timestamp).into_bytes();
+    data.extend_from_slice(body);
+    format!(
+        "sha256={}",
+        hex::encode(hmac_sha256(secret.as_bytes(), &data))
+    )
+}
+
+/// 校验推送签名，时间戳与当前时间相差超过 window 秒时视为重放
+pub fn verify_signature(
+    secret: &str,
+    window: u64,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<(), &'static str> {
+    let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+    let (signature, timestamp) = match (header(SIGNATURE_HEADER), header(TIMESTAMP_HEADER)) {
+        (Some(s), Some(t)) => (s, t),
+        _ => return Err("Missing signature"),
+    };
+    let timestamp: u64 = timestamp
+        .parse()
+        .map_err(|_| "Signature timestamp is invalid")?;
+    let now = (super::timestamp_nano() / 1_000_000_000) as u64;
+    if now.abs_diff(timestamp) > window {
+        return Err("Signature expired");
+    }
+    if constant_time_eq(
+        sign_body(secret, timestamp, body).as_bytes(),
+        signature.as_bytes(),
+    ) {
+        Ok(())
+    } else {
+        Err("Signature is invalid")
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
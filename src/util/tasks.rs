@@ -0,0 +1,79 @@
+use std::{future::Future, sync::Arc};
+
+use tokio::{sync::watch, task::JoinHandle};
+
+/// 运行中的任务计数，用于等待 OneBot 的任务全部结束
+#[derive(Clone)]
+pub(crate) struct Tasks(Arc<watch::Sender<usize>>);
+
+impl Default for Tasks {
+    fn default() -> Self {
+        Self(Arc::new(watch::channel(0).0))
+    }
+}
+
+/// 持有期间计入运行中的任务
+pub(crate) struct TaskGuard(Tasks);
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        self.0 .0.send_modify(|count| *count -= 1);
+    }
+}
+
+impl Tasks {
+    pub(crate) fn guard(&self) -> TaskGuard {
+        self.0.send_modify(|count| *count += 1);
+        TaskGuard(self.clone())
+    }
+
+    pub(crate) fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let guard = self.guard();
+        tokio::spawn(async move {
+            let _guard = guard;
+            future.await
+        })
+    }
+
+    pub(crate) fn count(&self) -> usize {
+        *self.0.borrow()
+    }
+
+    /// 等待任务全部结束
+    pub(crate) async fn wait(&self) {
+        let mut rx = self.0.subscribe();
+        while *rx.borrow_and_update() != 0 {
+            if rx.changed().await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+#[test]
+fn tasks_test() {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    rt.block_on(async {
+        let tasks = Tasks::default();
+        tasks.wait().await;
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        tasks.spawn(async move {
+            rx.await.ok();
+        });
+        let guard = tasks.guard();
+        assert_eq!(tasks.count(), 2);
+        let wait = tokio::time::timeout(std::time::Duration::from_millis(20), tasks.wait());
+        assert!(wait.await.is_err());
+        tx.send(()).unwrap();
+        drop(guard);
+        tasks.wait().await;
+        assert_eq!(tasks.count(), 0);
+    });
+}